@@ -0,0 +1,70 @@
+use std::time::Instant;
+
+use closeyc::backends::ir::{IrArgument, IrFunction, IrInstruction, IrModule, IrSsa};
+use closeyc::backends::x86_64;
+
+const FUNCTION_COUNT: usize = 10_000;
+const ITERATIONS: usize = 10;
+
+// Creates a module where every function calls the function before it.
+fn generate_module() -> IrModule {
+    let mut funcs = Vec::with_capacity(FUNCTION_COUNT);
+
+    for i in 0..FUNCTION_COUNT {
+        let mut ssas = vec![IrSsa {
+            local: Some(0),
+            local_lifetime: 1,
+            local_register: 0,
+            instr: IrInstruction::Load,
+            args: vec![IrArgument::Argument(0)],
+        }];
+
+        if i > 0 {
+            ssas.push(IrSsa {
+                local: Some(1),
+                local_lifetime: 1,
+                local_register: 0,
+                instr: IrInstruction::Call(true),
+                args: vec![IrArgument::Function(i - 1), IrArgument::Local(0)],
+            });
+        }
+
+        ssas.push(IrSsa {
+            local: None,
+            local_lifetime: 0,
+            local_register: 0,
+            instr: IrInstruction::Ret,
+            args: vec![IrArgument::Local(if i > 0 { 1 } else { 0 })],
+        });
+
+        funcs.push(IrFunction {
+            name: format!("f{}", i),
+            argc: 1,
+            ssas,
+        });
+    }
+
+    IrModule { funcs }
+}
+
+fn main() {
+    let mut total = 0.0;
+    let mut bytes = 0;
+
+    for _ in 0..ITERATIONS {
+        let mut module = generate_module();
+        let start = Instant::now();
+        let code = x86_64::codegen::generate_code(&mut module);
+        total += start.elapsed().as_secs_f64();
+        bytes = code.len();
+    }
+
+    let average = total / ITERATIONS as f64;
+    println!(
+        "codegen of {} functions: {:.3} ms per module, {:.1} MiB/s ({} bytes)",
+        FUNCTION_COUNT,
+        average * 1000.0,
+        bytes as f64 / average / (1024.0 * 1024.0),
+        bytes
+    );
+}
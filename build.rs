@@ -1,7 +1,11 @@
 fn main() {
     println!("cargo:rerun-if-changed=src/backends/aarch64/m1_jit.c");
-    cc::Build::new()
-        .file("src/backends/aarch64/m1_jit.c")
-        .pic(true)
-        .compile("m1_jit");
+
+    // Only unix hosts need the write protection shim used by the jit
+    if std::env::var_os("CARGO_CFG_UNIX").is_some() {
+        cc::Build::new()
+            .file("src/backends/aarch64/m1_jit.c")
+            .pic(true)
+            .compile("m1_jit");
+    }
 }
@@ -2,6 +2,13 @@
 /// generate the IR and manipulate it.
 pub mod ir;
 
+/// Module for generating call graphs from the lower level IR.
+pub mod callgraph;
+
+/// Module for escape analysis of the lower level IR. This module finds closures that never outlive
+/// the function that creates them.
+pub mod escape;
+
 /// Module for aarch64 code generation.
 pub mod aarch64;
 
@@ -15,9 +22,10 @@ pub mod x86_64;
 pub mod wasm64;
 
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::ops::Range;
 
-use ir::IrFunction;
+use ir::{IrArgument, IrFunction, IrInstruction, IrModule, IrSsa};
 
 #[cfg(target_arch = "aarch64")]
 pub const DEFAULT_ARCH: &str = "aarch64";
@@ -33,24 +41,100 @@ pub const DEFAULT_OS: &str = "linux";
 #[cfg(target_os = "macos")]
 pub const DEFAULT_OS: &str = "macos";
 
-/// Represents generated code in some architecture.
+/// Represents generated code in some architecture. Functions are referred to by ids interned in
+/// a per module function table; the ids of the functions in an `IrModule` are their indices in
+/// the module.
 #[derive(Default)]
 pub struct GeneratedCode {
-    func_addrs: HashMap<String, Range<usize>>,
-    func_refs: HashMap<usize, String>,
+    func_names: Vec<String>,
+    func_ids: HashMap<String, usize>,
+    func_addrs: Vec<Range<usize>>,
+    func_refs: HashMap<usize, usize>,
+    far_refs: HashMap<usize, usize>,
     data: Vec<u8>,
 }
 
+/// An error encountered while relocating generated code.
+#[derive(Debug)]
+pub enum RelocationError {
+    /// The displacement from a call site to a function does not fit in a 32 bit relative address.
+    DisplacementOverflow {
+        func: String,
+        at: usize,
+        displacement: i64,
+    },
+
+    /// A call site refers to a function that is neither defined by the code nor already in memory.
+    UnresolvedSymbol { func: String, at: usize },
+}
+
+impl Display for RelocationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RelocationError::DisplacementOverflow {
+                func,
+                at,
+                displacement,
+            } => write!(
+                f,
+                "reference to `{}` at {:#x} is {:#x} bytes away, which does not fit in a 32 bit displacement",
+                func, at, displacement
+            ),
+            RelocationError::UnresolvedSymbol { func, at } => write!(
+                f,
+                "reference to `{}` at {:#x} cannot be resolved, since it is not defined or loaded",
+                func, at
+            ),
+        }
+    }
+}
+
 impl GeneratedCode {
     /// Creates a new empty generated code.
     pub fn new() -> GeneratedCode {
         GeneratedCode {
-            func_addrs: HashMap::new(),
+            func_names: Vec::new(),
+            func_ids: HashMap::new(),
+            func_addrs: Vec::new(),
             func_refs: HashMap::new(),
+            far_refs: HashMap::new(),
             data: Vec::new(),
         }
     }
 
+    /// Creates a new empty generated code with space for at least `capacity` bytes of code.
+    pub fn with_capacity(capacity: usize) -> GeneratedCode {
+        GeneratedCode {
+            data: Vec::with_capacity(capacity),
+            ..GeneratedCode::new()
+        }
+    }
+
+    // Creates a new empty generated code whose first `count` ids are reserved for the functions of
+    // a module without interning their names.
+    fn with_reserved_ids(count: usize) -> GeneratedCode {
+        GeneratedCode {
+            func_names: vec![String::new(); count],
+            func_addrs: vec![0..0; count],
+            ..GeneratedCode::new()
+        }
+    }
+
+    /// Reserves space for at least `additional` more bytes of code.
+    pub fn reserve(&mut self, additional: usize) {
+        self.data.reserve(additional);
+    }
+
+    /// Appends a slice of bytes to the code.
+    pub fn emit(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Appends a 32 bit little endian value to the code.
+    pub fn emit_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
     /// Gets the length of the x86 code.
     pub fn len(&self) -> usize {
         self.data.len()
@@ -71,6 +155,30 @@ impl GeneratedCode {
         &mut self.data
     }
 
+    /// Interns a function name, returning its id. Functions that are interned but never defined
+    /// have the range `0..0` and are treated as external functions.
+    pub fn intern(&mut self, func: &str) -> usize {
+        if let Some(id) = self.func_ids.get(func) {
+            return *id;
+        }
+
+        let id = self.func_names.len();
+        self.func_names.push(String::from(func));
+        self.func_ids.insert(String::from(func), id);
+        self.func_addrs.push(0..0);
+        id
+    }
+
+    /// Gets the id of a function if it was interned.
+    pub fn get_func_id(&self, func: &str) -> Option<usize> {
+        self.func_ids.get(func).copied()
+    }
+
+    /// Gets the name of an interned function.
+    pub fn get_func_name(&self, id: usize) -> &str {
+        &self.func_names[id]
+    }
+
     /// Returns executable code as a function.
     ///
     /// # Safety
@@ -81,33 +189,43 @@ impl GeneratedCode {
         func: &str,
         base: *const u8,
     ) -> Option<unsafe extern "C" fn() -> *const u8> {
-        if let Some(f) = self.func_addrs.get(func) {
+        if let Some(id) = self.func_ids.get(func) {
             use std::mem::transmute;
-            Some(transmute(base.add(f.start + 16)))
+            Some(transmute(base.add(self.func_addrs[*id].start + 16)))
         } else {
             None
         }
     }
 
-    /// Gets the mapping from function names to ranges in code.
-    pub fn get_funcs(&self) -> &HashMap<String, Range<usize>> {
-        &self.func_addrs
+    /// Gets an iterator over function names and their ranges in code.
+    pub fn get_funcs(&self) -> impl Iterator<Item = (&str, &Range<usize>)> {
+        self.func_names
+            .iter()
+            .map(|v| v.as_str())
+            .zip(self.func_addrs.iter())
     }
 
-    /// Gets the mutable mapping from function names to ranges in code.
-    pub fn get_mut_funcs(&mut self) -> &mut HashMap<String, Range<usize>> {
+    /// Gets the mutable mapping from function ids to ranges in code.
+    pub fn get_mut_funcs(&mut self) -> &mut Vec<Range<usize>> {
         &mut self.func_addrs
     }
 
-    /// Gets the mapping used to relocate a file.
-    pub fn get_relocation_table(&self) -> &HashMap<usize, String> {
+    /// Gets the mapping from code addresses to the ids of the functions referenced there, used to
+    /// relocate a file.
+    pub fn get_relocation_table(&self) -> &HashMap<usize, usize> {
         &self.func_refs
     }
 
     /// Gets the mutable mapping used to relocate a file.
-    pub fn get_mut_relocation_table(&mut self) -> &mut HashMap<usize, String> {
+    pub fn get_mut_relocation_table(&mut self) -> &mut HashMap<usize, usize> {
         &mut self.func_refs
     }
+
+    /// Gets the mapping from code addresses to the ids of functions referenced there by absolute
+    /// 64 bit addresses.
+    pub fn get_far_relocation_table(&self) -> &HashMap<usize, usize> {
+        &self.far_refs
+    }
 }
 
 /// Performs register allocation by linear scan on an IrFunction.
@@ -134,3 +252,180 @@ pub fn linear_scan(func: &mut IrFunction, register_count: usize) {
         }
     }
 }
+
+/// A construct in the lower level IR that a backend may not be able to generate code for yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Returning a value from a function.
+    Ret,
+
+    /// Loading a function or argument into a local.
+    Load,
+
+    /// Applying arguments to a function or closure struct to form a new closure struct.
+    Apply,
+
+    /// Calling a function by name with known arity.
+    DirectCall,
+
+    /// Calling a local or argument with known arity.
+    IndirectCall,
+
+    /// Calling a function with known arity, passing an argument of the caller on the stack.
+    StackArgument,
+
+    /// Calling a function, function pointer, or closure struct with unknown arity.
+    UnknownArityCall,
+
+    /// Changing the reference count of a closure struct.
+    RefCount,
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Feature::Ret => write!(f, "returning from a function"),
+            Feature::Load => write!(f, "loading a value"),
+            Feature::Apply => write!(f, "partial application"),
+            Feature::DirectCall => write!(f, "calls with known arity"),
+            Feature::IndirectCall => {
+                write!(f, "calls with known arity through a local or argument")
+            }
+            Feature::StackArgument => {
+                write!(f, "calls with known arity that pass arguments on the stack")
+            }
+            Feature::UnknownArityCall => write!(f, "calls with unknown arity"),
+            Feature::RefCount => write!(f, "reference counting"),
+        }
+    }
+}
+
+impl Feature {
+    /// Gets the features used by an instruction. Calls with known arity pass every argument after
+    /// the first `register_args` on the stack.
+    pub fn used_by(ssa: &IrSsa, register_args: usize) -> Vec<Feature> {
+        match ssa.instr {
+            IrInstruction::Ret => vec![Feature::Ret],
+            IrInstruction::Load => vec![Feature::Load],
+            IrInstruction::Apply => vec![Feature::Apply],
+            IrInstruction::Call(false) => vec![Feature::UnknownArityCall],
+
+            IrInstruction::Call(true) => {
+                let mut features = match ssa.args.first() {
+                    Some(IrArgument::Function(_)) => vec![Feature::DirectCall],
+                    _ => vec![Feature::IndirectCall],
+                };
+
+                if ssa
+                    .args
+                    .iter()
+                    .skip(register_args + 1)
+                    .any(|v| matches!(v, IrArgument::Argument(_)))
+                {
+                    features.push(Feature::StackArgument);
+                }
+                features
+            }
+
+            IrInstruction::RcInc | IrInstruction::RcFuncFree => vec![Feature::RefCount],
+        }
+    }
+
+    /// Returns true if the feature is generated as a call into the runtime, such as `rccopy` for
+    /// applications or `call_unknown_arity`. Freestanding code cannot use these features.
+    pub fn needs_runtime(&self) -> bool {
+        matches!(
+            self,
+            Feature::Apply | Feature::UnknownArityCall | Feature::RefCount
+        )
+    }
+
+    /// Returns true if the feature allocates on the heap. Applications allocate a closure struct
+    /// in the runtime.
+    pub fn allocates(&self) -> bool {
+        matches!(self, Feature::Apply)
+    }
+}
+
+// Finds every use of a feature matching the predicate in a module, along with the name of the
+// function using it. Each feature is reported once per function, in module order.
+fn find_features(
+    module: &IrModule,
+    register_args: usize,
+    predicate: impl Fn(Feature) -> bool,
+) -> Vec<(String, Feature)> {
+    let mut found = vec![];
+
+    for func in module.funcs.iter() {
+        let mut seen = vec![];
+        for ssa in func.ssas.iter() {
+            for feature in Feature::used_by(ssa, register_args) {
+                if predicate(feature) && !seen.contains(&feature) {
+                    seen.push(feature);
+                    found.push((func.name.clone(), feature));
+                }
+            }
+        }
+    }
+
+    found
+}
+
+/// Finds every use of a feature in a module that needs the runtime. Each feature is reported once
+/// per function, in module order.
+pub fn find_runtime_uses(module: &IrModule) -> Vec<(String, Feature)> {
+    find_features(module, 0, |v| v.needs_runtime())
+}
+
+/// The constructs a backend can generate code for. Every backend declares its capabilities, so
+/// that unsupported code is reported before code generation rather than panicking during it.
+pub struct Capabilities {
+    /// The architecture the backend generates code for.
+    pub arch: &'static str,
+
+    /// The number of arguments of a call with known arity that are passed in registers.
+    pub register_args: usize,
+
+    /// The features the backend can generate code for.
+    pub features: &'static [Feature],
+}
+
+/// A use of a construct that a backend cannot generate code for.
+#[derive(Debug)]
+pub struct Unsupported {
+    /// The architecture of the backend.
+    pub arch: &'static str,
+
+    /// The name of the function that uses the construct.
+    pub func: String,
+
+    /// The construct that is not supported.
+    pub feature: Feature,
+}
+
+impl Capabilities {
+    /// Finds every use of a construct in a module that the backend cannot generate code for. Each
+    /// construct is reported once per function, in module order.
+    pub fn find_unsupported(&self, module: &IrModule) -> Vec<Unsupported> {
+        find_features(module, self.register_args, |v| !self.features.contains(&v))
+            .into_iter()
+            .map(|(func, feature)| Unsupported {
+                arch: self.arch,
+                func,
+                feature,
+            })
+            .collect()
+    }
+}
+
+/// Gets the capabilities of the backend for an architecture, or None if there is no backend for
+/// it.
+pub fn capabilities(arch: &str) -> Option<&'static Capabilities> {
+    match arch {
+        "aarch64" => Some(&aarch64::CAPABILITIES),
+        "riscv64" => Some(&riscv64::CAPABILITIES),
+        "wasm64" => Some(&wasm64::CAPABILITIES),
+        "x86_64" => Some(&x86_64::CAPABILITIES),
+        _ => None,
+    }
+}
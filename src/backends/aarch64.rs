@@ -1 +1,11 @@
 pub mod codegen;
+
+use super::Capabilities;
+
+/// The constructs the aarch64 backend can generate code for. The backend only emits a placeholder
+/// `main` so far, so it supports none of them.
+pub const CAPABILITIES: Capabilities = Capabilities {
+    arch: "aarch64",
+    register_args: 8,
+    features: &[],
+};
@@ -8,6 +8,7 @@ pub fn generate_code(_: &mut IrModule) -> GeneratedCode {
         0xff, 0x43, 0x00, 0xd1, 0xff, 0x0f, 0x00, 0xb9, 0xa0, 0x08, 0x80, 0x52, 0xff, 0x43, 0x00,
         0x91, 0xc0, 0x03, 0x5f, 0xd6,
     ];
-    code.func_addrs.insert(String::from("main"), 0..code.len());
+    let main = code.intern("main");
+    code.func_addrs[main] = 0..code.len();
     code
 }
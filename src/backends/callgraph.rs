@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::ir::{IrArgument, IrInstruction, IrModule};
+
+/// The name of the node used for callees that cannot be determined at compile time.
+const UNKNOWN_CALLEE: &str = "?";
+
+/// An edge in the call graph.
+struct CallEdge<'a> {
+    from: &'a str,
+    to: &'a str,
+    instr: IrInstruction,
+}
+
+/// Collects all edges in the call graph of a module.
+fn collect_edges(module: &IrModule) -> Vec<CallEdge> {
+    let mut edges = vec![];
+
+    for func in module.funcs.iter() {
+        // Locals that are known to hold (partially applied) functions
+        let mut local_funcs: HashMap<usize, &str> = HashMap::new();
+
+        for ssa in func.ssas.iter() {
+            let callee = match ssa.args.first() {
+                Some(IrArgument::Function(f)) => Some(module.funcs[*f].name.as_str()),
+                Some(IrArgument::Local(l)) => local_funcs.get(l).copied(),
+                _ => None,
+            };
+
+            match ssa.instr {
+                IrInstruction::Load => {
+                    if let (Some(local), Some(f)) = (ssa.local, callee) {
+                        local_funcs.insert(local, f);
+                    }
+                }
+
+                IrInstruction::Apply => {
+                    if let (Some(local), Some(f)) = (ssa.local, callee) {
+                        local_funcs.insert(local, f);
+                    }
+
+                    edges.push(CallEdge {
+                        from: &func.name,
+                        to: callee.unwrap_or(UNKNOWN_CALLEE),
+                        instr: ssa.instr,
+                    });
+                }
+
+                IrInstruction::Call(_) => {
+                    edges.push(CallEdge {
+                        from: &func.name,
+                        to: callee.unwrap_or(UNKNOWN_CALLEE),
+                        instr: ssa.instr,
+                    });
+                }
+
+                IrInstruction::Ret | IrInstruction::RcInc | IrInstruction::RcFuncFree => (),
+            }
+        }
+    }
+
+    edges
+}
+
+/// Generates a call graph of the module in the graphviz dot format. Each function is labelled
+/// with its arity, and each edge is labelled with whether the call is a known arity call (`call`),
+/// an unknown arity call (`call?`), or a partial application (`apply`). Calls whose callee cannot
+/// be determined at compile time point to the `?` node.
+pub fn generate_callgraph(module: &IrModule) -> String {
+    let edges = collect_edges(module);
+    let mut dot = String::from("digraph callgraph {\n");
+
+    for func in module.funcs.iter() {
+        writeln!(
+            dot,
+            "    \"{}\" [label=\"{}\\narity {}\"];",
+            func.name, func.name, func.argc
+        )
+        .unwrap();
+    }
+
+    if edges.iter().any(|v| v.to == UNKNOWN_CALLEE) {
+        writeln!(
+            dot,
+            "    \"{}\" [label=\"unknown\", shape=diamond];",
+            UNKNOWN_CALLEE
+        )
+        .unwrap();
+    }
+
+    for edge in edges {
+        let style = match edge.instr {
+            IrInstruction::Call(false) => ", style=dashed",
+            IrInstruction::Apply => ", style=dotted",
+            _ => "",
+        };
+
+        writeln!(
+            dot,
+            "    \"{}\" -> \"{}\" [label=\"{}\"{}];",
+            edge.from, edge.to, edge.instr, style
+        )
+        .unwrap();
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+/// Statistics about how the call sites of a module are compiled.
+#[derive(Default)]
+pub struct CallStatistics {
+    /// The number of call sites compiled as direct calls.
+    pub direct: usize,
+
+    /// The number of call sites compiled as calls with unknown arity.
+    pub dynamic: usize,
+
+    /// The number of partial applications.
+    pub applies: usize,
+
+    /// Notes about call sites that could be devirtualised.
+    pub hints: Vec<String>,
+}
+
+/// Collects statistics about direct and dynamic call sites in a module, along with hints for how
+/// dynamic call sites could be turned into direct calls.
+pub fn collect_call_statistics(module: &IrModule) -> CallStatistics {
+    let mut stats = CallStatistics::default();
+
+    for func in module.funcs.iter() {
+        for ssa in func.ssas.iter() {
+            match ssa.instr {
+                IrInstruction::Call(true) => stats.direct += 1,
+
+                IrInstruction::Call(false) => {
+                    stats.dynamic += 1;
+
+                    let hint = match ssa.args.first() {
+                        Some(IrArgument::Argument(a)) => format!(
+                            "`{}` calls argument {} with unknown arity; annotating it with a function type would allow a direct call",
+                            func.name, a
+                        ),
+
+                        Some(IrArgument::Local(l)) => format!(
+                            "`{}` calls the result of %{} with unknown arity; annotating the function that produces it would allow a direct call",
+                            func.name, l
+                        ),
+
+                        Some(IrArgument::Function(f)) => format!(
+                            "`{}` calls `{}` with unknown arity; annotating the return type of `{}` would allow a direct call",
+                            func.name, module.funcs[*f].name, module.funcs[*f].name
+                        ),
+
+                        None => continue,
+                    };
+                    stats.hints.push(hint);
+                }
+
+                IrInstruction::Apply => stats.applies += 1,
+
+                IrInstruction::Ret
+                | IrInstruction::Load
+                | IrInstruction::RcInc
+                | IrInstruction::RcFuncFree => (),
+            }
+        }
+    }
+
+    stats
+}
+
+/// The worst case stack usage of a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackUsage {
+    /// The function uses at most this many bytes of stack, including the functions it calls.
+    Fixed(usize),
+
+    /// The function is recursive, or calls a function that is, so its stack usage is unbounded.
+    Recursive,
+
+    /// The function calls a function that cannot be determined at compile time.
+    Dynamic,
+}
+
+/// Computes the worst case stack usage of every function in a module, in module order, given the
+/// size of the stack frame of each function. The stack usage of a function is the size of its own
+/// frame plus the largest stack usage of the functions it calls directly.
+pub fn stack_usage(module: &IrModule, frames: &[usize]) -> Vec<StackUsage> {
+    let mut usage = vec![None; module.funcs.len()];
+    let mut visiting = vec![false; module.funcs.len()];
+    for i in 0..module.funcs.len() {
+        visit_stack_usage(module, frames, i, &mut usage, &mut visiting);
+    }
+
+    usage.into_iter().map(Option::unwrap).collect()
+}
+
+/// Computes the worst case stack usage of a function, recording it and the stack usage of every
+/// function it calls. Recursion takes precedence over calls to unknown functions.
+fn visit_stack_usage(
+    module: &IrModule,
+    frames: &[usize],
+    func: usize,
+    usage: &mut [Option<StackUsage>],
+    visiting: &mut [bool],
+) -> StackUsage {
+    if let Some(v) = usage[func] {
+        return v;
+    } else if visiting[func] {
+        return StackUsage::Recursive;
+    }
+
+    visiting[func] = true;
+    let mut result = StackUsage::Fixed(0);
+    for ssa in module.funcs[func].ssas.iter() {
+        let callee = match (ssa.instr, ssa.args.first()) {
+            (IrInstruction::Call(true), Some(IrArgument::Function(f))) => {
+                visit_stack_usage(module, frames, *f, usage, visiting)
+            }
+            (IrInstruction::Call(_), _) => StackUsage::Dynamic,
+            _ => continue,
+        };
+
+        result = match (result, callee) {
+            (StackUsage::Fixed(a), StackUsage::Fixed(b)) => StackUsage::Fixed(a.max(b)),
+            (StackUsage::Recursive, _) | (_, StackUsage::Recursive) => StackUsage::Recursive,
+            _ => StackUsage::Dynamic,
+        };
+    }
+    visiting[func] = false;
+
+    if let StackUsage::Fixed(v) = result {
+        result = StackUsage::Fixed(v + frames[func]);
+    }
+    usage[func] = Some(result);
+    result
+}
@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+
+use super::ir::{IrArgument, IrFunction, IrInstruction, IrModule};
+
+/// Finds the locals holding closures created by partial applications in a function that never
+/// escape it. A closure escapes if it is returned, copied into another local, passed as an
+/// argument, applied to more arguments (which may reuse its closure struct), or called with
+/// unknown arity (which may return it as a partial application). Closures that do not escape are
+/// only called with known arity or have their reference count changed, so they do not need to
+/// outlive the stack frame of the function.
+pub fn non_escaping_closures(func: &IrFunction) -> HashSet<usize> {
+    let mut closures = HashSet::new();
+    let mut escaped = HashSet::new();
+
+    for ssa in func.ssas.iter() {
+        let locals = ssa.args.iter().enumerate().filter_map(|(i, v)| match v {
+            IrArgument::Local(l) => Some((i, *l)),
+            _ => None,
+        });
+
+        match ssa.instr {
+            IrInstruction::Apply => {
+                escaped.extend(locals.map(|v| v.1));
+                if let Some(local) = ssa.local {
+                    closures.insert(local);
+                }
+            }
+
+            // The callee of a call with known arity is only read
+            IrInstruction::Call(true) => escaped.extend(locals.filter(|v| v.0 != 0).map(|v| v.1)),
+
+            IrInstruction::Call(false) | IrInstruction::Ret | IrInstruction::Load => {
+                escaped.extend(locals.map(|v| v.1))
+            }
+
+            IrInstruction::RcInc | IrInstruction::RcFuncFree => (),
+        }
+    }
+
+    closures.difference(&escaped).copied().collect()
+}
+
+/// Statistics about which closures escape the functions that create them.
+#[derive(Default)]
+pub struct EscapeStatistics {
+    /// The number of closures created by partial applications.
+    pub closures: usize,
+
+    /// The number of those closures that never escape the function that creates them.
+    pub non_escaping: usize,
+
+    /// Notes naming the closures that never escape.
+    pub hints: Vec<String>,
+}
+
+/// Collects statistics about which closures in a module escape the functions that create them.
+pub fn collect_escape_statistics(module: &IrModule) -> EscapeStatistics {
+    let mut stats = EscapeStatistics::default();
+
+    for func in module.funcs.iter() {
+        stats.closures += func
+            .ssas
+            .iter()
+            .filter(|v| matches!(v.instr, IrInstruction::Apply) && v.local.is_some())
+            .count();
+
+        let mut non_escaping: Vec<_> = non_escaping_closures(func).into_iter().collect();
+        non_escaping.sort_unstable();
+        stats.non_escaping += non_escaping.len();
+
+        for local in non_escaping {
+            stats.hints.push(format!(
+                "the closure in %{} of `{}` never escapes it and could be allocated on the stack",
+                local, func.name
+            ));
+        }
+    }
+
+    stats
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt::Display;
 
 use super::super::frontend::ir::{self, ArityInfo, SExpr, SExprMetadata};
@@ -54,8 +54,8 @@ pub enum IrArgument {
     /// considered arguments.
     Argument(usize),
 
-    /// A function address.
-    Function(String),
+    /// A function address, given as the index of the function in its module.
+    Function(usize),
 }
 
 impl Display for IrArgument {
@@ -87,6 +87,28 @@ pub struct IrSsa {
     pub args: Vec<IrArgument>,
 }
 
+impl IrSsa {
+    fn fmt_with_names(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        funcs: &[IrFunction],
+    ) -> std::fmt::Result {
+        if let Some(l) = self.local {
+            write!(f, "%{} = ", l)?;
+        }
+
+        write!(f, "{}", self.instr)?;
+        for a in self.args.iter() {
+            if let IrArgument::Function(id) = a {
+                write!(f, " @{}", funcs[*id].name)?;
+            } else {
+                write!(f, " {}", a)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Display for IrSsa {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(l) = self.local {
@@ -154,28 +176,34 @@ pub struct IrModule {
 impl Display for IrModule {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for func in self.funcs.iter() {
-            write!(f, "{}\n\n", func)?;
+            write!(f, "{}({}):", func.name, func.argc)?;
+            for ssa in func.ssas.iter() {
+                write!(f, "\n    ")?;
+                ssa.fmt_with_names(f, &self.funcs)?;
+            }
+            write!(f, "\n\n")?;
         }
         Ok(())
     }
 }
 
 fn get_arg_if_applicable<'a>(
-    args_map: &HashMap<String, usize>,
+    args_map: &HashMap<String, IrArgument>,
     sexpr: &'a SExpr,
     map: &HashMap<String, Vec<String>>,
+    ids: &HashMap<String, usize>,
 ) -> Result<IrArgument, &'a SExpr> {
     match sexpr {
         SExpr::Symbol(_, s) => {
             if let Some(a) = args_map.get(s) {
-                Ok(IrArgument::Argument(*a))
+                Ok(a.clone())
             } else {
                 todo!("symbols that aren't arguments");
             }
         }
 
         SExpr::Function(_, f) if map.get(f).unwrap().is_empty() => {
-            Ok(IrArgument::Function(f.clone()))
+            Ok(IrArgument::Function(*ids.get(f).unwrap()))
         }
 
         _ => Err(sexpr),
@@ -183,12 +211,13 @@ fn get_arg_if_applicable<'a>(
 }
 
 fn conversion_helper(
-    args_map: &HashMap<String, usize>,
+    args_map: &HashMap<String, IrArgument>,
     func: &mut IrFunction,
     sexpr: &SExpr,
     map: &HashMap<String, Vec<String>>,
+    ids: &HashMap<String, usize>,
 ) -> Option<usize> {
-    match get_arg_if_applicable(args_map, sexpr, map) {
+    match get_arg_if_applicable(args_map, sexpr, map, ids) {
         Ok(v) => {
             let local = Some(func.get_next_local());
             func.ssas.push(IrSsa {
@@ -205,7 +234,15 @@ fn conversion_helper(
         Err(SExpr::TypeAlias(_, _)) => todo!(),
 
         Err(SExpr::ExternalFunc(_, _, _)) => todo!(),
-        Err(SExpr::Chain(_, _, _)) => todo!(),
+        Err(SExpr::Chain(_, a, b)) => {
+            // The first value is only evaluated for its effects, so arguments and functions
+            // without captures need no instructions
+            if let Err(e) = get_arg_if_applicable(args_map, a, map, ids) {
+                conversion_helper(args_map, func, e, map, ids);
+            }
+            conversion_helper(args_map, func, b, map, ids)
+        }
+        Err(SExpr::As(_, v)) => conversion_helper(args_map, func, v, map, ids),
 
         Err(SExpr::Function(_, f)) => {
             use std::iter::once;
@@ -215,6 +252,7 @@ fn conversion_helper(
                     args_map,
                     &SExpr::Symbol(SExprMetadata::empty(), v.clone()),
                     map,
+                    ids,
                 )
                 .unwrap()
             });
@@ -223,22 +261,28 @@ fn conversion_helper(
                 local_lifetime: 0,
                 local_register: 0,
                 instr: IrInstruction::Apply,
-                args: once(IrArgument::Function(f.clone())).chain(args).collect(),
+                args: once(IrArgument::Function(*ids.get(f).unwrap()))
+                    .chain(args)
+                    .collect(),
             });
             local
         }
 
         Err(SExpr::Application(m, f, a)) => {
-            let f = match get_arg_if_applicable(args_map, &**f, map) {
+            let f = match get_arg_if_applicable(args_map, &**f, map, ids) {
                 Ok(v) => v,
-                Err(e) => IrArgument::Local(conversion_helper(args_map, func, e, map).unwrap()),
+                Err(e) => {
+                    IrArgument::Local(conversion_helper(args_map, func, e, map, ids).unwrap())
+                }
             };
 
             let args: Vec<_> = a
                 .iter()
-                .map(|a| match get_arg_if_applicable(args_map, a, map) {
+                .map(|a| match get_arg_if_applicable(args_map, a, map, ids) {
                     Ok(v) => v,
-                    Err(e) => IrArgument::Local(conversion_helper(args_map, func, e, map).unwrap()),
+                    Err(e) => {
+                        IrArgument::Local(conversion_helper(args_map, func, e, map, ids).unwrap())
+                    }
                 })
                 .collect();
 
@@ -265,14 +309,247 @@ fn conversion_helper(
             local
         }
 
+        Err(SExpr::Tuple(_, values)) => {
+            // A tuple is its constructor applied to every element, which waits for a selector
+            let args: Vec<_> = values
+                .iter()
+                .map(|a| match get_arg_if_applicable(args_map, a, map, ids) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        IrArgument::Local(conversion_helper(args_map, func, e, map, ids).unwrap())
+                    }
+                })
+                .collect();
+
+            use std::iter::once;
+            let local = Some(func.get_next_local());
+            let constructor = *ids.get(&tuple_constructor_name(values.len())).unwrap();
+            func.ssas.push(IrSsa {
+                local,
+                local_lifetime: 0,
+                local_register: 0,
+                instr: IrInstruction::Apply,
+                args: once(IrArgument::Function(constructor))
+                    .chain(args.into_iter())
+                    .collect(),
+            });
+            local
+        }
+
+        Err(SExpr::Record(_, fields)) => {
+            // A record is a tuple of its fields sorted by name
+            let mut args: Vec<_> = fields
+                .iter()
+                .map(
+                    |(name, a)| match get_arg_if_applicable(args_map, a, map, ids) {
+                        Ok(v) => (name, v),
+                        Err(e) => (
+                            name,
+                            IrArgument::Local(
+                                conversion_helper(args_map, func, e, map, ids).unwrap(),
+                            ),
+                        ),
+                    },
+                )
+                .collect();
+            args.sort_by(|a, b| a.0.cmp(b.0));
+            let values: Vec<_> = args.into_iter().map(|v| v.1).collect();
+
+            use std::iter::once;
+            let local = Some(func.get_next_local());
+            let constructor = *ids.get(&tuple_constructor_name(values.len())).unwrap();
+            func.ssas.push(IrSsa {
+                local,
+                local_lifetime: 0,
+                local_register: 0,
+                instr: IrInstruction::Apply,
+                args: once(IrArgument::Function(constructor))
+                    .chain(values.into_iter())
+                    .collect(),
+            });
+            local
+        }
+
+        Err(SExpr::Field(_, _, _)) => unreachable!("field accesses are replaced while checking"),
+
+        Err(SExpr::Index(_, v, i, len)) => {
+            // Elements are taken out by passing the tuple the selector for the element
+            let v = match get_arg_if_applicable(args_map, v, map, ids) {
+                Ok(v) => v,
+                Err(e) => {
+                    IrArgument::Local(conversion_helper(args_map, func, e, map, ids).unwrap())
+                }
+            };
+
+            let local = Some(func.get_next_local());
+            let selector = *ids.get(&tuple_selector_name(*len, *i)).unwrap();
+            func.ssas.push(IrSsa {
+                local,
+                local_lifetime: 0,
+                local_register: 0,
+                instr: IrInstruction::Call(false),
+                args: vec![v, IrArgument::Function(selector)],
+            });
+            local
+        }
+
         Err(SExpr::Assign(_, _, _)) => todo!(),
-        Err(SExpr::With(_, _, _)) => todo!(),
+
+        Err(SExpr::With(_, assigns, body)) => {
+            // Let bindings are only visible in the body, where they refer to the local holding
+            // their value
+            let mut args_map = args_map.clone();
+            for assign in assigns {
+                if let SExpr::Assign(_, name, value) = assign {
+                    let value = match get_arg_if_applicable(&args_map, value, map, ids) {
+                        Ok(v) => v,
+                        Err(e) => IrArgument::Local(
+                            conversion_helper(&args_map, func, e, map, ids).unwrap(),
+                        ),
+                    };
+                    args_map.insert(name.clone(), value);
+                }
+            }
+
+            conversion_helper(&args_map, func, body, map, ids)
+        }
+
         Err(SExpr::Match(_, _, _)) => todo!(),
 
         Err(SExpr::Symbol(_, _)) => unreachable!(),
     }
 }
 
+// The name of the function that builds tuples with the given number of elements. Names written in
+// source code cannot contain commas, so it cannot clash with a user definition.
+fn tuple_constructor_name(len: usize) -> String {
+    format!("({})", ",".repeat(len - 1))
+}
+
+// The name of the function that selects an element from the elements of a tuple.
+fn tuple_selector_name(len: usize, index: usize) -> String {
+    format!("{}.{}", tuple_constructor_name(len), index)
+}
+
+// Finds the sizes of the tuples built or taken apart in an expression.
+fn tuple_sizes(sexpr: &SExpr, sizes: &mut BTreeSet<usize>) {
+    match sexpr {
+        SExpr::Tuple(_, values) => {
+            sizes.insert(values.len());
+            for value in values {
+                tuple_sizes(value, sizes);
+            }
+        }
+
+        SExpr::Index(_, v, _, len) => {
+            sizes.insert(*len);
+            tuple_sizes(v, sizes);
+        }
+
+        SExpr::Record(_, fields) => {
+            sizes.insert(fields.len());
+            for (_, value) in fields {
+                tuple_sizes(value, sizes);
+            }
+        }
+
+        SExpr::ExternalFunc(_, _, args) => {
+            for arg in args {
+                tuple_sizes(arg, sizes);
+            }
+        }
+
+        SExpr::Chain(_, a, b) => {
+            tuple_sizes(a, sizes);
+            tuple_sizes(b, sizes);
+        }
+
+        SExpr::As(_, v) | SExpr::Assign(_, _, v) | SExpr::Field(_, v, _) => tuple_sizes(v, sizes),
+
+        SExpr::Application(_, f, args) => {
+            tuple_sizes(f, sizes);
+            for arg in args {
+                tuple_sizes(arg, sizes);
+            }
+        }
+
+        SExpr::With(_, assigns, body) => {
+            for assign in assigns {
+                tuple_sizes(assign, sizes);
+            }
+            tuple_sizes(body, sizes);
+        }
+
+        SExpr::Match(_, value, arms) => {
+            tuple_sizes(value, sizes);
+            for (_, arm, _) in arms {
+                tuple_sizes(arm, sizes);
+            }
+        }
+
+        SExpr::Empty(_) | SExpr::TypeAlias(_, _) | SExpr::Symbol(_, _) | SExpr::Function(_, _) => {
+            ()
+        }
+    }
+}
+
+// Generates the functions that represent tuples of the given sizes. A tuple is a closure struct
+// holding its elements that is waiting for a selector, so it lives on the heap like any other
+// closure. The constructor takes the elements followed by a selector and calls the selector with
+// the elements, and the selector for an element returns that element.
+fn tuple_functions(sizes: &BTreeSet<usize>) -> Vec<IrFunction> {
+    let mut funcs = vec![];
+    for &len in sizes.iter() {
+        funcs.push(IrFunction {
+            name: tuple_constructor_name(len),
+            argc: len + 1,
+            ssas: vec![
+                IrSsa {
+                    local: Some(0),
+                    local_lifetime: 0,
+                    local_register: 0,
+                    instr: IrInstruction::Call(false),
+                    args: std::iter::once(IrArgument::Argument(len))
+                        .chain((0..len).map(IrArgument::Argument))
+                        .collect(),
+                },
+                IrSsa {
+                    local: None,
+                    local_lifetime: 0,
+                    local_register: 0,
+                    instr: IrInstruction::Ret,
+                    args: vec![IrArgument::Local(0)],
+                },
+            ],
+        });
+
+        for i in 0..len {
+            funcs.push(IrFunction {
+                name: tuple_selector_name(len, i),
+                argc: len,
+                ssas: vec![
+                    IrSsa {
+                        local: Some(0),
+                        local_lifetime: 0,
+                        local_register: 0,
+                        instr: IrInstruction::Load,
+                        args: vec![IrArgument::Argument(i)],
+                    },
+                    IrSsa {
+                        local: None,
+                        local_lifetime: 0,
+                        local_register: 0,
+                        instr: IrInstruction::Ret,
+                        args: vec![IrArgument::Local(0)],
+                    },
+                ],
+            });
+        }
+    }
+
+    funcs
+}
+
 fn calculate_lifetimes(func: &mut IrFunction) {
     let mut iter = func.ssas.iter_mut();
     let mut i = 0;
@@ -391,6 +668,63 @@ fn insert_rc_instructions(func: &mut IrFunction) {
     }
 }
 
+// Merges partial applications into the instructions that use them. An apply whose result is only
+// used as the callee of a later apply or call is removed and its arguments are passed by that
+// instruction instead, so a chain of applications to a function becomes a single apply, or a
+// single call with known arity once every argument of the function is passed.
+fn fuse_apply_chains(func: &mut IrFunction, argcs: &[usize]) {
+    use std::cmp::Ordering;
+
+    let mut i = 0;
+    while i < func.ssas.len() {
+        let local = match (func.ssas[i].instr, func.ssas[i].args.first()) {
+            (IrInstruction::Apply | IrInstruction::Call(_), Some(IrArgument::Local(l))) => *l,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let uses = func
+            .ssas
+            .iter()
+            .flat_map(|v| v.args.iter())
+            .filter(|v| **v == IrArgument::Local(local))
+            .count();
+        let applied = func.ssas[..i]
+            .iter()
+            .position(|v| v.local == Some(local))
+            .and_then(|j| match (func.ssas[j].instr, func.ssas[j].args.first()) {
+                (IrInstruction::Apply, Some(IrArgument::Function(f))) if uses == 1 => Some((j, *f)),
+                _ => None,
+            });
+        let (j, f) = match applied {
+            Some(v) => v,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let argc = func.ssas[j].args.len() + func.ssas[i].args.len() - 2;
+        let instr = match argc.cmp(&argcs[f]) {
+            Ordering::Less => IrInstruction::Apply,
+            Ordering::Equal => IrInstruction::Call(true),
+            Ordering::Greater => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let applied = func.ssas.remove(j);
+        i -= 1;
+        let ssa = &mut func.ssas[i];
+        let rest = ssa.args.split_off(1);
+        ssa.args = applied.args.into_iter().chain(rest).collect();
+        ssa.instr = instr;
+    }
+}
+
 /// Converts the frontend IR language to the backend IR language.
 pub fn convert_frontend_ir_to_backend_ir(module: &ir::IrModule) -> IrModule {
     let mut new = IrModule { funcs: vec![] };
@@ -400,23 +734,49 @@ pub fn convert_frontend_ir_to_backend_ir(module: &ir::IrModule) -> IrModule {
         .iter()
         .map(|v| (v.0.clone(), v.1.captured_names.clone()))
         .collect();
-    for func in module.funcs.iter() {
+
+    // Functions are sorted by name so that the order does not depend on the order of the map, and
+    // tuples are represented by generated functions that follow the functions of the module
+    let mut funcs: Vec<_> = module.funcs.iter().collect();
+    funcs.sort_by(|a, b| a.0.cmp(b.0));
+    let mut sizes = BTreeSet::new();
+    for func in funcs.iter() {
+        tuple_sizes(&func.1.body, &mut sizes);
+    }
+    let tuple_funcs = tuple_functions(&sizes);
+
+    // Function ids are indices into the list of functions
+    let ids: HashMap<_, _> = funcs
+        .iter()
+        .map(|v| v.0.clone())
+        .chain(tuple_funcs.iter().map(|v| v.name.clone()))
+        .enumerate()
+        .map(|(i, v)| (v, i))
+        .collect();
+
+    let argcs: Vec<_> = funcs
+        .iter()
+        .map(|v| v.1.args.len() + v.1.captured.len())
+        .chain(tuple_funcs.iter().map(|v| v.argc))
+        .collect();
+
+    for func in funcs {
         let mut f = IrFunction {
-            name: func.1.name.clone(),
+            name: func.0.clone(),
             argc: func.1.args.len() + func.1.captured.len(),
             ssas: vec![],
         };
-        let args_map: HashMap<String, usize> = func
+        let args_map: HashMap<String, IrArgument> = func
             .1
             .captured_names
             .iter()
             .cloned()
             .enumerate()
             .chain(func.1.args.iter().map(|v| v.0.clone()).enumerate())
-            .map(|v| (v.1, v.0))
+            .map(|v| (v.1, IrArgument::Argument(v.0)))
             .collect();
 
-        conversion_helper(&args_map, &mut f, &func.1.body, &map);
+        conversion_helper(&args_map, &mut f, &func.1.body, &map, &ids);
         f.ssas.push(IrSsa {
             local: None,
             local_lifetime: 0,
@@ -429,11 +789,154 @@ pub fn convert_frontend_ir_to_backend_ir(module: &ir::IrModule) -> IrModule {
             },
         });
 
+        fuse_apply_chains(&mut f, &argcs);
         calculate_lifetimes(&mut f);
         insert_rc_instructions(&mut f);
 
         new.funcs.push(f);
     }
 
+    for mut f in tuple_funcs {
+        calculate_lifetimes(&mut f);
+        insert_rc_instructions(&mut f);
+        new.funcs.push(f);
+    }
+
     new
 }
+
+/// Merges several lower level IR modules into one, renumbering function ids so that references
+/// still point to the same functions. The first module keeps its function names; functions in the
+/// other modules are qualified with their module name so that names stay unique.
+pub fn merge_modules(modules: Vec<(String, IrModule)>) -> IrModule {
+    let mut new = IrModule { funcs: vec![] };
+
+    for (i, (name, module)) in modules.into_iter().enumerate() {
+        let offset = new.funcs.len();
+        for mut func in module.funcs {
+            if i != 0 {
+                func.name = format!("{}::{}", name, func.name);
+            }
+
+            for ssa in func.ssas.iter_mut() {
+                for arg in ssa.args.iter_mut() {
+                    if let IrArgument::Function(f) = arg {
+                        *f += offset;
+                    }
+                }
+            }
+
+            new.funcs.push(func);
+        }
+    }
+
+    new
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ssa(local: Option<usize>, instr: IrInstruction, args: Vec<IrArgument>) -> IrSsa {
+        IrSsa {
+            local,
+            local_lifetime: 0,
+            local_register: 0,
+            instr,
+            args,
+        }
+    }
+
+    // Builds a function that applies function 0 to one of its own arguments at a time, n times,
+    // and returns the result.
+    fn apply_chain(n: usize) -> IrFunction {
+        let mut ssas = vec![ssa(
+            Some(0),
+            IrInstruction::Apply,
+            vec![IrArgument::Function(0), IrArgument::Argument(0)],
+        )];
+        for i in 1..n {
+            ssas.push(ssa(
+                Some(i),
+                IrInstruction::Apply,
+                vec![IrArgument::Local(i - 1), IrArgument::Argument(i)],
+            ));
+        }
+        ssas.push(ssa(
+            None,
+            IrInstruction::Ret,
+            vec![IrArgument::Local(n - 1)],
+        ));
+
+        IrFunction {
+            name: String::from("chain"),
+            argc: n,
+            ssas,
+        }
+    }
+
+    // Checks that a function is a single instruction passing every argument to function 0
+    // followed by a return.
+    fn is_fused(func: &IrFunction, n: usize, instr: IrInstruction) -> bool {
+        let args: Vec<_> = std::iter::once(IrArgument::Function(0))
+            .chain((0..n).map(IrArgument::Argument))
+            .collect();
+        func.ssas.len() == 2
+            && std::mem::discriminant(&func.ssas[0].instr) == std::mem::discriminant(&instr)
+            && func.ssas[0].args == args
+            && matches!(func.ssas[1].instr, IrInstruction::Ret)
+    }
+
+    #[test]
+    fn saturated_chains_fuse_into_one_call() {
+        for n in 2..8 {
+            let mut func = apply_chain(n);
+            fuse_apply_chains(&mut func, &[n]);
+            assert!(is_fused(&func, n, IrInstruction::Call(true)), "n = {}", n);
+        }
+    }
+
+    #[test]
+    fn partial_chains_fuse_into_one_apply() {
+        for n in 2..8 {
+            let mut func = apply_chain(n);
+            fuse_apply_chains(&mut func, &[n + 2]);
+            assert!(is_fused(&func, n, IrInstruction::Apply), "n = {}", n);
+        }
+    }
+
+    #[test]
+    fn chains_past_the_arity_call_then_apply_the_result() {
+        let mut func = apply_chain(4);
+        fuse_apply_chains(&mut func, &[2]);
+        assert_eq!(func.ssas.len(), 4);
+        assert!(matches!(func.ssas[0].instr, IrInstruction::Call(true)));
+        assert!(matches!(func.ssas[1].instr, IrInstruction::Apply));
+        assert!(func.ssas[1].args == vec![IrArgument::Local(1), IrArgument::Argument(2)]);
+    }
+
+    #[test]
+    fn shared_intermediates_are_left_alone() {
+        // The reference count of the partial application is changed, so it is used twice
+        let mut func = apply_chain(2);
+        func.ssas.insert(
+            1,
+            ssa(None, IrInstruction::RcInc, vec![IrArgument::Local(0)]),
+        );
+        fuse_apply_chains(&mut func, &[2]);
+        assert_eq!(func.ssas.len(), 4);
+        assert!(matches!(func.ssas[0].instr, IrInstruction::Apply));
+        assert!(matches!(func.ssas[2].instr, IrInstruction::Apply));
+    }
+
+    #[test]
+    fn call_intermediates_are_left_alone() {
+        // The intermediate value comes from a call that may do anything, so it is not an apply
+        let mut func = apply_chain(2);
+        func.ssas[0].instr = IrInstruction::Call(false);
+        fuse_apply_chains(&mut func, &[2]);
+        assert_eq!(func.ssas.len(), 3);
+        assert!(matches!(func.ssas[0].instr, IrInstruction::Call(false)));
+        assert!(func.ssas[1].args == vec![IrArgument::Local(0), IrArgument::Argument(1)]);
+    }
+}
@@ -1 +1,8 @@
+use super::Capabilities;
 
+/// The constructs the riscv64 backend can generate code for. There is no code generator for it yet.
+pub const CAPABILITIES: Capabilities = Capabilities {
+    arch: "riscv64",
+    register_args: 8,
+    features: &[],
+};
@@ -1 +1,8 @@
+use super::Capabilities;
 
+/// The constructs the wasm64 backend can generate code for. There is no code generator for it yet.
+pub const CAPABILITIES: Capabilities = Capabilities {
+    arch: "wasm64",
+    register_args: 0,
+    features: &[],
+};
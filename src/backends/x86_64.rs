@@ -1,13 +1,37 @@
 pub mod codegen;
 
-use super::GeneratedCode;
+use std::fmt::Write;
+
+use super::{Capabilities, Feature, GeneratedCode};
+
+/// The constructs the x86_64 backend can generate code for. Calls with known arity can only pass
+/// locals and functions on the stack, and can only call functions by name.
+pub const CAPABILITIES: Capabilities = Capabilities {
+    arch: "x86_64",
+    register_args: codegen::ARG_REGISTER_COUNT,
+    features: &[
+        Feature::Ret,
+        Feature::Load,
+        Feature::Apply,
+        Feature::DirectCall,
+        Feature::UnknownArityCall,
+        Feature::RefCount,
+    ],
+};
 
 /// Disassembles x86 machine code into human readable assembly to stdout.
 pub fn disassemble(code: &GeneratedCode, base: *const u8) {
+    print!("{}", disassemble_to_string(code, base));
+}
+
+/// Disassembles x86 machine code into human readable assembly.
+pub fn disassemble_to_string(code: &GeneratedCode, base: *const u8) -> String {
     use iced_x86::{Decoder, DecoderOptions, Formatter, Instruction, NasmFormatter};
 
-    for (name, range) in code.func_addrs.iter() {
-        println!("\n{}:", name);
+    let mut asm = String::new();
+
+    for (name, range) in code.get_funcs() {
+        writeln!(asm, "\n{}:", name).unwrap();
         let bytes = &code.data[range.start..range.end];
         let mut decoder = Decoder::with_ip(
             64,
@@ -29,18 +53,20 @@ pub fn disassemble(code: &GeneratedCode, base: *const u8) {
             output.clear();
             formatter.format(&instruction, &mut output);
 
-            print!("{:016X}\n    ", instruction.ip());
+            write!(asm, "{:016X}\n    ", instruction.ip()).unwrap();
             let start_index = instruction.ip() as usize - base as usize;
             let instr_bytes = &code.data[start_index..start_index + instruction.len()];
             for b in instr_bytes.iter() {
-                print!("{:02X}", b);
+                write!(asm, "{:02X}", b).unwrap();
             }
             if instr_bytes.len() < 10 {
                 for _ in 0..10 - instr_bytes.len() {
-                    print!("  ");
+                    asm.push_str("  ");
                 }
             }
-            println!(" {}", output);
+            writeln!(asm, " {}", output).unwrap();
         }
     }
+
+    asm
 }
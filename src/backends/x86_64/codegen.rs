@@ -1,12 +1,21 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 use super::super::super::backends;
-use super::super::ir::{IrArgument, IrInstruction, IrModule};
-use super::super::GeneratedCode;
+use super::super::ir::{IrArgument, IrFunction, IrInstruction, IrModule};
+use super::super::{GeneratedCode, RelocationError};
 
-const ARG_REGISTER_COUNT: usize = 6;
+/// The number of arguments passed in registers.
+pub const ARG_REGISTER_COUNT: usize = 6;
 const NONARG_REGISTER_COUNT: usize = 8;
 
+// Rough sizes in bytes used to estimate how much code a function generates.
+const FUNCTION_HEADER_SIZE_ESTIMATE: usize = 48;
+const SSA_SIZE_ESTIMATE: usize = 64;
+const SSA_ARG_SIZE_ESTIMATE: usize = 12;
+
+// Modules with fewer functions than this are generated on a single thread.
+const PARALLEL_FUNCTION_THRESHOLD: usize = 256;
+
 enum InstructionRegister {
     Bit32(u8),
     Bit64(u8),
@@ -176,10 +185,7 @@ fn generate_mov(
                 unreachable!();
             };
 
-            code.data.push((offset & 0xff) as u8);
-            code.data.push(((offset >> 8) & 0xff) as u8);
-            code.data.push(((offset >> 16) & 0xff) as u8);
-            code.data.push(((offset >> 24) & 0xff) as u8);
+            code.emit_u32(offset);
         }
 
         (false, true) => {
@@ -204,10 +210,7 @@ fn generate_mov(
             code.data.push(0x48 | (source_location.is_64_bit() << 2));
             code.data.push(0x89);
             code.data.push(0x85 | (source_location.get_register() << 3));
-            code.data.push((offset & 0xff) as u8);
-            code.data.push(((offset >> 8) & 0xff) as u8);
-            code.data.push(((offset >> 16) & 0xff) as u8);
-            code.data.push(((offset >> 24) & 0xff) as u8);
+            code.emit_u32(offset);
         }
 
         (false, false) => {
@@ -223,7 +226,7 @@ fn generate_mov(
 fn generate_lea(
     code: &mut GeneratedCode,
     dest: Register,
-    source: &str,
+    source: usize,
     stack_allocated_local_count: &mut usize,
 ) {
     let dest_location = dest.convert_to_instr_arg();
@@ -231,262 +234,295 @@ fn generate_lea(
         code.data.push(0x48 | (dest_location.is_64_bit() << 2));
         code.data.push(0x8d);
         code.data.push(0x05 | (dest_location.get_register() << 3));
-        code.func_refs.insert(code.data.len(), source.to_owned());
-        code.data.push(0x01);
-        code.data.push(0x00);
-        code.data.push(0x00);
-        code.data.push(0x00);
+        code.func_refs.insert(code.data.len(), source);
+        code.emit(&[0x01, 0x00, 0x00, 0x00]);
     } else {
-        code.data.push(0x48);
-        code.data.push(0x8d);
-        code.data.push(0x05);
-        code.func_refs.insert(code.data.len(), source.to_owned());
-        code.data.push(0x01);
-        code.data.push(0x00);
-        code.data.push(0x00);
-        code.data.push(0x00);
+        code.emit(&[0x48, 0x8d, 0x05]);
+        code.func_refs.insert(code.data.len(), source);
+        code.emit(&[0x01, 0x00, 0x00, 0x00]);
         generate_mov(code, dest, Register::Rax, stack_allocated_local_count);
     }
 }
 
+/// Estimates the number of bytes of machine code generated for a function.
+pub fn estimate_function_size(func: &IrFunction) -> usize {
+    FUNCTION_HEADER_SIZE_ESTIMATE
+        + func
+            .ssas
+            .iter()
+            .map(|v| SSA_SIZE_ESTIMATE + v.args.len() * SSA_ARG_SIZE_ESTIMATE)
+            .sum::<usize>()
+}
+
+/// Computes an upper bound on the number of bytes of stack used by the frame of a function: the
+/// return address, the saved frame pointer, saved callee saved registers, spilled locals, and the
+/// largest number of registers and arguments pushed around a single call. Stack used by the
+/// functions it calls is not included. Must be run after register allocation.
+pub fn frame_size(func: &IrFunction) -> usize {
+    let mut saved = HashSet::new();
+    let mut spilled = 0;
+    let mut call = 0;
+
+    for ssa in func.ssas.iter() {
+        if ssa.local.is_some() {
+            match Register::convert_nonarg_register_id(ssa.local_register) {
+                Register::Spilled(s) => spilled = spilled.max(s + 1),
+                r if r.is_callee_saved() => {
+                    saved.insert(ssa.local_register);
+                }
+                _ => (),
+            }
+        }
+
+        // Argument registers of the caller are saved around every call, along with r11
+        let pushed = match ssa.instr {
+            IrInstruction::Call(true) => {
+                1 + func.argc.min(ARG_REGISTER_COUNT)
+                    + ssa.args.len().saturating_sub(ARG_REGISTER_COUNT + 1)
+            }
+            IrInstruction::Call(false) => 1 + func.argc.min(ARG_REGISTER_COUNT) + ssa.args.len(),
+            IrInstruction::Apply => func.argc.min(ARG_REGISTER_COUNT) + ssa.args.len(),
+            IrInstruction::Ret
+            | IrInstruction::Load
+            | IrInstruction::RcInc
+            | IrInstruction::RcFuncFree => 0,
+        };
+        call = call.max(pushed);
+    }
+
+    (2 + saved.len() + spilled + call) * 8
+}
+
+/// Allocates registers for every function in a module and computes the size of the stack frame of
+/// each one, in module order.
+pub fn frame_sizes(module: &mut IrModule) -> Vec<usize> {
+    module
+        .funcs
+        .iter_mut()
+        .map(|func| {
+            backends::linear_scan(func, NONARG_REGISTER_COUNT);
+            frame_size(func)
+        })
+        .collect()
+}
+
 /// Generates the _start function, which calls main and the exit syscall.
 pub fn generate_start_func(code: &mut GeneratedCode) {
-    code.func_addrs
-        .insert(String::from("_start"), code.len()..code.len() + 1);
-    code.func_addrs.insert(String::from("exit"), 0..0);
+    let start = code.intern("_start");
+    let main = code.intern("main");
+    let exit = code.intern("exit");
+    code.func_addrs[start] = code.len()..code.len() + 1;
 
     // call main
     code.data.push(0xe8);
-    code.func_refs.insert(code.len(), String::from("main"));
-    code.data.push(0x10);
-    code.data.push(0x00);
-    code.data.push(0x00);
-    code.data.push(0x00);
+    code.func_refs.insert(code.len(), main);
+    code.emit(&[0x10, 0x00, 0x00, 0x00]);
 
     // mov rdi, rax
-    code.data.push(0x48);
-    code.data.push(0x89);
-    code.data.push(0xc7);
+    code.emit(&[0x48, 0x89, 0xc7]);
 
     // call exit
     code.data.push(0xe8);
-    code.func_refs.insert(code.len(), String::from("exit"));
-    code.data.push(0x00);
-    code.data.push(0x00);
-    code.data.push(0x00);
-    code.data.push(0x00);
+    code.func_refs.insert(code.len(), exit);
+    code.emit(&[0x00, 0x00, 0x00, 0x00]);
 
-    code.func_addrs.get_mut("_start").unwrap().end = code.len();
+    code.func_addrs[start].end = code.len();
 }
 
-/// Transforms an IrModule into x86 machine code.
-pub fn generate_code(module: &mut IrModule) -> GeneratedCode {
-    let mut code = GeneratedCode::new();
+// Generates a call to a function. The addend is added to the address of the function when the
+// call is relocated. Far calls load the absolute address of the function into rax and call it
+// through the register, so the function may be anywhere in the address space.
+fn generate_call(code: &mut GeneratedCode, func: usize, addend: u32, far_calls: bool) {
+    if far_calls {
+        // mov rax, func
+        code.emit(&[0x48, 0xb8]);
+        code.far_refs.insert(code.data.len(), func);
+        code.emit(&(addend as u64).to_le_bytes());
+
+        // call rax
+        code.emit(&[0xff, 0xd0]);
+    } else {
+        // call func
+        code.data.push(0xe8);
+        code.func_refs.insert(code.data.len(), func);
+        code.emit_u32(addend);
+    }
+}
 
-    for func in module.funcs.iter_mut() {
-        backends::linear_scan(func, NONARG_REGISTER_COUNT);
+// Generates the code for a single function, using the argument counts of all functions in the
+// module.
+fn generate_function(
+    code: &mut GeneratedCode,
+    id: usize,
+    func: &IrFunction,
+    argcs: &[usize],
+    far_calls: bool,
+) {
+    // Add padding
+    while code.data.len() % 16 != 0 {
+        code.data.push(0);
     }
 
-    for func in module.funcs.iter() {
-        // Add padding
-        while code.data.len() % 16 != 0 {
-            code.data.push(0);
-        }
+    // Add function
+    code.func_addrs[id] = code.len()..code.len() + 1;
 
-        // Add function
-        code.func_addrs
-            .insert(func.name.clone(), code.len()..code.len() + 1);
+    // Offset by 1
+    code.data.push(0x00);
 
-        // Offset by 1
-        code.data.push(0x00);
+    // Argument count
+    code.emit_u32(func.argc as u32);
 
-        // Argument count
-        code.data.push((func.argc & 0xff) as u8);
-        code.data.push(((func.argc >> 8) & 0xff) as u8);
-        code.data.push(((func.argc >> 16) & 0xff) as u8);
-        code.data.push(((func.argc >> 24) & 0xff) as u8);
+    // Padding
+    while code.data.len() % 16 != 0 {
+        code.data.push(0);
+    }
 
-        // Padding
-        while code.data.len() % 16 != 0 {
-            code.data.push(0);
+    // push rbp
+    code.data.push(0x55);
+    let mut stack_allocated_local_count = 0usize;
+
+    // mov rbp, rsp
+    generate_mov(
+        code,
+        Register::Rbp,
+        Register::Rsp,
+        &mut stack_allocated_local_count,
+    );
+
+    // Registers are pushed in order of their ids so that the same function always generates the
+    // same code
+    let mut used_registers = BTreeSet::new();
+    for ssa in func.ssas.iter() {
+        if ssa.local.is_some()
+            && Register::convert_nonarg_register_id(ssa.local_register).is_callee_saved()
+            && !used_registers.contains(&ssa.local_register)
+        {
+            used_registers.insert(ssa.local_register);
         }
+    }
 
-        // push rbp
-        code.data.push(0x55);
-        let mut stack_allocated_local_count = 0usize;
-
-        // mov rbp, rsp
-        generate_mov(
-            &mut code,
-            Register::Rbp,
-            Register::Rsp,
-            &mut stack_allocated_local_count,
-        );
-
-        let mut used_registers = HashSet::new();
-        for ssa in func.ssas.iter() {
-            if ssa.local.is_some()
-                && Register::convert_nonarg_register_id(ssa.local_register).is_callee_saved()
-                && !used_registers.contains(&ssa.local_register)
-            {
-                used_registers.insert(ssa.local_register);
-            }
+    // Push used registers
+    let used_registers: Vec<_> = used_registers.into_iter().collect();
+    for register in used_registers.iter() {
+        let register = Register::convert_nonarg_register_id(*register).convert_to_instr_arg();
+        if register.is_64_bit() != 0 {
+            code.data.push(0x41);
         }
+        code.data.push(0x50 | register.get_register());
+    }
 
-        // Push used registers
-        let used_registers: Vec<_> = used_registers.into_iter().collect();
-        for register in used_registers.iter() {
-            let register = Register::convert_nonarg_register_id(*register).convert_to_instr_arg();
-            if register.is_64_bit() != 0 {
-                code.data.push(0x41);
+    let mut local_to_register = HashMap::new();
+    let mut register_lifetimes = vec![0; NONARG_REGISTER_COUNT];
+    for ssa in func.ssas.iter() {
+        for lifetime in register_lifetimes.iter_mut() {
+            if *lifetime != 0 {
+                *lifetime -= 1;
             }
-            code.data.push(0x50 | register.get_register());
         }
 
-        let mut local_to_register = HashMap::new();
-        let mut register_lifetimes = vec![0; NONARG_REGISTER_COUNT];
-        for ssa in func.ssas.iter() {
-            for lifetime in register_lifetimes.iter_mut() {
-                if *lifetime != 0 {
-                    *lifetime -= 1;
-                }
-            }
+        if let Some(local) = ssa.local {
+            let register = Register::convert_nonarg_register_id(ssa.local_register);
 
-            if let Some(local) = ssa.local {
-                let register = Register::convert_nonarg_register_id(ssa.local_register);
-
-                if register_lifetimes.len() < ssa.local_register {
-                    register_lifetimes[ssa.local_register] = ssa.local_lifetime;
-                } else {
-                    register_lifetimes.push(ssa.local_lifetime);
-                }
-
-                local_to_register.insert(local, register);
+            if register_lifetimes.len() < ssa.local_register {
+                register_lifetimes[ssa.local_register] = ssa.local_lifetime;
+            } else {
+                register_lifetimes.push(ssa.local_lifetime);
             }
 
-            match ssa.instr {
-                IrInstruction::Ret => {
-                    if let Some(IrArgument::Local(arg)) = ssa.args.first() {
-                        let register = local_to_register.get(arg).unwrap();
-                        generate_mov(
-                            &mut code,
-                            Register::Rax,
-                            *register,
-                            &mut stack_allocated_local_count,
-                        );
-                    }
-
-                    // Pop used registers
-                    for register in used_registers.iter().rev() {
-                        let register =
-                            Register::convert_nonarg_register_id(*register).convert_to_instr_arg();
-                        if register.is_64_bit() != 0 {
-                            code.data.push(0x41);
-                        }
-                        code.data.push(0x58 | register.get_register());
-                    }
+            local_to_register.insert(local, register);
+        }
 
-                    // mov rsp, rbp
+        match ssa.instr {
+            IrInstruction::Ret => {
+                if let Some(IrArgument::Local(arg)) = ssa.args.first() {
+                    let register = local_to_register.get(arg).unwrap();
                     generate_mov(
-                        &mut code,
-                        Register::Rsp,
-                        Register::Rbp,
+                        code,
+                        Register::Rax,
+                        *register,
                         &mut stack_allocated_local_count,
                     );
+                }
 
-                    // pop rbp
-                    code.data.push(0x5d);
-
-                    // ret
-                    code.data.push(0xc3);
+                // Pop used registers
+                for register in used_registers.iter().rev() {
+                    let register =
+                        Register::convert_nonarg_register_id(*register).convert_to_instr_arg();
+                    if register.is_64_bit() != 0 {
+                        code.data.push(0x41);
+                    }
+                    code.data.push(0x58 | register.get_register());
                 }
 
-                IrInstruction::Load => {
-                    if let Some(local) = ssa.local {
-                        let local_reg = *local_to_register.get(&local).unwrap();
+                // mov rsp, rbp
+                generate_mov(
+                    code,
+                    Register::Rsp,
+                    Register::Rbp,
+                    &mut stack_allocated_local_count,
+                );
 
-                        match ssa.args.first() {
-                            Some(IrArgument::Argument(arg)) => {
-                                // mov local, [rbp + offset]
-                                generate_mov(
-                                    &mut code,
-                                    local_reg,
-                                    Register::convert_arg_register_id(*arg),
-                                    &mut stack_allocated_local_count,
-                                );
-                            }
+                // pop rbp
+                code.data.push(0x5d);
 
-                            Some(IrArgument::Function(func)) => {
-                                generate_lea(
-                                    &mut code,
-                                    local_reg,
-                                    func,
-                                    &mut stack_allocated_local_count,
-                                );
-                            }
+                // ret
+                code.data.push(0xc3);
+            }
 
-                            _ => (),
-                        }
-                    }
-                }
+            IrInstruction::Load => {
+                if let Some(local) = ssa.local {
+                    let local_reg = *local_to_register.get(&local).unwrap();
 
-                IrInstruction::Apply => {
-                    let f = ssa.args.first().unwrap();
-                    match f {
-                        // TODO: prove or disprove this
-                        IrArgument::Local(_) => {
-                            unreachable!("Locals are either called or applied earlier")
+                    match ssa.args.first() {
+                        Some(IrArgument::Argument(arg)) => {
+                            // mov local, [rbp + offset]
+                            generate_mov(
+                                code,
+                                local_reg,
+                                Register::convert_arg_register_id(*arg),
+                                &mut stack_allocated_local_count,
+                            );
                         }
 
-                        IrArgument::Argument(_) => {
-                            unreachable!("Arguments are called with unknown arity")
+                        Some(IrArgument::Function(func)) => {
+                            generate_lea(code, local_reg, *func, &mut stack_allocated_local_count);
                         }
 
-                        IrArgument::Function(f) => {
-                            for arg in ssa.args.iter().rev() {
-                                match arg {
-                                    IrArgument::Local(local) => {
-                                        let local_reg = local_to_register.get(&local).unwrap();
-                                        let local_location = local_reg.convert_to_instr_arg();
-                                        if local_location.is_register() {
-                                            // push local
-                                            if local_location.is_64_bit() != 0 {
-                                                code.data.push(0x41);
-                                            }
-                                            code.data.push(0x50 | local_location.get_register());
-                                        } else {
-                                            // mov rax, [rbp - offset]
-                                            generate_mov(
-                                                &mut code,
-                                                Register::Rax,
-                                                *local_reg,
-                                                &mut stack_allocated_local_count,
-                                            );
-
-                                            // push rax
-                                            code.data.push(0x50);
-                                        }
-                                    }
+                        _ => (),
+                    }
+                }
+            }
 
-                                    IrArgument::Argument(arg) => {
-                                        // mov rax, arg
-                                        generate_mov(
-                                            &mut code,
-                                            Register::Rax,
-                                            Register::convert_arg_register_id(*arg),
-                                            &mut stack_allocated_local_count,
-                                        );
+            IrInstruction::Apply => {
+                let f = ssa.args.first().unwrap();
+                match f {
+                    // TODO: prove or disprove this
+                    IrArgument::Local(_) => {
+                        unreachable!("Locals are either called or applied earlier")
+                    }
 
-                                        // push rax
-                                        code.data.push(0x50);
-                                    }
+                    IrArgument::Argument(_) => {
+                        unreachable!("Arguments are called with unknown arity")
+                    }
 
-                                    IrArgument::Function(f) => {
-                                        // lea rax, [rel func]
-                                        generate_lea(
-                                            &mut code,
+                    IrArgument::Function(f) => {
+                        for arg in ssa.args.iter().rev() {
+                            match arg {
+                                IrArgument::Local(local) => {
+                                    let local_reg = local_to_register.get(&local).unwrap();
+                                    let local_location = local_reg.convert_to_instr_arg();
+                                    if local_location.is_register() {
+                                        // push local
+                                        if local_location.is_64_bit() != 0 {
+                                            code.data.push(0x41);
+                                        }
+                                        code.data.push(0x50 | local_location.get_register());
+                                    } else {
+                                        // mov rax, [rbp - offset]
+                                        generate_mov(
+                                            code,
                                             Register::Rax,
-                                            f,
+                                            *local_reg,
                                             &mut stack_allocated_local_count,
                                         );
 
@@ -494,212 +530,195 @@ pub fn generate_code(module: &mut IrModule) -> GeneratedCode {
                                         code.data.push(0x50);
                                     }
                                 }
-                            }
 
-                            // mov rax, rsp
-                            generate_mov(
-                                &mut code,
-                                Register::Rax,
-                                Register::Rsp,
-                                &mut stack_allocated_local_count,
-                            );
+                                IrArgument::Argument(arg) => {
+                                    // mov rax, arg
+                                    generate_mov(
+                                        code,
+                                        Register::Rax,
+                                        Register::convert_arg_register_id(*arg),
+                                        &mut stack_allocated_local_count,
+                                    );
 
-                            // Push arguments
-                            for i in 0..func.argc {
-                                let reg =
-                                    Register::convert_arg_register_id(i).convert_to_instr_arg();
-                                if !reg.is_register() {
-                                    break;
+                                    // push rax
+                                    code.data.push(0x50);
                                 }
 
-                                if reg.is_64_bit() != 0 {
-                                    code.data.push(0x41);
-                                }
+                                IrArgument::Function(f) => {
+                                    // lea rax, [rel func]
+                                    generate_lea(
+                                        code,
+                                        Register::Rax,
+                                        *f,
+                                        &mut stack_allocated_local_count,
+                                    );
 
-                                code.data.push(0x50 | reg.get_register());
+                                    // push rax
+                                    code.data.push(0x50);
+                                }
                             }
+                        }
 
-                            // mov rdi, rax
-                            generate_mov(
-                                &mut code,
-                                Register::Rdi,
-                                Register::Rax,
-                                &mut stack_allocated_local_count,
-                            );
+                        // mov rax, rsp
+                        generate_mov(
+                            code,
+                            Register::Rax,
+                            Register::Rsp,
+                            &mut stack_allocated_local_count,
+                        );
 
-                            // mov rsi, len
-                            let len = ssa.args.len() * 8;
-                            code.data.push(0xbe);
-                            code.data.push((len & 0xff) as u8);
-                            code.data.push(((len >> 8) & 0xff) as u8);
-                            code.data.push(((len >> 16) & 0xff) as u8);
-                            code.data.push(((len >> 24) & 0xff) as u8);
-
-                            // mov rdx, size
-                            let mut size = 0;
-                            for func in module.funcs.iter() {
-                                if &func.name == f {
-                                    size = (func.argc + 1) * 8;
-                                    break;
-                                }
+                        // Push arguments
+                        for i in 0..func.argc {
+                            let reg = Register::convert_arg_register_id(i).convert_to_instr_arg();
+                            if !reg.is_register() {
+                                break;
                             }
-                            code.data.push(0xba);
-                            code.data.push((size & 0xff) as u8);
-                            code.data.push(((size >> 8) & 0xff) as u8);
-                            code.data.push(((size >> 16) & 0xff) as u8);
-                            code.data.push(((size >> 24) & 0xff) as u8);
-
-                            // call rccopy
-                            code.data.push(0xe8);
-                            code.func_refs
-                                .insert(code.data.len(), String::from("rccopy"));
-                            if !code.func_addrs.contains_key("rccopy") {
-                                code.func_addrs.insert(String::from("rccopy"), 0..0);
+
+                            if reg.is_64_bit() != 0 {
+                                code.data.push(0x41);
                             }
-                            code.data.push(0x00);
-                            code.data.push(0x00);
-                            code.data.push(0x00);
-                            code.data.push(0x00);
-
-                            // Pop original arguments
-                            for i in 0..func.argc {
-                                let reg =
-                                    Register::convert_arg_register_id(i).convert_to_instr_arg();
-                                if !reg.is_register() {
-                                    break;
-                                }
 
-                                if reg.is_64_bit() != 0 {
-                                    code.data.push(0x41);
-                                }
+                            code.data.push(0x50 | reg.get_register());
+                        }
+
+                        // mov rdi, rax
+                        generate_mov(
+                            code,
+                            Register::Rdi,
+                            Register::Rax,
+                            &mut stack_allocated_local_count,
+                        );
 
-                                code.data.push(0x58 | reg.get_register());
+                        // mov rsi, len
+                        let len = ssa.args.len() * 8;
+                        code.data.push(0xbe);
+                        code.emit_u32(len as u32);
+
+                        // mov rdx, size
+                        let size = (argcs[*f] + 1) * 8;
+                        code.data.push(0xba);
+                        code.emit_u32(size as u32);
+
+                        // call rccopy
+                        let rccopy = code.intern("rccopy");
+                        generate_call(code, rccopy, 0, far_calls);
+
+                        // Pop original arguments
+                        for i in 0..func.argc {
+                            let reg = Register::convert_arg_register_id(i).convert_to_instr_arg();
+                            if !reg.is_register() {
+                                break;
                             }
 
-                            // sub rsp, len
-                            code.data.push(0x48);
-                            code.data.push(0x81);
-                            code.data.push(0xec);
-                            code.data.push((len & 0xff) as u8);
-                            code.data.push(((len >> 8) & 0xff) as u8);
-                            code.data.push(((len >> 16) & 0xff) as u8);
-                            code.data.push(((len >> 24) & 0xff) as u8);
-
-                            if let Some(local) = ssa.local {
-                                // mov local, rax
-                                generate_mov(
-                                    &mut code,
-                                    *local_to_register.get(&local).unwrap(),
-                                    Register::Rax,
-                                    &mut stack_allocated_local_count,
-                                );
+                            if reg.is_64_bit() != 0 {
+                                code.data.push(0x41);
                             }
+
+                            code.data.push(0x58 | reg.get_register());
                         }
-                    }
-                }
 
-                IrInstruction::Call(known_arity) => {
-                    if register_lifetimes[Register::R11.revert_to_nonarg_register_id()] != 0 {
-                        // push r11
-                        code.data.push(0x41);
-                        code.data.push(0x53);
-                    }
+                        // sub rsp, len
+                        code.emit(&[0x48, 0x81, 0xec]);
+                        code.emit_u32(len as u32);
 
-                    // Push arguments
-                    for i in 0..func.argc {
-                        let reg = Register::convert_arg_register_id(i).convert_to_instr_arg();
-                        if !reg.is_register() {
-                            break;
+                        if let Some(local) = ssa.local {
+                            // mov local, rax
+                            generate_mov(
+                                code,
+                                *local_to_register.get(&local).unwrap(),
+                                Register::Rax,
+                                &mut stack_allocated_local_count,
+                            );
                         }
+                    }
+                }
+            }
 
-                        if reg.is_64_bit() != 0 {
-                            code.data.push(0x41);
-                        }
+            IrInstruction::Call(known_arity) => {
+                if register_lifetimes[Register::R11.revert_to_nonarg_register_id()] != 0 {
+                    // push r11
+                    code.emit(&[0x41, 0x53]);
+                }
 
-                        code.data.push(0x50 | reg.get_register());
+                // Push arguments
+                for i in 0..func.argc {
+                    let reg = Register::convert_arg_register_id(i).convert_to_instr_arg();
+                    if !reg.is_register() {
+                        break;
                     }
 
-                    if known_arity {
-                        // First 6 arguments are stored in registers
-                        for (i, arg) in ssa.args.iter().skip(1).enumerate() {
-                            let arg_reg = Register::convert_arg_register_id(i);
+                    if reg.is_64_bit() != 0 {
+                        code.data.push(0x41);
+                    }
 
-                            match arg {
-                                IrArgument::Local(local) => {
-                                    let local_reg = *local_to_register.get(local).unwrap();
+                    code.data.push(0x50 | reg.get_register());
+                }
 
-                                    // mov arg, local
-                                    generate_mov(
-                                        &mut code,
-                                        arg_reg,
-                                        local_reg,
-                                        &mut stack_allocated_local_count,
-                                    );
-                                }
+                if known_arity {
+                    // First 6 arguments are stored in registers
+                    for (i, arg) in ssa.args.iter().skip(1).enumerate() {
+                        let arg_reg = Register::convert_arg_register_id(i);
 
-                                IrArgument::Argument(arg) => {
-                                    let local_reg = Register::convert_arg_register_id(*arg);
+                        match arg {
+                            IrArgument::Local(local) => {
+                                let local_reg = *local_to_register.get(local).unwrap();
 
-                                    // mov arg, local
-                                    generate_mov(
-                                        &mut code,
-                                        arg_reg,
-                                        local_reg,
-                                        &mut stack_allocated_local_count,
-                                    );
-                                }
+                                // mov arg, local
+                                generate_mov(
+                                    code,
+                                    arg_reg,
+                                    local_reg,
+                                    &mut stack_allocated_local_count,
+                                );
+                            }
 
-                                IrArgument::Function(func) => {
-                                    // lea arg, [rel func]
-                                    generate_lea(
-                                        &mut code,
-                                        arg_reg,
-                                        func,
-                                        &mut stack_allocated_local_count,
-                                    );
-                                }
+                            IrArgument::Argument(arg) => {
+                                let local_reg = Register::convert_arg_register_id(*arg);
+
+                                // mov arg, local
+                                generate_mov(
+                                    code,
+                                    arg_reg,
+                                    local_reg,
+                                    &mut stack_allocated_local_count,
+                                );
                             }
 
-                            if i == ARG_REGISTER_COUNT - 1 {
-                                break;
+                            IrArgument::Function(func) => {
+                                // lea arg, [rel func]
+                                generate_lea(
+                                    code,
+                                    arg_reg,
+                                    *func,
+                                    &mut stack_allocated_local_count,
+                                );
                             }
                         }
 
-                        // Rest of the arguments are stored on the stack
-                        for arg in ssa.args.iter().skip(ARG_REGISTER_COUNT + 1).rev() {
-                            match arg {
-                                IrArgument::Local(local) => {
-                                    let local_reg = *local_to_register.get(local).unwrap();
-                                    let local_location = local_reg.convert_to_instr_arg();
+                        if i == ARG_REGISTER_COUNT - 1 {
+                            break;
+                        }
+                    }
 
-                                    if local_location.is_register() {
-                                        // push local
-                                        if local_location.is_64_bit() != 0 {
-                                            code.data.push(0x41);
-                                        }
-                                        code.data.push(0x50 | local_location.get_register());
-                                    } else {
-                                        // mov rax, [rbp - offset]
-                                        generate_mov(
-                                            &mut code,
-                                            Register::Rax,
-                                            local_reg,
-                                            &mut stack_allocated_local_count,
-                                        );
+                    // Rest of the arguments are stored on the stack
+                    for arg in ssa.args.iter().skip(ARG_REGISTER_COUNT + 1).rev() {
+                        match arg {
+                            IrArgument::Local(local) => {
+                                let local_reg = *local_to_register.get(local).unwrap();
+                                let local_location = local_reg.convert_to_instr_arg();
 
-                                        // push rax
-                                        code.data.push(0x50);
+                                if local_location.is_register() {
+                                    // push local
+                                    if local_location.is_64_bit() != 0 {
+                                        code.data.push(0x41);
                                     }
-                                }
-
-                                IrArgument::Argument(_) => todo!(),
-
-                                IrArgument::Function(func) => {
-                                    // lea rax, [rel func]
-                                    generate_lea(
-                                        &mut code,
+                                    code.data.push(0x50 | local_location.get_register());
+                                } else {
+                                    // mov rax, [rbp - offset]
+                                    generate_mov(
+                                        code,
                                         Register::Rax,
-                                        func,
+                                        local_reg,
                                         &mut stack_allocated_local_count,
                                     );
 
@@ -707,181 +726,219 @@ pub fn generate_code(module: &mut IrModule) -> GeneratedCode {
                                     code.data.push(0x50);
                                 }
                             }
-                        }
 
-                        match ssa.args.first().unwrap() {
-                            IrArgument::Local(_) => todo!(),
                             IrArgument::Argument(_) => todo!(),
 
                             IrArgument::Function(func) => {
-                                // call func
-                                code.data.push(0xe8);
-
-                                // Insert the label
-                                code.func_refs.insert(code.data.len(), func.clone());
+                                // lea rax, [rel func]
+                                generate_lea(
+                                    code,
+                                    Register::Rax,
+                                    *func,
+                                    &mut stack_allocated_local_count,
+                                );
 
-                                // Value
-                                code.data.push(0x10);
-                                code.data.push(0x00);
-                                code.data.push(0x00);
-                                code.data.push(0x00);
+                                // push rax
+                                code.data.push(0x50);
                             }
                         }
-                    } else {
-                        for arg in ssa.args.iter().skip(1).rev() {
-                            match arg {
-                                IrArgument::Local(local) => {
-                                    // mov rax, local
-                                    generate_mov(
-                                        &mut code,
-                                        Register::Rax,
-                                        *local_to_register.get(local).unwrap(),
-                                        &mut stack_allocated_local_count,
-                                    );
-                                }
-
-                                IrArgument::Argument(arg) => {
-                                    // mov rax, arg
-                                    generate_mov(
-                                        &mut code,
-                                        Register::Rax,
-                                        Register::convert_arg_register_id(*arg),
-                                        &mut stack_allocated_local_count,
-                                    );
-                                }
+                    }
 
-                                IrArgument::Function(func) => {
-                                    // lea rax, [rel func]
-                                    generate_lea(
-                                        &mut code,
-                                        Register::Rax,
-                                        func,
-                                        &mut stack_allocated_local_count,
-                                    );
-                                }
-                            }
+                    match ssa.args.first().unwrap() {
+                        IrArgument::Local(_) => todo!(),
+                        IrArgument::Argument(_) => todo!(),
 
-                            // push rax
-                            code.data.push(0x50);
+                        IrArgument::Function(func) => {
+                            // call func
+                            generate_call(code, *func, 0x10, far_calls);
                         }
-
-                        // mov rsi, called_argc
-                        let called_argc = ssa.args.len() - 1;
-                        code.data.push(0xbe);
-                        code.data.push((called_argc & 0xff) as u8);
-                        code.data.push(((called_argc >> 8) & 0xff) as u8);
-                        code.data.push(((called_argc >> 16) & 0xff) as u8);
-                        code.data.push(((called_argc >> 24) & 0xff) as u8);
-
-                        match ssa.args.first().unwrap() {
+                    }
+                } else {
+                    for arg in ssa.args.iter().skip(1).rev() {
+                        match arg {
                             IrArgument::Local(local) => {
-                                // mov rdi, local
+                                // mov rax, local
                                 generate_mov(
-                                    &mut code,
-                                    Register::Rdi,
+                                    code,
+                                    Register::Rax,
                                     *local_to_register.get(local).unwrap(),
                                     &mut stack_allocated_local_count,
                                 );
                             }
 
                             IrArgument::Argument(arg) => {
-                                // mov rdi, arg
+                                // mov rax, arg
                                 generate_mov(
-                                    &mut code,
-                                    Register::Rdi,
+                                    code,
+                                    Register::Rax,
                                     Register::convert_arg_register_id(*arg),
                                     &mut stack_allocated_local_count,
                                 );
                             }
 
                             IrArgument::Function(func) => {
-                                // lea rdi, [rel func]
+                                // lea rax, [rel func]
                                 generate_lea(
-                                    &mut code,
-                                    Register::Rdi,
-                                    func,
+                                    code,
+                                    Register::Rax,
+                                    *func,
                                     &mut stack_allocated_local_count,
                                 );
                             }
                         }
 
-                        // mov rdx, rsp
-                        generate_mov(
-                            &mut code,
-                            Register::Rdx,
-                            Register::Rsp,
-                            &mut stack_allocated_local_count,
-                        );
+                        // push rax
+                        code.data.push(0x50);
+                    }
 
-                        // call call_unknown_arity
-                        code.data.push(0xe8);
-                        code.func_refs
-                            .insert(code.data.len(), String::from("call_unknown_arity"));
-                        if !code.func_addrs.contains_key("call_unknown_arity") {
-                            code.func_addrs
-                                .insert(String::from("call_unknown_arity"), 0..0);
+                    // mov rsi, called_argc
+                    let called_argc = ssa.args.len() - 1;
+                    code.data.push(0xbe);
+                    code.emit_u32(called_argc as u32);
+
+                    match ssa.args.first().unwrap() {
+                        IrArgument::Local(local) => {
+                            // mov rdi, local
+                            generate_mov(
+                                code,
+                                Register::Rdi,
+                                *local_to_register.get(local).unwrap(),
+                                &mut stack_allocated_local_count,
+                            );
+                        }
+
+                        IrArgument::Argument(arg) => {
+                            // mov rdi, arg
+                            generate_mov(
+                                code,
+                                Register::Rdi,
+                                Register::convert_arg_register_id(*arg),
+                                &mut stack_allocated_local_count,
+                            );
                         }
-                        code.data.push(0x00);
-                        code.data.push(0x00);
-                        code.data.push(0x00);
-                        code.data.push(0x00);
-                    }
 
-                    // Pop arguments passed into the function and arguments saved
-                    let mut pop_count = ssa.args.len() - 1;
-                    if known_arity {
-                        if pop_count > ARG_REGISTER_COUNT {
-                            pop_count -= ARG_REGISTER_COUNT;
-                        } else {
-                            pop_count = 0;
+                        IrArgument::Function(func) => {
+                            // lea rdi, [rel func]
+                            generate_lea(
+                                code,
+                                Register::Rdi,
+                                *func,
+                                &mut stack_allocated_local_count,
+                            );
                         }
                     }
-                    pop_count *= 8;
-                    if pop_count != 0 {
-                        // add rsp, pop_count
-                        code.data.push(0x48);
-                        code.data.push(0x81);
-                        code.data.push(0xc4);
-                        code.data.push((pop_count & 0xff) as u8);
-                        code.data.push(((pop_count >> 8) & 0xff) as u8);
-                        code.data.push(((pop_count >> 16) & 0xff) as u8);
-                        code.data.push(((pop_count >> 24) & 0xff) as u8);
+
+                    // mov rdx, rsp
+                    generate_mov(
+                        code,
+                        Register::Rdx,
+                        Register::Rsp,
+                        &mut stack_allocated_local_count,
+                    );
+
+                    // call call_unknown_arity
+                    let call_unknown_arity = code.intern("call_unknown_arity");
+                    generate_call(code, call_unknown_arity, 0, far_calls);
+                }
+
+                // Pop arguments passed into the function and arguments saved
+                let mut pop_count = ssa.args.len() - 1;
+                if known_arity {
+                    if pop_count > ARG_REGISTER_COUNT {
+                        pop_count -= ARG_REGISTER_COUNT;
+                    } else {
+                        pop_count = 0;
+                    }
+                }
+                pop_count *= 8;
+                if pop_count != 0 {
+                    // add rsp, pop_count
+                    code.emit(&[0x48, 0x81, 0xc4]);
+                    code.emit_u32(pop_count as u32);
+                }
+
+                // Pop original arguments
+                for i in (0..func.argc).rev() {
+                    let reg = Register::convert_arg_register_id(i).convert_to_instr_arg();
+                    if !reg.is_register() {
+                        continue;
                     }
 
-                    // Pop original arguments
-                    for i in (0..func.argc).rev() {
+                    if reg.is_64_bit() != 0 {
+                        code.data.push(0x41);
+                    }
+
+                    code.data.push(0x58 | reg.get_register());
+                }
+
+                if register_lifetimes[Register::R11.revert_to_nonarg_register_id()] != 0 {
+                    // pop r11
+                    code.emit(&[0x41, 0x5b]);
+                }
+
+                if let Some(local) = ssa.local {
+                    // mov local, rax
+                    let local_reg = Register::convert_nonarg_register_id(local);
+                    generate_mov(
+                        code,
+                        local_reg,
+                        Register::Rax,
+                        &mut stack_allocated_local_count,
+                    );
+                }
+            }
+
+            IrInstruction::RcInc => {
+                let mut register = Register::Rax;
+                match ssa.args.first().unwrap() {
+                    IrArgument::Local(local) => {
+                        register = *local_to_register.get(local).unwrap();
+                    }
+
+                    IrArgument::Argument(arg) => {
+                        register = Register::convert_arg_register_id(*arg);
+                    }
+
+                    IrArgument::Function(_) => (),
+                }
+
+                if !matches!(register, Register::Rax) {
+                    // mov rax, register
+                    generate_mov(
+                        code,
+                        Register::Rax,
+                        register,
+                        &mut stack_allocated_local_count,
+                    );
+
+                    // test al, 0x1
+                    code.emit(&[0xa8, 0x01]);
+
+                    // jne rip+4
+                    code.emit(&[0x75, 0x04]);
+
+                    // add dword ptr [rax - 8], 0x1
+                    code.emit(&[0x83, 0x40, 0xf8, 0x01]);
+                }
+            }
+
+            IrInstruction::RcFuncFree => {
+                if !matches!(ssa.args.first().unwrap(), IrArgument::Function(_)) {
+                    // Push arguments
+                    for i in 0..func.argc {
                         let reg = Register::convert_arg_register_id(i).convert_to_instr_arg();
                         if !reg.is_register() {
-                            continue;
+                            break;
                         }
 
                         if reg.is_64_bit() != 0 {
                             code.data.push(0x41);
                         }
 
-                        code.data.push(0x58 | reg.get_register());
-                    }
-
-                    if register_lifetimes[Register::R11.revert_to_nonarg_register_id()] != 0 {
-                        // pop r11
-                        code.data.push(0x41);
-                        code.data.push(0x5b);
-                    }
-
-                    if let Some(local) = ssa.local {
-                        // mov local, rax
-                        let local_reg = Register::convert_nonarg_register_id(local);
-                        generate_mov(
-                            &mut code,
-                            local_reg,
-                            Register::Rax,
-                            &mut stack_allocated_local_count,
-                        );
+                        code.data.push(0x50 | reg.get_register());
                     }
-                }
 
-                IrInstruction::RcInc => {
-                    let mut register = Register::Rax;
+                    let register;
                     match ssa.args.first().unwrap() {
                         IrArgument::Local(local) => {
                             register = *local_to_register.get(local).unwrap();
@@ -891,121 +948,283 @@ pub fn generate_code(module: &mut IrModule) -> GeneratedCode {
                             register = Register::convert_arg_register_id(*arg);
                         }
 
-                        IrArgument::Function(_) => (),
+                        &IrArgument::Function(_) => unreachable!(),
                     }
 
-                    if !matches!(register, Register::Rax) {
-                        // mov rax, register
-                        generate_mov(
-                            &mut code,
-                            Register::Rax,
-                            register,
-                            &mut stack_allocated_local_count,
-                        );
+                    // mov rdi, register
+                    generate_mov(
+                        code,
+                        Register::Rdi,
+                        register,
+                        &mut stack_allocated_local_count,
+                    );
 
-                        // test al, 0x1
-                        code.data.push(0xa8);
-                        code.data.push(0x01);
+                    // call rcfuncfree
+                    let rcfuncfree = code.intern("rcfuncfree");
+                    generate_call(code, rcfuncfree, 0, far_calls);
 
-                        // jne rip+4
-                        code.data.push(0x75);
-                        code.data.push(0x04);
+                    // Pop arguments
+                    for i in (0..func.argc).rev() {
+                        let reg = Register::convert_arg_register_id(i).convert_to_instr_arg();
+                        if !reg.is_register() {
+                            continue;
+                        }
 
-                        // add dword ptr [rax - 8], 0x1
-                        code.data.push(0x83);
-                        code.data.push(0x40);
-                        code.data.push(0xf8);
-                        code.data.push(0x01);
+                        if reg.is_64_bit() != 0 {
+                            code.data.push(0x41);
+                        }
+
+                        code.data.push(0x50 | reg.get_register());
                     }
                 }
+            }
+        }
+    }
+    code.func_addrs[id].end = code.len();
+}
 
-                IrInstruction::RcFuncFree => {
-                    if !matches!(ssa.args.first().unwrap(), IrArgument::Function(_)) {
-                        // Push arguments
-                        for i in 0..func.argc {
-                            let reg = Register::convert_arg_register_id(i).convert_to_instr_arg();
-                            if !reg.is_register() {
-                                break;
-                            }
+// Appends the code generated for a part of a module, fixing up function addresses and relocations.
+// Function ids below `func_count` refer to functions in the module and are kept as is.
+fn append_code(code: &mut GeneratedCode, part: GeneratedCode, func_count: usize) {
+    // Add padding
+    while code.data.len() % 16 != 0 {
+        code.data.push(0);
+    }
+    let base = code.data.len();
+
+    let ids: Vec<_> = part
+        .func_names
+        .iter()
+        .enumerate()
+        .map(|(id, name)| {
+            if id < func_count {
+                id
+            } else {
+                code.intern(name)
+            }
+        })
+        .collect();
 
-                            if reg.is_64_bit() != 0 {
-                                code.data.push(0x41);
-                            }
+    for (id, range) in part.func_addrs.iter().enumerate().take(func_count) {
+        if range.end != 0 {
+            code.func_addrs[id] = range.start + base..range.end + base;
+        }
+    }
 
-                            code.data.push(0x50 | reg.get_register());
-                        }
+    for (addr, id) in part.func_refs.into_iter() {
+        code.func_refs.insert(addr + base, ids[id]);
+    }
 
-                        let register;
-                        match ssa.args.first().unwrap() {
-                            IrArgument::Local(local) => {
-                                register = *local_to_register.get(local).unwrap();
-                            }
+    for (addr, id) in part.far_refs.into_iter() {
+        code.far_refs.insert(addr + base, ids[id]);
+    }
 
-                            IrArgument::Argument(arg) => {
-                                register = Register::convert_arg_register_id(*arg);
-                            }
+    code.emit(&part.data);
+}
 
-                            &IrArgument::Function(_) => unreachable!(),
-                        }
+/// Transforms an IrModule into x86 machine code. Functions are generated in parallel and then
+/// concatenated in module order, so the layout of the generated code only depends on the order of
+/// the functions in the module, which `convert_frontend_ir_to_backend_ir` sorts by name.
+pub fn generate_code(module: &mut IrModule) -> GeneratedCode {
+    generate_code_with_far_calls(module, false)
+}
 
-                        // mov rdi, register
-                        generate_mov(
-                            &mut code,
-                            Register::Rdi,
-                            register,
-                            &mut stack_allocated_local_count,
-                        );
+/// Transforms an IrModule into x86 machine code. If `far_calls` is true, all calls are generated
+/// as absolute calls through a register, which is used as a fallback when a relative call cannot
+/// reach its target.
+pub fn generate_code_with_far_calls(module: &mut IrModule, far_calls: bool) -> GeneratedCode {
+    generate_code_excluding(module, far_calls, &HashSet::new())
+}
 
-                        // call rcfuncfree
-                        code.data.push(0xe8);
-                        code.func_refs
-                            .insert(code.data.len(), String::from("rcfuncfree"));
-                        if !code.func_addrs.contains_key("rcfuncfree") {
-                            code.func_addrs.insert(String::from("rcfuncfree"), 0..0);
+/// Transforms an IrModule into x86 machine code, skipping the functions named in `exclude`. The
+/// skipped functions are still interned, so references to them are left for `relocate_with_symbols`
+/// to resolve against code that was loaded earlier.
+pub fn generate_code_excluding(
+    module: &mut IrModule,
+    far_calls: bool,
+    exclude: &HashSet<String>,
+) -> GeneratedCode {
+    let mut code =
+        GeneratedCode::with_capacity(module.funcs.iter().map(estimate_function_size).sum());
+
+    // Intern functions so that their ids are their indices in the module
+    for func in module.funcs.iter() {
+        code.intern(&func.name);
+    }
+
+    let func_count = module.funcs.len();
+    let argcs: Vec<_> = module.funcs.iter().map(|v| v.argc).collect();
+    let thread_count = if func_count < PARALLEL_FUNCTION_THRESHOLD {
+        1
+    } else {
+        std::thread::available_parallelism().map_or(1, |v| v.get())
+    };
+    let chunk_size = func_count.div_ceil(thread_count).max(1);
+
+    let parts: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = module
+            .funcs
+            .chunks_mut(chunk_size)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let argcs = &argcs;
+                scope.spawn(move || {
+                    let mut part = GeneratedCode::with_reserved_ids(func_count);
+                    for (j, func) in chunk.iter_mut().enumerate() {
+                        if exclude.contains(&func.name) {
+                            continue;
                         }
-                        code.data.push(0x00);
-                        code.data.push(0x00);
-                        code.data.push(0x00);
-                        code.data.push(0x00);
 
-                        // Pop arguments
-                        for i in (0..func.argc).rev() {
-                            let reg = Register::convert_arg_register_id(i).convert_to_instr_arg();
-                            if !reg.is_register() {
-                                continue;
-                            }
+                        backends::linear_scan(func, NONARG_REGISTER_COUNT);
+                        generate_function(&mut part, i * chunk_size + j, func, argcs, far_calls);
+                    }
+                    part
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|v| v.join().expect("code generation thread panicked"))
+            .collect()
+    });
+
+    for part in parts {
+        append_code(&mut code, part, func_count);
+    }
 
-                            if reg.is_64_bit() != 0 {
-                                code.data.push(0x41);
-                            }
+    code
+}
 
-                            code.data.push(0x50 | reg.get_register());
-                        }
+/// Relocates all function addresses to their offset. Absolute addresses used by far calls are
+/// calculated relative to `base`, the address the code will be loaded at. References to functions
+/// the code does not define, such as the runtime, are left pointing at their offset, so the result
+/// is only fit for listing. Returns an error if a relative address does not fit in 32 bits.
+pub fn relocate(code: &mut GeneratedCode, base: *const u8) -> Result<(), RelocationError> {
+    relocate_references(code, base, None)
+}
+
+/// Relocates all function addresses like `relocate`. References to functions that the code does
+/// not define are resolved against `symbols`, which maps names to the absolute addresses of
+/// functions already in memory, such as code loaded earlier by a JIT. Existing code is never
+/// touched. Returns an error if a reference cannot be resolved.
+pub fn relocate_with_symbols(
+    code: &mut GeneratedCode,
+    base: *const u8,
+    symbols: &HashMap<String, u64>,
+) -> Result<(), RelocationError> {
+    relocate_references(code, base, Some(symbols))
+}
+
+// relocate_references(&mut GeneratedCode, *const u8, Option<&HashMap<String, u64>>) -> Result<(), RelocationError>
+// Relocates all function addresses. Without symbols, references to functions the code does not
+// define are left pointing at their offset; with symbols, they must be resolved against them.
+fn relocate_references(
+    code: &mut GeneratedCode,
+    base: *const u8,
+    symbols: Option<&HashMap<String, u64>>,
+) -> Result<(), RelocationError> {
+    for (code_addr, func) in code.func_refs.iter() {
+        let target = match code.func_addrs.get(*func) {
+            Some(range) if range.end == 0 => match symbols {
+                Some(symbols) => match symbols.get(&code.func_names[*func]) {
+                    Some(addr) => (*addr as i64).wrapping_sub(base as i64),
+                    None => {
+                        return Err(RelocationError::UnresolvedSymbol {
+                            func: code.func_names[*func].clone(),
+                            at: *code_addr,
+                        })
                     }
-                }
+                },
+                None => range.start as i64,
+            },
+            Some(range) => range.start as i64,
+            None => continue,
+        };
+
+        let mut addend = [0; 4];
+        addend.copy_from_slice(&code.data[*code_addr..*code_addr + 4]);
+        let displacement = target - *code_addr as i64 + i32::from_le_bytes(addend) as i64 - 4;
+
+        let displacement = match i32::try_from(displacement) {
+            Ok(v) => v,
+            Err(_) => {
+                return Err(RelocationError::DisplacementOverflow {
+                    func: code.func_names[*func].clone(),
+                    at: *code_addr,
+                    displacement,
+                })
             }
-        }
-        code.func_addrs.get_mut(&func.name).unwrap().end = code.len();
+        };
+
+        code.data[*code_addr..*code_addr + 4].copy_from_slice(&displacement.to_le_bytes());
     }
 
-    code
+    for (code_addr, func) in code.far_refs.iter() {
+        let target = match code.func_addrs.get(*func) {
+            Some(range) if range.end == 0 => match symbols {
+                Some(symbols) => match symbols.get(&code.func_names[*func]) {
+                    Some(addr) => *addr,
+                    None => {
+                        return Err(RelocationError::UnresolvedSymbol {
+                            func: code.func_names[*func].clone(),
+                            at: *code_addr,
+                        })
+                    }
+                },
+                None => (base as u64).wrapping_add(range.start as u64),
+            },
+            Some(range) => (base as u64).wrapping_add(range.start as u64),
+            None => continue,
+        };
+
+        let mut addend = [0; 8];
+        addend.copy_from_slice(&code.data[*code_addr..*code_addr + 8]);
+        let addr = target.wrapping_add(u64::from_le_bytes(addend));
+
+        code.data[*code_addr..*code_addr + 8].copy_from_slice(&addr.to_le_bytes());
+    }
+
+    Ok(())
 }
 
-/// Relocates all function addresses to their offset.
-pub fn relocate(code: &mut GeneratedCode) {
-    for (code_addr, func) in code.func_refs.iter() {
-        if let Some(range) = code.func_addrs.get(func) {
-            let addr = ((range.start as i32 - *code_addr as i32) as i64
-                + unsafe { *(code.data.as_ptr().add(*code_addr) as *const i32) } as i64
-                - 4) as u64;
-
-            for (i, byte) in code.data.iter_mut().skip(*code_addr).enumerate() {
-                if i >= 4 {
-                    break;
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                *byte = ((addr >> (i * 8)) & 0xff) as u8;
+    // Generates code that calls `rccopy` without defining it.
+    fn calls_external(far_calls: bool) -> GeneratedCode {
+        let mut code = GeneratedCode::new();
+        let func = code.intern("rccopy");
+        generate_call(&mut code, func, 0, far_calls);
+        code
+    }
+
+    #[test]
+    fn unresolved_symbols_are_errors() {
+        for far_calls in [false, true] {
+            let mut code = calls_external(far_calls);
+            let base = code.data().as_ptr();
+            match relocate_with_symbols(&mut code, base, &HashMap::new()) {
+                Err(RelocationError::UnresolvedSymbol { func, .. }) => assert_eq!(func, "rccopy"),
+                v => panic!("expected an unresolved symbol, got {:?}", v),
             }
         }
     }
+
+    #[test]
+    fn symbols_are_resolved() {
+        let mut code = calls_external(true);
+        let symbols = HashMap::from([(String::from("rccopy"), 0x1234_5678_9abc)]);
+        relocate_with_symbols(&mut code, std::ptr::null(), &symbols).unwrap();
+        assert_eq!(code.data()[2..10], 0x1234_5678_9abcu64.to_le_bytes());
+    }
+
+    #[test]
+    fn listings_leave_unresolved_symbols() {
+        for far_calls in [false, true] {
+            let mut code = calls_external(far_calls);
+            assert!(relocate(&mut code, std::ptr::null()).is_ok());
+        }
+    }
 }
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+
+use crate::frontend::interpreter::{EvalError, Interpreter, Reduction, Value};
+use crate::frontend::ir::{IrModule, Location};
+
+/// The header written at the top of every coverage file.
+const COVERAGE_HEADER: &str = "# closey coverage v1";
+
+/// The number of times each function was entered, keyed by the location of the function.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    counts: HashMap<Location, usize>,
+}
+
+impl Display for Coverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", COVERAGE_HEADER)?;
+
+        let mut counts: Vec<_> = self.counts.iter().collect();
+        counts.sort_by(|a, b| {
+            (&a.0.filename, a.0.span.start, a.0.span.end).cmp(&(
+                &b.0.filename,
+                b.0.span.start,
+                b.0.span.end,
+            ))
+        });
+
+        for (loc, count) in counts {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}",
+                loc.filename, loc.span.start, loc.span.end, count
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Coverage {
+    /// Runs the main function of the first module in the interpreter, counting how many times each
+    /// function is entered. Functions of any of the modules that are never entered are recorded
+    /// with a count of 0.
+    pub fn collect(modules: &[&IrModule]) -> (Result<Value, EvalError>, Coverage) {
+        let mut counts: HashMap<Location, usize> = modules
+            .iter()
+            .flat_map(|m| m.funcs.values())
+            .map(|v| (v.loc.clone(), 0))
+            .collect();
+
+        let result = Interpreter::with_hook(modules[0], |reduction: &Reduction| {
+            *counts.entry(reduction.loc.clone()).or_insert(0) += 1;
+            true
+        })
+        .evaluate("main");
+
+        (result, Coverage { counts })
+    }
+
+    /// Parses a coverage file written by the `Display` implementation.
+    pub fn parse(s: &str) -> Result<Coverage, String> {
+        let mut lines = s.lines();
+        if lines.next() != Some(COVERAGE_HEADER) {
+            return Err(String::from("not a coverage file"));
+        }
+
+        let mut counts: HashMap<Location, usize> = HashMap::new();
+        for (i, line) in lines.enumerate() {
+            let fields: Vec<_> = line.rsplitn(4, '\t').collect();
+            let parsed = match fields.as_slice() {
+                [count, end, start, filename] => {
+                    match (start.parse(), end.parse(), count.parse()) {
+                        (Ok(start), Ok(end), Ok(count)) => {
+                            Some((Location::new(start..end, filename), count))
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+
+            match parsed {
+                Some((loc, count)) => {
+                    counts.insert(loc, count);
+                }
+                None => return Err(format!("invalid entry on line {}", i + 2)),
+            }
+        }
+
+        Ok(Coverage { counts })
+    }
+
+    /// Gets the names of the files covered.
+    pub fn files(&self) -> Vec<&str> {
+        let mut files: Vec<_> = self.counts.keys().map(|v| v.filename.as_str()).collect();
+        files.sort_unstable();
+        files.dedup();
+        files
+    }
+
+    /// Renders per line coverage of a file. Each line is prefixed with the number of times the
+    /// innermost function containing it was entered, or `#####` if it was never entered. Lines
+    /// outside any function have no count.
+    pub fn report(&self, filename: &str, contents: &str) -> String {
+        let mut report = String::new();
+        let mut start = 0;
+
+        for line in contents.split_inclusive('\n') {
+            let end = start + line.len();
+            let count = self
+                .counts
+                .iter()
+                .filter(|(loc, _)| {
+                    loc.filename == filename && loc.span.start < end && start < loc.span.end
+                })
+                .min_by_key(|(loc, _)| loc.span.end - loc.span.start)
+                .map(|v| *v.1);
+
+            let count = match count {
+                Some(0) => String::from("#####"),
+                Some(v) => v.to_string(),
+                None => String::from("-"),
+            };
+            writeln!(report, "{:>9} | {}", count, line.trim_end_matches('\n')).unwrap();
+
+            start = end;
+        }
+
+        report
+    }
+}
@@ -1,17 +1,33 @@
+/// Module for evaluating constant expressions at compile time. This module contains a small
+/// evaluator for integer and boolean expressions, for use wherever a value must be known before
+/// the program runs.
+pub mod consteval;
+
 /// Module for correctness checking. This module contains all the functions that are involved in,
 /// for example, determining arity and type checking.
 pub mod correctness;
 
+/// Module for interpreting the frontend intermediate representation. This module contains an
+/// interpreter that can report each reduction as it happens, which is used for step debugging.
+pub mod interpreter;
+
 /// Module for the frontend intermediate representation. This module contains functions for
 /// generating the IR and handling it.
 pub mod ir;
 
+/// Module for macros. This module contains functions for expanding macros after parsing.
+pub mod macros;
+
 /// Module for parsing the source text.
 pub mod parser;
 
 /// Module for scopes. This module contains functions for manipulating scopes and variables.
 pub mod scopes;
 
+/// Module for uncurrying. This module contains a pass that gives curried functions an entry that
+/// takes all of their arguments at once.
+pub mod uncurry;
+
 /// Module for types. This module contains functions to help with type checking and manipulating
 /// types.
 pub mod types;
@@ -0,0 +1,404 @@
+use logos::Span;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+// Represents a value computed at compile time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstValue {
+    Int(i64),
+    Bool(bool),
+}
+
+impl Display for ConstValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConstValue::Int(v) => write!(f, "{}", v),
+            ConstValue::Bool(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl ConstValue {
+    // type_name(&self) -> &str
+    // Gets the name of the type of the value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ConstValue::Int(_) => "int",
+            ConstValue::Bool(_) => "bool",
+        }
+    }
+}
+
+// Represents an error encountered while evaluating a constant expression. Spans are relative to
+// the start of the expression.
+#[derive(Debug)]
+pub enum ConstError {
+    UnexpectedChar(Span, char),
+    UnexpectedEnd(Span),
+    Expected(Span, &'static str),
+    UnknownName(Span, String),
+    TypeMismatch(Span, String, ConstValue),
+    DivisionByZero(Span),
+    Overflow(Span),
+}
+
+impl Display for ConstError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConstError::UnexpectedChar(_, c) => write!(f, "unexpected character `{}`", c),
+            ConstError::UnexpectedEnd(_) => write!(f, "unexpected end of expression"),
+            ConstError::Expected(_, v) => write!(f, "expected {}", v),
+            ConstError::UnknownName(_, v) => write!(f, "`{}` is not a known constant", v),
+            ConstError::TypeMismatch(_, op, v) => write!(
+                f,
+                "`{}` cannot be applied to `{}` of type {}",
+                op,
+                v,
+                v.type_name()
+            ),
+            ConstError::DivisionByZero(_) => write!(f, "division by zero"),
+            ConstError::Overflow(_) => write!(f, "integer overflow"),
+        }
+    }
+}
+
+impl ConstError {
+    // span(&self) -> Span
+    // Gets the span of the expression the error was found in.
+    pub fn span(&self) -> Span {
+        match self {
+            ConstError::UnexpectedChar(s, _)
+            | ConstError::UnexpectedEnd(s)
+            | ConstError::Expected(s, _)
+            | ConstError::UnknownName(s, _)
+            | ConstError::TypeMismatch(s, _, _)
+            | ConstError::DivisionByZero(s)
+            | ConstError::Overflow(s) => s.clone(),
+        }
+    }
+}
+
+// The operators understood by the evaluator. Longer operators come first so that they are lexed
+// before their prefixes.
+const OPERATORS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "!", "(", ")",
+];
+
+// Represents a token in a constant expression.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+// evaluate(&str, &HashMap<String, ConstValue>) -> Result<ConstValue, ConstError>
+// Evaluates a constant expression. Expressions are made of integers, `true`, `false`, names of
+// constants in the environment, arithmetic (`+ - * / %`), comparisons (`== != < <= > >=`), and
+// logic (`&& || !`), with the usual precedence.
+pub fn evaluate(s: &str, env: &HashMap<String, ConstValue>) -> Result<ConstValue, ConstError> {
+    let mut evaluator = Evaluator {
+        tokens: lex(s)?,
+        pos: 0,
+        end: s.len(),
+        env,
+    };
+
+    let value = evaluator.or()?;
+    match evaluator.tokens.get(evaluator.pos) {
+        Some((_, span)) => Err(ConstError::Expected(span.clone(), "end of expression")),
+        None => Ok(value),
+    }
+}
+
+// lex(&str) -> Result<Vec<(Token, Span)>, ConstError>
+// Splits a constant expression into tokens.
+fn lex(s: &str) -> Result<Vec<(Token, Span)>, ConstError> {
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while let Some(c) = s[i..].chars().next() {
+        let rest = &s[i..];
+        let len = if c.is_whitespace() {
+            c.len_utf8()
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            match rest[..len].parse() {
+                Ok(v) => tokens.push((Token::Int(v), i..i + len)),
+                Err(_) => return Err(ConstError::Overflow(i..i + len)),
+            }
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push((Token::Name(String::from(&rest[..len])), i..i + len));
+            len
+        } else if let Some(op) = OPERATORS.iter().copied().find(|v| rest.starts_with(v)) {
+            tokens.push((Token::Op(op), i..i + op.len()));
+            op.len()
+        } else {
+            return Err(ConstError::UnexpectedChar(i..i + c.len_utf8(), c));
+        };
+
+        i += len;
+    }
+
+    Ok(tokens)
+}
+
+// Evaluates a constant expression while parsing it.
+struct Evaluator<'a> {
+    tokens: Vec<(Token, Span)>,
+    pos: usize,
+    end: usize,
+    env: &'a HashMap<String, ConstValue>,
+}
+
+impl<'a> Evaluator<'a> {
+    // next_op(&mut self, &[&str]) -> Option<(&str, Span)>
+    // Consumes the next token if it is one of the given operators.
+    fn next_op(&mut self, ops: &[&'static str]) -> Option<(&'static str, Span)> {
+        match self.tokens.get(self.pos) {
+            Some((Token::Op(op), span)) if ops.contains(op) => {
+                let result = Some((*op, span.clone()));
+                self.pos += 1;
+                result
+            }
+
+            _ => None,
+        }
+    }
+
+    // or(&mut self) -> Result<ConstValue, ConstError>
+    // Evaluates a chain of `||`.
+    fn or(&mut self) -> Result<ConstValue, ConstError> {
+        let mut left = self.and()?;
+        while let Some((op, span)) = self.next_op(&["||"]) {
+            let right = self.and()?;
+            left = ConstValue::Bool(as_bool(left, op, &span)? || as_bool(right, op, &span)?);
+        }
+        Ok(left)
+    }
+
+    // and(&mut self) -> Result<ConstValue, ConstError>
+    // Evaluates a chain of `&&`.
+    fn and(&mut self) -> Result<ConstValue, ConstError> {
+        let mut left = self.comparison()?;
+        while let Some((op, span)) = self.next_op(&["&&"]) {
+            let right = self.comparison()?;
+            left = ConstValue::Bool(as_bool(left, op, &span)? && as_bool(right, op, &span)?);
+        }
+        Ok(left)
+    }
+
+    // comparison(&mut self) -> Result<ConstValue, ConstError>
+    // Evaluates a comparison. Comparisons do not chain.
+    fn comparison(&mut self) -> Result<ConstValue, ConstError> {
+        let left = self.additive()?;
+        let (op, span) = match self.next_op(&["==", "!=", "<=", ">=", "<", ">"]) {
+            Some(v) => v,
+            None => return Ok(left),
+        };
+        let right = self.additive()?;
+
+        let result = match (op, left, right) {
+            ("==", l, r) if l.type_name() == r.type_name() => l == r,
+            ("!=", l, r) if l.type_name() == r.type_name() => l != r,
+            (_, ConstValue::Int(l), ConstValue::Int(r)) => match op {
+                "<=" => l <= r,
+                ">=" => l >= r,
+                "<" => l < r,
+                _ => l > r,
+            },
+            (_, ConstValue::Int(_), v) | (_, v, _) => {
+                return Err(ConstError::TypeMismatch(span, String::from(op), v))
+            }
+        };
+
+        Ok(ConstValue::Bool(result))
+    }
+
+    // additive(&mut self) -> Result<ConstValue, ConstError>
+    // Evaluates a chain of `+` and `-`.
+    fn additive(&mut self) -> Result<ConstValue, ConstError> {
+        let mut left = self.multiplicative()?;
+        while let Some((op, span)) = self.next_op(&["+", "-"]) {
+            let right = self.multiplicative()?;
+            let (l, r) = (as_int(left, op, &span)?, as_int(right, op, &span)?);
+            let result = if op == "+" {
+                l.checked_add(r)
+            } else {
+                l.checked_sub(r)
+            };
+            left = ConstValue::Int(result.ok_or(ConstError::Overflow(span))?);
+        }
+        Ok(left)
+    }
+
+    // multiplicative(&mut self) -> Result<ConstValue, ConstError>
+    // Evaluates a chain of `*`, `/`, and `%`.
+    fn multiplicative(&mut self) -> Result<ConstValue, ConstError> {
+        let mut left = self.unary()?;
+        while let Some((op, span)) = self.next_op(&["*", "/", "%"]) {
+            let right = self.unary()?;
+            let (l, r) = (as_int(left, op, &span)?, as_int(right, op, &span)?);
+            if op != "*" && r == 0 {
+                return Err(ConstError::DivisionByZero(span));
+            }
+
+            let result = match op {
+                "*" => l.checked_mul(r),
+                "/" => l.checked_div(r),
+                _ => l.checked_rem(r),
+            };
+            left = ConstValue::Int(result.ok_or(ConstError::Overflow(span))?);
+        }
+        Ok(left)
+    }
+
+    // unary(&mut self) -> Result<ConstValue, ConstError>
+    // Evaluates a negation or logical not.
+    fn unary(&mut self) -> Result<ConstValue, ConstError> {
+        match self.next_op(&["-", "!"]) {
+            Some(("-", span)) => {
+                let v = as_int(self.unary()?, "-", &span)?;
+                Ok(ConstValue::Int(
+                    v.checked_neg().ok_or(ConstError::Overflow(span))?,
+                ))
+            }
+
+            Some((op, span)) => Ok(ConstValue::Bool(!as_bool(self.unary()?, op, &span)?)),
+
+            None => self.atom(),
+        }
+    }
+
+    // atom(&mut self) -> Result<ConstValue, ConstError>
+    // Evaluates an integer, boolean, constant, or parenthesised expression.
+    fn atom(&mut self) -> Result<ConstValue, ConstError> {
+        let (token, span) = match self.tokens.get(self.pos) {
+            Some(v) => v.clone(),
+            None => return Err(ConstError::UnexpectedEnd(self.end..self.end)),
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Int(v) => Ok(ConstValue::Int(v)),
+            Token::Name(v) if v == "true" => Ok(ConstValue::Bool(true)),
+            Token::Name(v) if v == "false" => Ok(ConstValue::Bool(false)),
+            Token::Name(v) => match self.env.get(&v) {
+                Some(v) => Ok(*v),
+                None => Err(ConstError::UnknownName(span, v)),
+            },
+
+            Token::Op("(") => {
+                let value = self.or()?;
+                match self.next_op(&[")"]) {
+                    Some(_) => Ok(value),
+                    None => Err(ConstError::Expected(span, "`)` to close this `(`")),
+                }
+            }
+
+            Token::Op(_) => Err(ConstError::Expected(span, "a value")),
+        }
+    }
+}
+
+// as_int(ConstValue, &str, &Span) -> Result<i64, ConstError>
+// Gets an integer operand of an operator.
+fn as_int(value: ConstValue, op: &str, span: &Span) -> Result<i64, ConstError> {
+    match value {
+        ConstValue::Int(v) => Ok(v),
+        v => Err(ConstError::TypeMismatch(span.clone(), String::from(op), v)),
+    }
+}
+
+// as_bool(ConstValue, &str, &Span) -> Result<bool, ConstError>
+// Gets a boolean operand of an operator.
+fn as_bool(value: ConstValue, op: &str, span: &Span) -> Result<bool, ConstError> {
+    match value {
+        ConstValue::Bool(v) => Ok(v),
+        v => Err(ConstError::TypeMismatch(span.clone(), String::from(op), v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(s: &str) -> Result<ConstValue, ConstError> {
+        let env = [(String::from("x"), ConstValue::Int(21))]
+            .into_iter()
+            .collect();
+        evaluate(s, &env)
+    }
+
+    #[test]
+    fn operators_have_the_usual_precedence() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), ConstValue::Int(7));
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), ConstValue::Int(9));
+        assert_eq!(eval("x * 2 - 1").unwrap(), ConstValue::Int(41));
+        assert_eq!(eval("1 < 2 && !false").unwrap(), ConstValue::Bool(true));
+        assert_eq!(eval("false || x % 2 == 1").unwrap(), ConstValue::Bool(true));
+    }
+
+    #[test]
+    fn malformed_expressions_are_reported_where_they_go_wrong() {
+        assert!(matches!(eval("1 $ 2"), Err(ConstError::UnexpectedChar(s, '$')) if s == (2..3)));
+        assert!(matches!(eval("1 +"), Err(ConstError::UnexpectedEnd(s)) if s == (3..3)));
+        assert!(matches!(eval("* 2"), Err(ConstError::Expected(s, "a value")) if s == (0..1)));
+        assert!(matches!(eval("(1 + 2"), Err(ConstError::Expected(s, _)) if s == (0..1)));
+        assert!(matches!(
+            eval("2 == 2 == true"),
+            Err(ConstError::Expected(s, "end of expression")) if s == (7..9)
+        ));
+    }
+
+    #[test]
+    fn unknown_names_are_reported() {
+        assert!(
+            matches!(eval("y + 1"), Err(ConstError::UnknownName(s, v)) if s == (0..1) && v == "y")
+        );
+    }
+
+    #[test]
+    fn operands_of_the_wrong_type_are_reported() {
+        let e = eval("true + 1").unwrap_err();
+        assert!(
+            matches!(&e, ConstError::TypeMismatch(s, op, ConstValue::Bool(true)) if *s == (5..6) && op == "+")
+        );
+        assert_eq!(
+            e.to_string(),
+            "`+` cannot be applied to `true` of type bool"
+        );
+
+        assert!(matches!(
+            eval("1 < true"),
+            Err(ConstError::TypeMismatch(_, op, ConstValue::Bool(true))) if op == "<"
+        ));
+        assert!(matches!(
+            eval("!1"),
+            Err(ConstError::TypeMismatch(_, op, ConstValue::Int(1))) if op == "!"
+        ));
+    }
+
+    #[test]
+    fn arithmetic_errors_are_reported() {
+        assert!(matches!(eval("1 / 0"), Err(ConstError::DivisionByZero(s)) if s == (2..3)));
+        assert!(matches!(
+            eval("1 % (x - 21)"),
+            Err(ConstError::DivisionByZero(_))
+        ));
+        assert!(matches!(
+            eval("9223372036854775807 + 1"),
+            Err(ConstError::Overflow(s)) if s == (20..21)
+        ));
+        assert!(matches!(
+            eval("99999999999999999999"),
+            Err(ConstError::Overflow(s)) if s == (0..20)
+        ));
+    }
+}
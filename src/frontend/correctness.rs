@@ -1,17 +1,231 @@
 use logos::Span;
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::sync::Arc;
 
 use super::ir::{ArityInfo, Ir, IrFunction, IrModule, Location, SExpr, SExprMetadata};
-use super::types::{arc, Type};
+use super::types::{arc, Conflict, Type, TypeRc};
+use super::uncurry;
 
-pub enum CorrectnessError {}
+// Represents an error found while checking correctness.
+pub enum CorrectnessError {
+    // The expression at the first location has a type that is not a subtype of the type ascribed
+    // to it at the second location
+    AscriptionMismatch(Location, Location, TypeRc, TypeRc),
+
+    // The definition at the first location has a type that is not a subtype of the type given by
+    // its signature at the second location
+    SignatureMismatch(Location, Location, TypeRc, TypeRc),
+
+    // The value destructured at the location has a type that is not a tuple with the given number
+    // of elements
+    DestructureMismatch(Location, TypeRc, usize),
+
+    // The value at the location has a type without the given field
+    NoSuchField(Location, TypeRc, String),
+
+    // The argument at the first location does not match the type of the argument expected by the
+    // function at the second location; the conflict is between the smallest mismatched parts
+    ArgumentMismatch(Location, Location, TypeRc, TypeRc, Conflict),
+
+    // The value at the location is applied to arguments but is not a function
+    NotAFunction(Location, TypeRc),
+
+    // The argument at the first location is one more than the function declared at the second
+    // location takes; the function has the given type and takes the first number of arguments but
+    // is given the second
+    TooManyArguments(Location, Location, TypeRc, usize, usize),
+
+    // The function with the given name is used at the location while its own type is still being
+    // derived, and it has no signature to give its type
+    RecursionWithoutSignature(Location, String),
+
+    // The name used at the location is not in scope; the second name is the closest name that is,
+    // if any is close enough to be a likely typo
+    SymbolNotFound(Location, String, Option<String>),
+}
+
+// Represents a single step in the derivation of a type.
+#[derive(Debug, Clone)]
+pub struct DerivationStep {
+    pub depth: usize,
+    pub description: String,
+}
+
+// Represents the derivation of the type of a top level definition.
+#[derive(Debug, Clone)]
+pub struct TypeDerivation {
+    pub module: String,
+    pub name: String,
+    pub _type: TypeRc,
+    pub steps: Vec<DerivationStep>,
+}
+
+impl Display for TypeDerivation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}.{} : {}", self.module, self.name, self._type)?;
+        for (i, step) in self.steps.iter().enumerate() {
+            writeln!(
+                f,
+                "  {:indent$}{}. {}",
+                "",
+                i + 1,
+                step.description,
+                indent = step.depth * 4
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Records derivation steps while checking if enabled.
+#[derive(Default)]
+struct Tracer {
+    enabled: bool,
+    depth: usize,
+    steps: Vec<DerivationStep>,
+}
+
+impl Tracer {
+    // step(&mut self, impl FnOnce() -> String) -> ()
+    // Records a step at the current depth. The description is only built if tracing is enabled.
+    fn step(&mut self, description: impl FnOnce() -> String) {
+        if self.enabled {
+            self.steps.push(DerivationStep {
+                depth: self.depth,
+                description: description(),
+            });
+        }
+    }
+}
+
+// check_signature(&mut IrFunction, &str, &IrModule, &mut Vec<CorrectnessError>, &mut Tracer) -> ()
+// Checks the type of a function against its signature if it has one. The function takes the type
+// of the signature if it matches.
+fn check_signature(
+    func: &mut IrFunction,
+    raw: &str,
+    module: &IrModule,
+    errors: &mut Vec<CorrectnessError>,
+    tracer: &mut Tracer,
+) {
+    if let Some((loc, sig)) = module.signatures.get(raw) {
+        if func
+            ._type
+            .is_subtype(sig, &module.types, &mut HashMap::new())
+        {
+            tracer.step(|| format!("{} matches the signature {}", func._type, sig));
+            func._type = sig.clone();
+        } else {
+            errors.push(CorrectnessError::SignatureMismatch(
+                func.loc.clone(),
+                loc.clone(),
+                func._type.clone(),
+                sig.clone(),
+            ));
+        }
+    }
+}
+
+// check_top_level(&mut IrFunction, &str, &mut IrModule, &mut Vec<CorrectnessError>, &mut Tracer) -> ()
+// Checks the body of a top level function that has been removed from the module and sets its type.
+fn check_top_level(
+    func: &mut IrFunction,
+    raw: &str,
+    module: &mut IrModule,
+    errors: &mut Vec<CorrectnessError>,
+    tracer: &mut Tracer,
+) {
+    use std::mem::swap;
+
+    module.scope.push_scope(true);
+    for arg in func.args.iter() {
+        tracer.step(|| format!("assume argument `{}` has type {}", arg.0, arg.1));
+        module.scope.put_var(
+            &arg.0,
+            &arg.1,
+            ArityInfo::Unknown,
+            &Location::empty(),
+            true,
+            "",
+        );
+    }
+
+    let mut body = SExpr::Empty(SExprMetadata::empty());
+    swap(&mut func.body, &mut body);
+    check_sexpr(func, &mut body, module, errors, tracer);
+    swap(&mut func.body, &mut body);
+
+    let mut _type = func.body.get_metadata()._type.clone();
+    for arg in func.args.iter().rev() {
+        _type = arc::new(Type::Func(arg.1.clone(), _type));
+    }
+
+    func._type = _type;
+
+    check_signature(func, raw, module, errors, tracer);
+
+    module.scope.pop_scope();
+}
+
+// edit_distance(&str, &str) -> usize
+// Gets the number of characters that must be inserted, removed, or replaced to turn one string
+// into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<_> = b.chars().collect();
+    let mut row: Vec<_> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let replaced = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+// closest_name(&str, impl Iterator<Item = &str>) -> Option<&str>
+// Gets the name closest to the given name, if it is within a third of the length of the name.
+// Ties are broken by picking the first name in alphabetical order.
+// TODO: Suggest declared type names too, once misspelled type names are reported instead of being
+// kept as symbol types.
+fn closest_name<'a>(name: &str, names: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max = (name.chars().count() / 3).max(1);
+    names
+        .filter(|v| *v != name)
+        .map(|v| (edit_distance(name, v), v))
+        .filter(|v| v.0 <= max)
+        .min()
+        .map(|v| v.1)
+}
+
+// declaration_loc(&SExpr, &IrModule) -> Location
+// Gets the location of the declaration of the function at the bottom of a chain of applications,
+// or the location of the value itself if it is not a known function.
+fn declaration_loc(sexpr: &SExpr, module: &IrModule) -> Location {
+    match sexpr {
+        SExpr::Application(_, func, _) => declaration_loc(func, module),
+
+        SExpr::Function(m, f) => module
+            .funcs
+            .get(f)
+            .map(|v| v.loc.clone())
+            .unwrap_or_else(|| m.loc.clone()),
+
+        _ => sexpr.get_metadata().loc.clone(),
+    }
+}
 
 fn check_sexpr(
     parent_func: &mut IrFunction,
     sexpr: &mut SExpr,
     module: &mut IrModule,
     errors: &mut Vec<CorrectnessError>,
+    tracer: &mut Tracer,
 ) {
     match sexpr {
         SExpr::Empty(_) => todo!(),
@@ -22,15 +236,28 @@ fn check_sexpr(
             if let Some((_type, arity, _, _, _)) = module.scope.get_var(s) {
                 m._type = _type.clone();
                 m.arity = *arity;
+                tracer.step(|| format!("`{}` is a variable of type {}", s, m._type));
                 if module.scope.is_captured(s) && !parent_func.captured_names.contains(s) {
                     parent_func.captured_names.push(s.clone());
                     parent_func.captured.insert(s.clone(), _type.clone());
                 }
             } else if let Some(func) = module.globals.get(s) {
                 *sexpr = SExpr::Function(m.clone(), func.clone());
-                check_sexpr(parent_func, sexpr, module, errors);
+                check_sexpr(parent_func, sexpr, module, errors, tracer);
             } else {
-                panic!("variable {} not found", s);
+                let names = module
+                    .scope
+                    .names()
+                    .into_iter()
+                    .chain(module.globals.keys().map(|v| v.as_str()));
+                let suggestion = closest_name(s, names).map(String::from);
+                errors.push(CorrectnessError::SymbolNotFound(
+                    m.loc.clone(),
+                    s.clone(),
+                    suggestion,
+                ));
+                m._type = arc::new(Type::Error);
+                m.arity = ArityInfo::Unknown;
             }
         }
 
@@ -39,11 +266,21 @@ fn check_sexpr(
                 if func.checked {
                     m._type = func._type.clone();
                     m.arity = ArityInfo::Known(func.args.len());
+                    tracer.step(|| format!("`{}` is a function of type {}", f, m._type));
+                } else if let Some((_, _type)) = module.signatures.get(f) {
+                    // Functions with signatures are checked on their own, so mutually recursive
+                    // functions only need a signature on one function in every cycle
+                    m._type = _type.clone();
+                    m.arity = ArityInfo::Known(func.args.len());
+                    tracer.step(|| format!("`{}` has type {} by its signature", f, m._type));
                 } else {
                     let mut func = module.funcs.remove(f).unwrap();
                     module.scope.push_scope(true);
 
+                    tracer.step(|| format!("checking function `{}`", f));
+                    tracer.depth += 1;
                     for arg in func.args.iter() {
+                        tracer.step(|| format!("assume argument `{}` has type {}", arg.0, arg.1));
                         module.scope.put_var(
                             &arg.0,
                             &arg.1,
@@ -57,10 +294,11 @@ fn check_sexpr(
                     use std::mem::swap;
                     let mut body = SExpr::Empty(SExprMetadata::empty());
                     swap(&mut func.body, &mut body);
-                    check_sexpr(&mut func, &mut body, module, errors);
+                    check_sexpr(&mut func, &mut body, module, errors, tracer);
                     swap(&mut func.body, &mut body);
 
                     module.scope.pop_scope();
+                    tracer.depth -= 1;
 
                     let mut _type = func.body.get_metadata()._type.clone();
                     for arg in func.args.iter().rev() {
@@ -68,28 +306,81 @@ fn check_sexpr(
                     }
 
                     func._type = _type;
+                    check_signature(&mut func, f, module, errors, tracer);
                     m._type = func._type.clone();
                     m.arity = ArityInfo::Known(func.args.len());
+                    tracer.step(|| format!("so function `{}` has type {}", f, m._type));
 
                     func.checked = true;
                     module.funcs.insert(f.clone(), func);
                 }
+            } else if let Some((_, _type)) = module.signatures.get(f) {
+                // The function is being checked, so it must be recursive; use its signature
+                m._type = _type.clone();
+                m.arity = ArityInfo::Unknown;
+                tracer.step(|| format!("`{}` has type {} by its signature", f, m._type));
             } else {
-                panic!("this shouldn't happen i believe");
+                // Redefined top level functions have their names suffixed with a number
+                let name = match f.rsplit_once('.') {
+                    Some((name, n)) if n.parse::<usize>().is_ok() => name,
+                    _ => f.as_str(),
+                };
+                errors.push(CorrectnessError::RecursionWithoutSignature(
+                    m.loc.clone(),
+                    String::from(name),
+                ));
+                m._type = arc::new(Type::Error);
+                m.arity = ArityInfo::Unknown;
             }
         }
 
         SExpr::ExternalFunc(_, _, _) => todo!(),
 
-        SExpr::Chain(_, _, _) => todo!(),
+        SExpr::Chain(m, a, b) => {
+            check_sexpr(parent_func, a, module, errors, tracer);
+            check_sexpr(parent_func, b, module, errors, tracer);
+            m._type = b.get_metadata()._type.clone();
+            m.arity = b.get_metadata().arity;
+            tracer.step(|| format!("so the chain has the type of its last value, {}", m._type));
+        }
+
+        SExpr::As(m, v) => {
+            check_sexpr(parent_func, v, module, errors, tracer);
+            let found = v.get_metadata()._type.clone();
+            if found.is_subtype(&m._type, &module.types, &mut HashMap::new()) {
+                tracer.step(|| format!("a value of type {} is ascribed type {}", found, m._type));
+            } else if m
+                ._type
+                .newtype_inner(&module.types)
+                .is_some_and(|t| found.is_subtype(&t, &module.types, &mut HashMap::new()))
+            {
+                // Ascribing a newtype to a value of the type it wraps wraps the value
+                tracer.step(|| format!("a value of type {} is wrapped in {}", found, m._type));
+            } else if found
+                .newtype_inner(&module.types)
+                .is_some_and(|t| t.is_subtype(&m._type, &module.types, &mut HashMap::new()))
+            {
+                // Ascribing the wrapped type to a newtype unwraps the value
+                tracer.step(|| format!("a value of type {} is unwrapped to {}", found, m._type));
+            } else {
+                errors.push(CorrectnessError::AscriptionMismatch(
+                    v.get_metadata().loc.clone(),
+                    m.loc2.clone(),
+                    found,
+                    m._type.clone(),
+                ));
+            }
+            m.arity = v.get_metadata().arity;
+        }
 
         SExpr::Application(m, func, args) => {
-            check_sexpr(parent_func, func, module, errors);
+            check_sexpr(parent_func, func, module, errors, tracer);
             for arg in args.iter_mut() {
-                check_sexpr(parent_func, arg, module, errors);
+                check_sexpr(parent_func, arg, module, errors, tracer);
             }
 
-            let mut ft = func.get_metadata()._type.clone();
+            let applied = func.get_metadata()._type.clone();
+            let mut ft = applied.clone();
             let mut generics_map = HashMap::new();
 
             use std::mem::swap;
@@ -97,18 +388,46 @@ fn check_sexpr(
             swap(&mut args_temp, args);
             let mut arity = func.get_metadata().arity;
             let last_index = args_temp.len();
+            let mut failed = false;
             for (i, arg) in args_temp.into_iter().enumerate() {
+                // Arguments after a mismatch are kept but not checked against the function
+                if failed {
+                    args.push(arg);
+                    continue;
+                }
+
+                let before = generics_map.clone();
                 if let Type::Func(at, rt) = &*ft {
-                    if arg
-                        .get_metadata()
-                        ._type
-                        .is_subtype(at, &module.types, &mut generics_map)
+                    let found = arg.get_metadata()._type.clone();
+                    if matches!(*found, Type::Error)
+                        || found.is_subtype(at, &module.types, &mut generics_map)
                     {
+                        let func_type = ft.clone();
                         m._type = rt.clone();
                         ft = rt.clone();
                         Arc::make_mut(&mut m._type).replace_generics(&generics_map);
+                        tracer.step(|| {
+                            format!(
+                                "applying {} to an argument of type {} gives {}",
+                                func_type,
+                                arg.get_metadata()._type,
+                                m._type
+                            )
+                        });
                     } else {
-                        panic!("{} is not a subtype of {}", arg.get_metadata()._type, at);
+                        let conflict = found.find_conflict(at, &module.types, &mut before.clone());
+                        let mut expected = at.clone();
+                        Arc::make_mut(&mut expected).replace_generics(&before);
+                        errors.push(CorrectnessError::ArgumentMismatch(
+                            arg.get_metadata().loc.clone(),
+                            func.get_metadata().loc.clone(),
+                            found,
+                            expected,
+                            conflict,
+                        ));
+                        failed = true;
+                        args.push(arg);
+                        continue;
                     }
 
                     args.push(arg);
@@ -147,77 +466,313 @@ fn check_sexpr(
                         );
                     }
                 } else {
-                    panic!("type {} is not a function", func.get_metadata()._type);
+                    match &*ft {
+                        // Values whose types are already errors have been reported
+                        Type::Error => (),
+
+                        // The function returned a value that is not a function after taking
+                        // every argument it can
+                        _ if i > 0 => errors.push(CorrectnessError::TooManyArguments(
+                            arg.get_metadata().loc.clone(),
+                            declaration_loc(func, module),
+                            applied.clone(),
+                            i,
+                            last_index,
+                        )),
+
+                        _ => errors.push(CorrectnessError::NotAFunction(
+                            func.get_metadata().loc.clone(),
+                            ft.clone(),
+                        )),
+                    }
+                    failed = true;
+                    args.push(arg);
+                }
+            }
+
+            if failed {
+                m._type = arc::new(Type::Error);
+                m.arity = ArityInfo::Unknown;
+                tracer.step(|| String::from("the application does not type check"));
+            } else {
+                m._type = ft;
+                m.arity = arity;
+                Arc::make_mut(&mut m._type).replace_generics(&generics_map);
+            }
+        }
+
+        SExpr::Tuple(m, values) => {
+            for value in values.iter_mut() {
+                check_sexpr(parent_func, value, module, errors, tracer);
+            }
+
+            m._type = arc::new(Type::Tuple(
+                values
+                    .iter()
+                    .map(|v| v.get_metadata()._type.clone())
+                    .collect(),
+            ));
+            tracer.step(|| format!("so the tuple has type {}", m._type));
+        }
+
+        SExpr::Index(m, v, i, len) => {
+            check_sexpr(parent_func, v, module, errors, tracer);
+            let found = v.get_metadata()._type.clone();
+            match &*found {
+                Type::Tuple(fields) if fields.len() == *len => {
+                    m._type = fields[*i].clone();
+                    tracer.step(|| {
+                        format!(
+                            "element {} of a tuple of type {} has type {}",
+                            i, found, m._type
+                        )
+                    });
                 }
+
+                // Destructuring checks every element, so only report the mismatch once
+                _ => {
+                    if *i == 0 {
+                        errors.push(CorrectnessError::DestructureMismatch(
+                            v.get_metadata().loc.clone(),
+                            found,
+                            *len,
+                        ));
+                    }
+                    m._type = arc::new(Type::Error);
+                }
+            }
+        }
+
+        SExpr::Record(m, fields) => {
+            for (_, value) in fields.iter_mut() {
+                check_sexpr(parent_func, value, module, errors, tracer);
             }
 
-            m._type = ft;
-            m.arity = arity;
-            Arc::make_mut(&mut m._type).replace_generics(&generics_map);
+            let mut types: Vec<_> = fields
+                .iter()
+                .map(|(name, v)| (name.clone(), v.get_metadata()._type.clone()))
+                .collect();
+            types.sort_by(|a, b| a.0.cmp(&b.0));
+            m._type = arc::new(Type::Record(types));
+            tracer.step(|| format!("so the record has type {}", m._type));
+        }
+
+        SExpr::Field(m, v, name) => {
+            check_sexpr(parent_func, v, module, errors, tracer);
+            let found = v.get_metadata()._type.clone();
+            let resolved = found
+                .resolve_alias(&module.types)
+                .unwrap_or_else(|| found.clone());
+            let field = match &*resolved {
+                Type::Record(fields) => fields
+                    .iter()
+                    .position(|f| &f.0 == name)
+                    .map(|i| (i, fields.len(), fields[i].1.clone())),
+                _ => None,
+            };
+
+            match field {
+                // Fields are stored in the same order as elements of a tuple
+                Some((i, len, _type)) => {
+                    m._type = _type;
+                    tracer.step(|| {
+                        format!("field `{}` of type {} has type {}", name, found, m._type)
+                    });
+
+                    let v = std::mem::replace(&mut **v, SExpr::Empty(SExprMetadata::empty()));
+                    *sexpr = SExpr::Index(m.clone(), Box::new(v), i, len);
+                }
+
+                None => {
+                    errors.push(CorrectnessError::NoSuchField(
+                        m.loc.clone(),
+                        found,
+                        name.clone(),
+                    ));
+                    m._type = arc::new(Type::Error);
+                }
+            }
         }
 
         SExpr::Assign(m, a, v) => {
-            check_sexpr(parent_func, v, module, errors);
+            check_sexpr(parent_func, v, module, errors, tracer);
             m._type = v.get_metadata()._type.clone();
             m.arity = v.get_metadata().arity;
+            tracer.step(|| format!("`{}` is bound to a value of type {}", a, m._type));
             module
                 .scope
                 .put_var(a, &m._type, m.arity, &m.loc, true, &module.name);
         }
 
-        SExpr::With(_, _, _) => todo!(),
+        SExpr::With(m, assigns, body) => {
+            // Bindings are only in scope for the rest of the let expression
+            module.scope.push_scope(false);
+            for assign in assigns.iter_mut() {
+                check_sexpr(parent_func, assign, module, errors, tracer);
+            }
+
+            check_sexpr(parent_func, body, module, errors, tracer);
+            module.scope.pop_scope();
+
+            m._type = body.get_metadata()._type.clone();
+            m.arity = body.get_metadata().arity;
+            tracer.step(|| format!("so the let expression has type {}", m._type));
+        }
 
         SExpr::Match(_, _, _) => todo!(),
     }
 }
 
-pub fn check_correctness(ir: &mut Ir, _require_main: bool) -> Result<(), Vec<CorrectnessError>> {
+// check_correctness(&mut Ir, bool) -> Result<(), Vec<CorrectnessError>>
+// Checks the types of every function in the ir.
+pub fn check_correctness(ir: &mut Ir, require_main: bool) -> Result<(), Vec<CorrectnessError>> {
+    check_correctness_helper(ir, require_main, false).map(|_| ())
+}
+
+// check_correctness_with_derivations(&mut Ir, bool) -> Result<Vec<TypeDerivation>, Vec<CorrectnessError>>
+// Checks the types of every function in the ir, recording how the type of each top level
+// definition was derived. Derivations are ordered by module and then by location.
+pub fn check_correctness_with_derivations(
+    ir: &mut Ir,
+    require_main: bool,
+) -> Result<Vec<TypeDerivation>, Vec<CorrectnessError>> {
+    check_correctness_helper(ir, require_main, true)
+}
+
+fn check_correctness_helper(
+    ir: &mut Ir,
+    _require_main: bool,
+    trace: bool,
+) -> Result<Vec<TypeDerivation>, Vec<CorrectnessError>> {
     let mut errors = vec![];
+    let mut derivations = vec![];
 
     for (_, module) in ir.modules.iter_mut() {
         let globals = module.globals.clone();
-        for (_, raw) in globals {
-            use std::mem::swap;
-
+        for (name, raw) in globals {
             let mut func = module.funcs.remove(&raw).unwrap();
             if func.checked {
+                // The type was derived while checking an earlier definition that referenced it
+                if trace {
+                    derivations.push((
+                        func.loc.span.start,
+                        TypeDerivation {
+                            module: module.name.clone(),
+                            name,
+                            _type: func._type.clone(),
+                            steps: vec![],
+                        },
+                    ));
+                }
+
                 module.funcs.insert(raw, func);
                 continue;
             }
 
-            module.scope.push_scope(true);
-            for arg in func.args.iter() {
-                module.scope.put_var(
-                    &arg.0,
-                    &arg.1,
-                    ArityInfo::Unknown,
-                    &Location::empty(),
-                    true,
-                    "",
-                );
-            }
-
-            let mut body = SExpr::Empty(SExprMetadata::empty());
-            swap(&mut func.body, &mut body);
-            check_sexpr(&mut func, &mut body, module, &mut errors);
-            swap(&mut func.body, &mut body);
-
-            let mut _type = func.body.get_metadata()._type.clone();
-            for arg in func.args.iter().rev() {
-                _type = arc::new(Type::Func(arg.1.clone(), _type));
+            let mut tracer = Tracer {
+                enabled: trace,
+                ..Tracer::default()
+            };
+            check_top_level(&mut func, &raw, module, &mut errors, &mut tracer);
+
+            if trace {
+                derivations.push((
+                    func.loc.span.start,
+                    TypeDerivation {
+                        module: module.name.clone(),
+                        name,
+                        _type: func._type.clone(),
+                        steps: tracer.steps,
+                    },
+                ));
             }
 
-            func._type = _type;
-
-            module.scope.pop_scope();
+            // Mark the function as checked so that extending the module only checks new definitions
+            func.checked = true;
+            module.funcs.insert(raw, func);
+        }
 
+        // References to functions with signatures do not check them, so shadowed and private
+        // imported definitions with signatures may not have been checked yet
+        let mut signed: Vec<_> = module
+            .signatures
+            .keys()
+            .filter(|v| module.funcs.get(*v).is_some_and(|f| !f.checked))
+            .cloned()
+            .collect();
+        signed.sort();
+        for raw in signed {
+            let mut func = module.funcs.remove(&raw).unwrap();
+            check_top_level(&mut func, &raw, module, &mut errors, &mut Tracer::default());
+            func.checked = true;
             module.funcs.insert(raw, func);
         }
     }
 
     if errors.is_empty() {
-        Ok(())
+        for (_, module) in ir.modules.iter_mut() {
+            uncurry::uncurry(module);
+        }
+
+        derivations.sort_by(|a, b| a.1.module.cmp(&b.1.module).then(a.0.cmp(&b.0)));
+        Ok(derivations.into_iter().map(|v| v.1).collect())
     } else {
         Err(errors)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distance_counts_insertions_removals_and_replacements() {
+        assert_eq!(edit_distance("length", "length"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("lenght", "length"), 2);
+    }
+
+    #[test]
+    fn closest_name_is_within_a_third_of_the_length() {
+        // Six characters allow a distance of two
+        assert_eq!(
+            closest_name("lenght", ["length"].into_iter()),
+            Some("length")
+        );
+        assert_eq!(closest_name("lenght", ["lentil"].into_iter()), None);
+
+        // Nine characters allow a distance of three
+        assert_eq!(
+            closest_name("abcdefghi", ["abcxyzghi"].into_iter()),
+            Some("abcxyzghi")
+        );
+        assert_eq!(closest_name("abcdefghi", ["abwxyzghi"].into_iter()), None);
+    }
+
+    #[test]
+    fn closest_name_allows_a_distance_of_one_for_short_names() {
+        assert_eq!(closest_name("ab", ["ax"].into_iter()), Some("ax"));
+        assert_eq!(closest_name("ab", ["xy"].into_iter()), None);
+        assert_eq!(closest_name("a", ["b"].into_iter()), Some("b"));
+    }
+
+    #[test]
+    fn closest_name_breaks_ties_alphabetically() {
+        assert_eq!(
+            closest_name("cat", ["hat", "bat", "rat"].into_iter()),
+            Some("bat")
+        );
+        assert_eq!(
+            closest_name("cat", ["cart", "hat"].into_iter()),
+            Some("cart")
+        );
+    }
+
+    #[test]
+    fn closest_name_excludes_the_name_itself() {
+        assert_eq!(closest_name("foo", ["foo"].into_iter()), None);
+        assert_eq!(closest_name("foo", ["foo", "fob"].into_iter()), Some("fob"));
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use super::ir::{IrModule, Location, SExpr};
+
+// Represents a value produced by the interpreter.
+#[derive(Debug, Clone)]
+pub enum Value {
+    // A function along with the values it closed over followed by the arguments applied to it
+    Closure(String, Vec<Value>),
+
+    // A tuple of values
+    Tuple(Vec<Value>),
+
+    // A record, whose fields are sorted by name
+    Record(Vec<(String, Value)>),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Closure(func, values) if values.is_empty() => write!(f, "{}", func),
+            Value::Closure(func, values) => {
+                write!(f, "({}", func)?;
+                for value in values {
+                    write!(f, " {}", value)?;
+                }
+                write!(f, ")")
+            }
+
+            Value::Tuple(values) => {
+                write!(f, "(")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, ")")
+            }
+
+            Value::Record(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} = {}", name, value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+// Represents an error encountered while interpreting.
+#[derive(Debug)]
+pub enum EvalError {
+    Stopped,
+    UnknownFunction(String),
+    UnknownVariable(String),
+    NotAFunction(Value),
+    NotATuple(Value, usize),
+    Unsupported(&'static str),
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::Stopped => write!(f, "evaluation stopped"),
+            EvalError::UnknownFunction(func) => write!(f, "function `{}` not found", func),
+            EvalError::UnknownVariable(var) => write!(f, "variable `{}` not found", var),
+            EvalError::NotAFunction(v) => write!(f, "`{}` is not a function", v),
+            EvalError::NotATuple(v, i) => write!(f, "`{}` has no element {}", v, i),
+            EvalError::Unsupported(e) => write!(f, "{} are not supported by the interpreter", e),
+        }
+    }
+}
+
+// Represents a single reduction, ie a function being entered with all of its arguments.
+pub struct Reduction<'a> {
+    pub func: &'a str,
+    pub loc: &'a Location,
+    pub body: &'a SExpr,
+    pub env: Vec<(&'a str, &'a Value)>,
+    pub depth: usize,
+}
+
+// Represents an interpreter for a checked module of the ir.
+pub struct Interpreter<'a> {
+    module: &'a IrModule,
+    hook: Option<Box<dyn FnMut(&Reduction) -> bool + 'a>>,
+    depth: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    // new(&IrModule) -> Interpreter
+    // Creates a new interpreter for a module.
+    pub fn new(module: &'a IrModule) -> Interpreter<'a> {
+        Interpreter {
+            module,
+            hook: None,
+            depth: 0,
+        }
+    }
+
+    // with_hook(&IrModule, impl FnMut(&Reduction) -> bool) -> Interpreter
+    // Creates a new interpreter that calls the hook before every reduction. Evaluation stops if
+    // the hook returns false.
+    pub fn with_hook(
+        module: &'a IrModule,
+        hook: impl FnMut(&Reduction) -> bool + 'a,
+    ) -> Interpreter<'a> {
+        Interpreter {
+            module,
+            hook: Some(Box::new(hook)),
+            depth: 0,
+        }
+    }
+
+    // evaluate(&mut self, &str) -> Result<Value, EvalError>
+    // Evaluates a global value.
+    pub fn evaluate(&mut self, name: &str) -> Result<Value, EvalError> {
+        let module = self.module;
+        match module.globals.get(name) {
+            Some(raw) => self.make_closure(raw, &HashMap::new()),
+            None => Err(EvalError::UnknownVariable(String::from(name))),
+        }
+    }
+
+    // make_closure(&mut self, &str, &HashMap<String, Value>) -> Result<Value, EvalError>
+    // Creates a closure for a function, capturing values from the environment. Functions that
+    // take no arguments are evaluated immediately.
+    fn make_closure(
+        &mut self,
+        func: &str,
+        env: &HashMap<String, Value>,
+    ) -> Result<Value, EvalError> {
+        let module = self.module;
+        let f = match module.funcs.get(func) {
+            Some(v) => v,
+            None => return Err(EvalError::UnknownFunction(String::from(func))),
+        };
+
+        let mut values = vec![];
+        for name in f.captured_names.iter() {
+            match env.get(name) {
+                Some(v) => values.push(v.clone()),
+                None => return Err(EvalError::UnknownVariable(name.clone())),
+            }
+        }
+
+        if f.args.is_empty() {
+            self.call(func, values)
+        } else {
+            Ok(Value::Closure(String::from(func), values))
+        }
+    }
+
+    // apply(&mut self, Value, Value) -> Result<Value, EvalError>
+    // Applies an argument to a value, calling the function if it has all its arguments.
+    fn apply(&mut self, func: Value, arg: Value) -> Result<Value, EvalError> {
+        let (func, mut values) = match func {
+            Value::Closure(func, values) => (func, values),
+            Value::Tuple(_) | Value::Record(_) => return Err(EvalError::NotAFunction(func)),
+        };
+        values.push(arg);
+
+        let module = self.module;
+        let f = match module.funcs.get(&func) {
+            Some(v) => v,
+            None => return Err(EvalError::UnknownFunction(func)),
+        };
+
+        if values.len() == f.captured_names.len() + f.args.len() {
+            self.call(&func, values)
+        } else {
+            Ok(Value::Closure(func, values))
+        }
+    }
+
+    // call(&mut self, &str, Vec<Value>) -> Result<Value, EvalError>
+    // Calls a function with its closed over values followed by its arguments.
+    fn call(&mut self, func: &str, values: Vec<Value>) -> Result<Value, EvalError> {
+        let module = self.module;
+        let f = match module.funcs.get(func) {
+            Some(v) => v,
+            None => return Err(EvalError::UnknownFunction(String::from(func))),
+        };
+
+        let names: Vec<_> = f
+            .captured_names
+            .iter()
+            .chain(f.args.iter().map(|v| &v.0))
+            .collect();
+
+        if let Some(hook) = self.hook.as_mut() {
+            let reduction = Reduction {
+                func: &f.name,
+                loc: &f.loc,
+                body: &f.body,
+                env: names
+                    .iter()
+                    .map(|v| v.as_str())
+                    .zip(values.iter())
+                    .collect(),
+                depth: self.depth,
+            };
+
+            if !hook(&reduction) {
+                return Err(EvalError::Stopped);
+            }
+        }
+
+        let mut env: HashMap<_, _> = names.into_iter().cloned().zip(values).collect();
+        self.depth += 1;
+        let result = self.eval_sexpr(&f.body, &mut env);
+        self.depth -= 1;
+        result
+    }
+
+    // eval_sexpr(&mut self, &SExpr, &mut HashMap<String, Value>) -> Result<Value, EvalError>
+    // Evaluates an expression in an environment.
+    fn eval_sexpr(
+        &mut self,
+        sexpr: &SExpr,
+        env: &mut HashMap<String, Value>,
+    ) -> Result<Value, EvalError> {
+        match sexpr {
+            SExpr::Symbol(_, s) => {
+                if let Some(v) = env.get(s) {
+                    Ok(v.clone())
+                } else if let Some(raw) = self.module.globals.get(s).cloned() {
+                    self.make_closure(&raw, env)
+                } else {
+                    Err(EvalError::UnknownVariable(s.clone()))
+                }
+            }
+
+            SExpr::Function(_, f) => self.make_closure(f, env),
+
+            SExpr::As(_, v) => self.eval_sexpr(v, env),
+
+            SExpr::Application(_, func, args) => {
+                let mut value = self.eval_sexpr(func, env)?;
+                for arg in args {
+                    let arg = self.eval_sexpr(arg, env)?;
+                    value = self.apply(value, arg)?;
+                }
+                Ok(value)
+            }
+
+            SExpr::Tuple(_, values) => {
+                let mut tuple = vec![];
+                for value in values {
+                    tuple.push(self.eval_sexpr(value, env)?);
+                }
+                Ok(Value::Tuple(tuple))
+            }
+
+            SExpr::Index(_, v, i, _) => match self.eval_sexpr(v, env)? {
+                Value::Tuple(mut values) if *i < values.len() => Ok(values.swap_remove(*i)),
+                Value::Record(mut fields) if *i < fields.len() => Ok(fields.swap_remove(*i).1),
+                v => Err(EvalError::NotATuple(v, *i)),
+            },
+
+            SExpr::Record(_, fields) => {
+                let mut record = vec![];
+                for (name, value) in fields {
+                    record.push((name.clone(), self.eval_sexpr(value, env)?));
+                }
+                record.sort_by(|a, b| a.0.cmp(&b.0));
+                Ok(Value::Record(record))
+            }
+
+            SExpr::Field(_, _, _) => unreachable!("field accesses are replaced while checking"),
+
+            SExpr::Assign(_, a, v) => {
+                let value = self.eval_sexpr(v, env)?;
+                env.insert(a.clone(), value.clone());
+                Ok(value)
+            }
+
+            SExpr::Chain(_, a, b) => {
+                self.eval_sexpr(a, env)?;
+                self.eval_sexpr(b, env)
+            }
+
+            SExpr::With(_, assigns, body) => {
+                // Bindings are only visible in the body
+                let mut scope = env.clone();
+                for assign in assigns {
+                    self.eval_sexpr(assign, &mut scope)?;
+                }
+                self.eval_sexpr(body, &mut scope)
+            }
+
+            SExpr::Empty(_) | SExpr::TypeAlias(_, _) => Err(EvalError::Unsupported("types")),
+            SExpr::ExternalFunc(_, _, _) => Err(EvalError::Unsupported("external functions")),
+            SExpr::Match(_, _, _) => Err(EvalError::Unsupported("match expressions")),
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use logos::Span;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
+use std::path::Path;
 
+use super::macros::{self, Macro};
 use super::parser::Ast;
 use super::scopes::Scope;
 use super::types;
@@ -44,6 +46,16 @@ pub enum IrError {
     UnsupportedAnnotation(Location, String),
     InvalidFFIType(Location, TypeRc),
     DuplicateModule(String, DuplicateModuleInfo),
+    SignatureWithoutDefinition(Location, String),
+    MacroArity(Location, String, usize, usize),
+    RecursiveMacro(Location, String),
+    UnknownModule(Location, String),
+    ImportCycle(Vec<(Location, String, String)>),
+    UnknownImport(Location, String, String),
+    PrivateItem(Location, Location, String, String),
+
+    // The construct at the location parses but cannot be converted yet; the string names it
+    Unsupported(Location, &'static str),
 }
 
 pub enum DuplicateModuleInfo {
@@ -130,9 +142,24 @@ pub enum SExpr {
     // Chain operator
     Chain(SExprMetadata, Box<SExpr>, Box<SExpr>),
 
+    // Type ascription
+    As(SExprMetadata, Box<SExpr>),
+
     // Function application
     Application(SExprMetadata, Box<SExpr>, Vec<SExpr>),
 
+    // Tuples
+    Tuple(SExprMetadata, Vec<SExpr>),
+
+    // Tuple element access, given the index of the element and the number of elements
+    Index(SExprMetadata, Box<SExpr>, usize, usize),
+
+    // Records
+    Record(SExprMetadata, Vec<(String, SExpr)>),
+
+    // Field access, which is replaced by an index into the record when checked
+    Field(SExprMetadata, Box<SExpr>, String),
+
     // Assignment
     Assign(SExprMetadata, String, Box<SExpr>),
 
@@ -154,7 +181,8 @@ impl Display for SExpr {
             SExpr::Symbol(m, s) => write!(f, "{}: {}", s, m._type),
             SExpr::Function(m, func) => write!(f, "func-get {}: {}", func, m._type),
             SExpr::ExternalFunc(_, _, _) => todo!(),
-            SExpr::Chain(_, _, _) => todo!(),
+            SExpr::Chain(m, a, b) => write!(f, "({}); ({}) : {}", a, b, m._type),
+            SExpr::As(m, v) => write!(f, "({}) as {}", v, m._type),
             SExpr::Application(m, func, args) => {
                 write!(f, "({})", func)?;
                 for arg in args {
@@ -167,8 +195,33 @@ impl Display for SExpr {
                 write!(f, " : {}", m._type)
             }
 
+            SExpr::Tuple(m, values) => {
+                write!(f, "(tuple")?;
+                for value in values {
+                    write!(f, " ({})", value)?;
+                }
+                write!(f, ") : {}", m._type)
+            }
+
+            SExpr::Index(m, v, i, _) => write!(f, "(get ({}) {}) : {}", v, i, m._type),
+            SExpr::Record(m, fields) => {
+                write!(f, "(record")?;
+                for (name, value) in fields {
+                    write!(f, " {} = ({})", name, value)?;
+                }
+                write!(f, ") : {}", m._type)
+            }
+
+            SExpr::Field(m, v, name) => write!(f, "({}).{} : {}", v, name, m._type),
             SExpr::Assign(m, v, a) => write!(f, "set {}: {} = ({})", v, m._type, a),
-            SExpr::With(_, _, _) => todo!(),
+            SExpr::With(m, assigns, body) => {
+                write!(f, "(with")?;
+                for assign in assigns {
+                    write!(f, " ({})", assign)?;
+                }
+                write!(f, " in ({})) : {}", body, m._type)
+            }
+
             SExpr::Match(_, _, _) => todo!(),
         }
     }
@@ -191,8 +244,12 @@ impl SExpr {
             | Self::Function(m, _)
             | Self::ExternalFunc(m, _, _)
             | Self::Chain(m, _, _)
-            //| Self::As(m, _)
+            | Self::As(m, _)
             | Self::Application(m, _, _)
+            | Self::Tuple(m, _)
+            | Self::Index(m, _, _, _)
+            | Self::Record(m, _)
+            | Self::Field(m, _, _)
             | Self::Assign(m, _, _)
             | Self::With(m, _, _)
             //| Self::Walrus(m, _, _)
@@ -217,8 +274,12 @@ impl SExpr {
             | Self::Function(m, _)
             | Self::ExternalFunc(m, _, _)
             | Self::Chain(m, _, _)
-            //| Self::As(m, _)
+            | Self::As(m, _)
             | Self::Application(m, _, _)
+            | Self::Tuple(m, _)
+            | Self::Index(m, _, _, _)
+            | Self::Record(m, _)
+            | Self::Field(m, _, _)
             | Self::Assign(m, _, _)
             | Self::With(m, _, _)
             //| Self::Walrus(m, _, _)
@@ -229,7 +290,7 @@ impl SExpr {
 }
 
 // Represents a function in the Ir.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IrFunction {
     pub loc: Location,
     pub name: String,
@@ -257,7 +318,7 @@ impl Display for IrFunction {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IrImport {
     pub name: String,
     pub loc: Location,
@@ -265,7 +326,7 @@ pub struct IrImport {
     pub imports: HashMap<String, (TypeRc, usize, bool)>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IrExtern {
     pub loc: Location,
     pub extern_name: String,
@@ -275,7 +336,7 @@ pub struct IrExtern {
 }
 
 // Represents a module of the ir.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IrModule {
     pub name: String,
     pub filename: String,
@@ -288,16 +349,31 @@ pub struct IrModule {
     pub funcs: HashMap<String, IrFunction>,
     pub types: HashMap<String, TypeRc>,
     pub globals: HashMap<String, String>,
+    pub signatures: HashMap<String, (Location, TypeRc)>,
+    pub seen_funcs: HashMap<String, usize>,
+    pub macros: HashMap<String, Macro>,
+
+    // Definitions and types declared without `pub`, which other modules cannot refer to
+    pub private: HashMap<String, Location>,
+    pub private_types: HashMap<String, Location>,
+
+    // The `@no_runtime` annotation, which makes builds freestanding
+    pub no_runtime: Option<Location>,
 }
 
 impl Display for IrModule {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Maps are printed in sorted order so that the same IR is always printed the same way
+        let mut funcs: Vec<_> = self.funcs.iter().collect();
+        funcs.sort_by(|a, b| a.0.cmp(b.0));
         write!(f, "(module {}", self.name)?;
-        for func in self.funcs.iter() {
+        for func in funcs {
             write!(f, "\n        {}", func.1)?;
         }
 
-        for (global, raw) in self.globals.iter() {
+        let mut globals: Vec<_> = self.globals.iter().collect();
+        globals.sort();
+        for (global, raw) in globals {
             write!(
                 f,
                 "\n        (global {} = func-get {} : {})",
@@ -311,15 +387,17 @@ impl Display for IrModule {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Ir {
     pub modules: HashMap<String, IrModule>,
 }
 
 impl Display for Ir {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut modules: Vec<_> = self.modules.iter().collect();
+        modules.sort_by(|a, b| a.0.cmp(b.0));
         write!(f, "(")?;
-        for module in self.modules.iter() {
+        for module in modules {
             write!(f, "\n    {}", module.1)?;
         }
         write!(f, ")")
@@ -351,6 +429,12 @@ impl IrModule {
             funcs: HashMap::with_capacity(0),
             types: HashMap::with_capacity(0),
             globals: HashMap::with_capacity(0),
+            signatures: HashMap::with_capacity(0),
+            seen_funcs: vec![(String::with_capacity(0), 0)].into_iter().collect(),
+            macros: HashMap::with_capacity(0),
+            private: HashMap::with_capacity(0),
+            private_types: HashMap::with_capacity(0),
+            no_runtime: None,
         }
     }
 }
@@ -458,13 +542,14 @@ fn convert_node(
 
         Ast::Generic(_, _)
         | Ast::Annotation(_, _)
+        | Ast::Pub(_)
         | Ast::Import(_, _, _)
         | Ast::QualifiedImport(_, _, _)
         | Ast::Header(_, _, _, _)
         | Ast::LibHeader(_, _, _)
         | Ast::Extern(_, _, _, _) => {
             unreachable!(
-                "generics, annotations, imports, headers, and external declarations are already handled!"
+                "generics, annotations, visibility, imports, headers, and external declarations are already handled!"
             );
         }
 
@@ -633,11 +718,144 @@ fn convert_node(
                         vec![arg],
                     )
                 }
+            } else if op == ";" {
+                // Chains evaluate the left side for its effects and give the right side
+                let first = convert_node(
+                    *l,
+                    filename,
+                    funcs,
+                    global,
+                    seen_funcs,
+                    types,
+                    generic_uids,
+                    last_uid,
+                );
+                let second = convert_node(
+                    *r,
+                    filename,
+                    funcs,
+                    global,
+                    seen_funcs,
+                    types,
+                    generic_uids,
+                    last_uid,
+                );
+                SExpr::Chain(
+                    SExprMetadata {
+                        loc: Location::new(span, filename),
+                        loc2: Location::empty(),
+                        origin: String::with_capacity(0),
+                        _type: arc::new(Type::Error),
+                        arity: ArityInfo::Unknown,
+                        tailrec: false,
+                        impure: false,
+                    },
+                    Box::new(first),
+                    Box::new(second),
+                )
+            } else if op == "::" {
+                // Qualified names refer to definitions imported from other modules
+                let name = Ast::Infix(span.clone(), op, l, r).qualified_name().unwrap();
+                SExpr::Symbol(
+                    SExprMetadata {
+                        loc: Location::new(span, filename),
+                        loc2: Location::empty(),
+                        origin: String::with_capacity(0),
+                        _type: arc::new(Type::Error),
+                        arity: ArityInfo::Unknown,
+                        tailrec: false,
+                        impure: false,
+                    },
+                    name,
+                )
             } else {
                 unreachable!("uwu moment");
             }
         }
 
+        // Tuples
+        Ast::Tuple(span, values) => SExpr::Tuple(
+            SExprMetadata {
+                loc: Location::new(span, filename),
+                loc2: Location::empty(),
+                origin: String::with_capacity(0),
+                _type: arc::new(Type::Error),
+                arity: ArityInfo::Unknown,
+                tailrec: false,
+                impure: false,
+            },
+            values
+                .into_iter()
+                .map(|v| {
+                    convert_node(
+                        v,
+                        filename,
+                        funcs,
+                        global,
+                        seen_funcs,
+                        types,
+                        generic_uids,
+                        last_uid,
+                    )
+                })
+                .collect(),
+        ),
+
+        // Records
+        Ast::Record(span, fields) => SExpr::Record(
+            SExprMetadata {
+                loc: Location::new(span, filename),
+                loc2: Location::empty(),
+                origin: String::with_capacity(0),
+                _type: arc::new(Type::Error),
+                arity: ArityInfo::Unknown,
+                tailrec: false,
+                impure: false,
+            },
+            fields
+                .into_iter()
+                .map(|(name, v)| {
+                    (
+                        name,
+                        convert_node(
+                            v,
+                            filename,
+                            funcs,
+                            global,
+                            seen_funcs,
+                            types,
+                            generic_uids,
+                            last_uid,
+                        ),
+                    )
+                })
+                .collect(),
+        ),
+
+        // Field access
+        Ast::Field(span, v, name) => SExpr::Field(
+            SExprMetadata {
+                loc: Location::new(span, filename),
+                loc2: Location::empty(),
+                origin: String::with_capacity(0),
+                _type: arc::new(Type::Error),
+                arity: ArityInfo::Unknown,
+                tailrec: false,
+                impure: false,
+            },
+            Box::new(convert_node(
+                *v,
+                filename,
+                funcs,
+                global,
+                seen_funcs,
+                types,
+                generic_uids,
+                last_uid,
+            )),
+            name,
+        ),
+
         // Application
         Ast::Application(span, l, r) => SExpr::Application(
             SExprMetadata {
@@ -687,72 +905,12 @@ fn convert_node(
                 generic_uids,
                 last_uid,
             );
-            if global && name != "_" {
-                let func_name = if seen_funcs.contains_key(&name) {
-                    let seen = seen_funcs.get_mut(&name).unwrap();
-                    let name = format!("{}.{}", name, seen);
-                    *seen += 1;
-                    name
-                } else {
-                    seen_funcs.insert(name.clone(), 0);
-                    name.clone()
-                };
-                funcs.insert(
-                    func_name.clone(),
-                    IrFunction {
-                        loc: Location::new(span.clone(), filename),
-                        name: name.clone(),
-                        _type: arc::new(Type::Unknown),
-                        args: Vec::with_capacity(0),
-                        captured: HashMap::with_capacity(0),
-                        captured_names: Vec::with_capacity(0),
-                        body: sexpr,
-                        global: true,
-                        checked: false,
-                        written: false,
-                        impure: false,
-                    },
-                );
+            convert_assign(span, name, sexpr, filename, funcs, global, seen_funcs)
+        }
 
-                SExpr::Assign(
-                    SExprMetadata {
-                        loc: Location::new(span.clone(), filename),
-                        loc2: Location::empty(),
-                        origin: String::with_capacity(0),
-                        _type: arc::new(Type::Error),
-                        arity: ArityInfo::Unknown,
-                        tailrec: false,
-                        impure: false,
-                    },
-                    name,
-                    Box::new(SExpr::Function(
-                        SExprMetadata {
-                            loc: Location::new(span, filename),
-                            loc2: Location::empty(),
-                            origin: String::with_capacity(0),
-                            _type: arc::new(Type::Error),
-                            arity: ArityInfo::Unknown,
-                            tailrec: false,
-                            impure: false,
-                        },
-                        func_name,
-                    )),
-                )
-            } else {
-                SExpr::Assign(
-                    SExprMetadata {
-                        loc: Location::new(span, filename),
-                        loc2: Location::empty(),
-                        origin: String::with_capacity(0),
-                        _type: arc::new(Type::Error),
-                        arity: ArityInfo::Unknown,
-                        tailrec: false,
-                        impure: false,
-                    },
-                    name,
-                    Box::new(sexpr),
-                )
-            }
+        // Tuple destructuring
+        Ast::AssignTuple(_, _, _) => {
+            unreachable!("destructuring is converted by convert_destructure")
         }
 
         // Assignment with types
@@ -858,7 +1016,11 @@ fn convert_node(
             }
         }
 
-        Ast::AssignType(_, _, _) => todo!(),
+        Ast::AssignType(_, _, _) | Ast::AssignNewtype(_, _, _) => {
+            unreachable!("type declarations are only at the top level")
+        }
+        Ast::Signature(_, _, _) => unreachable!("signatures are only at the top level"),
+        Ast::Macro(_, _, _, _) => unreachable!("macros are expanded before conversion"),
         /*
         Ast::AssignType(span, name, _type) => {
             let span2 = _type.get_span();
@@ -1069,6 +1231,43 @@ fn convert_node(
             func_id
         }
 
+        // Type ascriptions
+        Ast::As(span, v, t) => {
+            let loc2 = Location::new(t.get_span(), filename);
+            let known: Vec<_> = generic_uids.keys().cloned().collect();
+            let _type = arc::new(types::convert_ast_to_type(
+                *t,
+                filename,
+                generic_uids,
+                last_uid,
+            ));
+
+            // Generics introduced by the ascription are only in scope for the ascription
+            generic_uids.retain(|g, _| known.contains(g));
+
+            SExpr::As(
+                SExprMetadata {
+                    loc: Location::new(span, filename),
+                    loc2,
+                    origin: String::with_capacity(0),
+                    _type,
+                    arity: ArityInfo::Unknown,
+                    tailrec: false,
+                    impure: false,
+                },
+                Box::new(convert_node(
+                    *v,
+                    filename,
+                    funcs,
+                    global,
+                    seen_funcs,
+                    types,
+                    generic_uids,
+                    last_uid,
+                )),
+            )
+        }
+
         // With expressions
         Ast::With(span, a, v) => SExpr::With(
             SExprMetadata {
@@ -1081,17 +1280,32 @@ fn convert_node(
                 impure: false,
             },
             a.into_iter()
-                .map(|a| {
-                    convert_node(
-                        a,
-                        filename,
-                        funcs,
-                        false,
-                        seen_funcs,
-                        types,
-                        generic_uids,
-                        last_uid,
-                    )
+                .flat_map(|a| {
+                    if let Ast::AssignTuple(span, names, v) = a {
+                        convert_destructure(
+                            span,
+                            names,
+                            *v,
+                            filename,
+                            funcs,
+                            false,
+                            seen_funcs,
+                            types,
+                            generic_uids,
+                            last_uid,
+                        )
+                    } else {
+                        vec![convert_node(
+                            a,
+                            filename,
+                            funcs,
+                            false,
+                            seen_funcs,
+                            types,
+                            generic_uids,
+                            last_uid,
+                        )]
+                    }
                 })
                 .collect(),
             Box::new(convert_node(
@@ -1143,17 +1357,56 @@ fn convert_node(
                 .collect(),
         ),
         */
-        Ast::Match(_, _, _) => todo!(),
-        Ast::Int(_, _) => todo!(),
-        Ast::Float(_, _) => todo!(),
-        Ast::Word(_, _) => todo!(),
-        Ast::Char(_, _) => todo!(),
-        Ast::String(_, _) => todo!(),
-        Ast::Enum(_, _) => todo!(),
-        Ast::List(_, _) => todo!(),
-        Ast::Prefix(_, _, _) => todo!(),
-        Ast::As(_, _, _) => todo!(),
-        Ast::Walrus(_, _, _) => todo!(),
+        // Definitions using these are reported by find_unsupported and never converted
+        Ast::Match(_, _, _)
+        | Ast::Int(_, _)
+        | Ast::Float(_, _)
+        | Ast::Word(_, _)
+        | Ast::Char(_, _)
+        | Ast::String(_, _)
+        | Ast::Enum(_, _)
+        | Ast::List(_, _)
+        | Ast::Prefix(_, _, _)
+        | Ast::Walrus(_, _, _) => unreachable!("unsupported construct was converted"),
+    }
+}
+
+// find_unsupported(&Ast) -> Option<(Span, &'static str)>
+// Finds the first construct in a definition that parses but cannot be converted yet, along with a
+// name for the kind of construct.
+fn find_unsupported(ast: &Ast) -> Option<(Span, &'static str)> {
+    match ast {
+        Ast::Int(s, _)
+        | Ast::Float(s, _)
+        | Ast::Word(s, _)
+        | Ast::Char(s, _)
+        | Ast::String(s, _)
+        | Ast::Enum(s, _) => Some((s.clone(), "Literals")),
+        Ast::List(s, _) => Some((s.clone(), "Lists")),
+        Ast::Match(s, _, _) => Some((s.clone(), "Match expressions")),
+        Ast::Prefix(s, _, _) => Some((s.clone(), "Prefix operators")),
+        Ast::Walrus(s, _, _) => Some((s.clone(), "Walrus assignments")),
+
+        Ast::Tuple(_, values) => values.iter().find_map(find_unsupported),
+        Ast::Record(_, fields) => fields.iter().find_map(|v| find_unsupported(&v.1)),
+        Ast::Application(_, func, args) => {
+            find_unsupported(func).or_else(|| args.iter().find_map(find_unsupported))
+        }
+        Ast::Infix(_, _, l, r) => find_unsupported(l).or_else(|| find_unsupported(r)),
+        Ast::With(_, assigns, body) => assigns
+            .iter()
+            .find_map(find_unsupported)
+            .or_else(|| find_unsupported(body)),
+
+        Ast::Field(_, v, _)
+        | Ast::As(_, v, _)
+        | Ast::Assign(_, _, v)
+        | Ast::AssignTuple(_, _, v)
+        | Ast::AssignTyped(_, _, _, v)
+        | Ast::AssignFunction(_, _, _, v)
+        | Ast::Lambda(_, _, v) => find_unsupported(v),
+
+        _ => None,
     }
 }
 
@@ -1161,12 +1414,165 @@ fn convert_node(
 // Extracts types and inserts them into the Ir's list of types.
 fn extract_types_to_ir(asts: &[Ast], module: &mut IrModule) {
     for ast in asts {
-        if let Ast::AssignType(_, v, _) = ast {
+        if let Ast::AssignType(_, v, _) | Ast::AssignNewtype(_, v, _) = ast {
             module.types.insert(v.clone(), arc::new(Type::Unknown));
         }
     }
 }
 
+// convert_assign(Span, String, SExpr, &str, &mut HashMap<String, IrFunction>, bool, &mut HashMap<String, usize>) -> SExpr
+// Converts an assignment of an already converted value. Global assignments put the value in a
+// function of their own.
+fn convert_assign(
+    span: Span,
+    name: String,
+    sexpr: SExpr,
+    filename: &str,
+    funcs: &mut HashMap<String, IrFunction>,
+    global: bool,
+    seen_funcs: &mut HashMap<String, usize>,
+) -> SExpr {
+    if global && name != "_" {
+        let func_name = if seen_funcs.contains_key(&name) {
+            let seen = seen_funcs.get_mut(&name).unwrap();
+            let name = format!("{}.{}", name, seen);
+            *seen += 1;
+            name
+        } else {
+            seen_funcs.insert(name.clone(), 0);
+            name.clone()
+        };
+        funcs.insert(
+            func_name.clone(),
+            IrFunction {
+                loc: Location::new(span.clone(), filename),
+                name: name.clone(),
+                _type: arc::new(Type::Unknown),
+                args: Vec::with_capacity(0),
+                captured: HashMap::with_capacity(0),
+                captured_names: Vec::with_capacity(0),
+                body: sexpr,
+                global: true,
+                checked: false,
+                written: false,
+                impure: false,
+            },
+        );
+
+        SExpr::Assign(
+            SExprMetadata {
+                loc: Location::new(span.clone(), filename),
+                loc2: Location::empty(),
+                origin: String::with_capacity(0),
+                _type: arc::new(Type::Error),
+                arity: ArityInfo::Unknown,
+                tailrec: false,
+                impure: false,
+            },
+            name,
+            Box::new(SExpr::Function(
+                SExprMetadata {
+                    loc: Location::new(span, filename),
+                    loc2: Location::empty(),
+                    origin: String::with_capacity(0),
+                    _type: arc::new(Type::Error),
+                    arity: ArityInfo::Unknown,
+                    tailrec: false,
+                    impure: false,
+                },
+                func_name,
+            )),
+        )
+    } else {
+        SExpr::Assign(
+            SExprMetadata {
+                loc: Location::new(span, filename),
+                loc2: Location::empty(),
+                origin: String::with_capacity(0),
+                _type: arc::new(Type::Error),
+                arity: ArityInfo::Unknown,
+                tailrec: false,
+                impure: false,
+            },
+            name,
+            Box::new(sexpr),
+        )
+    }
+}
+
+// convert_destructure(Span, Vec<String>, Ast, &str, &mut HashMap<String, IrFunction>, bool, &mut HashMap<String, usize>, &mut HashMap<String, TypeRc>, &mut HashMap<String, usize>, &mut usize) -> Vec<SExpr>
+// Converts an assignment that destructures a tuple, such as `(a, b) = value`. The tuple is
+// assigned to a name that cannot be written in source code, and each name is assigned an element
+// of it. Elements assigned to `_` are ignored.
+fn convert_destructure(
+    span: Span,
+    names: Vec<String>,
+    value: Ast,
+    filename: &str,
+    funcs: &mut HashMap<String, IrFunction>,
+    global: bool,
+    seen_funcs: &mut HashMap<String, usize>,
+    types: &mut HashMap<String, TypeRc>,
+    generic_uids: &mut HashMap<String, usize>,
+    last_uid: &mut usize,
+) -> Vec<SExpr> {
+    let tuple = format!("({})", names.join(", "));
+    let len = names.len();
+    let value = convert_node(
+        value,
+        filename,
+        funcs,
+        false,
+        seen_funcs,
+        types,
+        generic_uids,
+        last_uid,
+    );
+
+    let meta = SExprMetadata {
+        loc: Location::new(span.clone(), filename),
+        loc2: Location::empty(),
+        origin: String::with_capacity(0),
+        _type: arc::new(Type::Error),
+        arity: ArityInfo::Unknown,
+        tailrec: false,
+        impure: false,
+    };
+
+    let mut assigns = vec![convert_assign(
+        span.clone(),
+        tuple.clone(),
+        value,
+        filename,
+        funcs,
+        global,
+        seen_funcs,
+    )];
+    for (i, name) in names.into_iter().enumerate() {
+        if name == "_" {
+            continue;
+        }
+
+        let element = SExpr::Index(
+            meta.clone(),
+            Box::new(SExpr::Symbol(meta.clone(), tuple.clone())),
+            i,
+            len,
+        );
+        assigns.push(convert_assign(
+            span.clone(),
+            name,
+            element,
+            filename,
+            funcs,
+            global,
+            seen_funcs,
+        ));
+    }
+
+    assigns
+}
+
 // Represents the purity of the next function.
 enum Purity {
     Pure,
@@ -1174,30 +1580,115 @@ enum Purity {
     Default,
 }
 
-// convert_ast_to_ir(Vec<Ast>) -> Ir
-// Converts a list of asts into ir.
-pub fn convert_ast_to_ir(
-    filename: &str,
-    contents: &str,
-    asts: Vec<Ast>,
-    ir: &mut Ir,
-) -> Result<(), Vec<IrError>> {
-    // Set up
-    let mut module = IrModule::new(filename, contents);
-    extract_types_to_ir(&asts, &mut module);
+// pin_globals(&mut SExpr, &mut Vec<String>, &HashMap<String, String>, &mut HashMap<String, IrFunction>) -> ()
+// Replaces references to globals defined so far with the functions they currently refer to, so
+// that redefining a global shadows it instead of changing the meaning of earlier definitions.
+// Globals that are not defined yet are left as symbols and refer to their last definition.
+fn pin_globals(
+    sexpr: &mut SExpr,
+    bound: &mut Vec<String>,
+    globals: &HashMap<String, String>,
+    funcs: &mut HashMap<String, IrFunction>,
+) {
+    match sexpr {
+        SExpr::Symbol(m, s) => {
+            if !bound.contains(s) {
+                if let Some(raw) = globals.get(s.as_str()) {
+                    *sexpr = SExpr::Function(m.clone(), raw.clone());
+                }
+            }
+        }
 
-    let mut seen_funcs = HashMap::new();
-    seen_funcs.insert(String::with_capacity(0), 0);
+        SExpr::Function(_, f) => {
+            // Only lambdas need to be checked, since globals have already been pinned
+            if let Some(mut func) = funcs.remove(f.as_str()) {
+                if !func.global {
+                    let len = bound.len();
+                    bound.extend(func.args.iter().map(|v| v.0.clone()));
+                    pin_globals(&mut func.body, bound, globals, funcs);
+                    bound.truncate(len);
+                }
+                funcs.insert(f.clone(), func);
+            }
+        }
+
+        SExpr::ExternalFunc(_, _, args) => {
+            for arg in args {
+                pin_globals(arg, bound, globals, funcs);
+            }
+        }
+
+        SExpr::Chain(_, l, r) => {
+            pin_globals(l, bound, globals, funcs);
+            pin_globals(r, bound, globals, funcs);
+        }
+
+        SExpr::As(_, v) => pin_globals(v, bound, globals, funcs),
+
+        SExpr::Application(_, func, args) => {
+            pin_globals(func, bound, globals, funcs);
+            for arg in args {
+                pin_globals(arg, bound, globals, funcs);
+            }
+        }
+
+        SExpr::Tuple(_, values) => {
+            for value in values {
+                pin_globals(value, bound, globals, funcs);
+            }
+        }
+
+        SExpr::Index(_, v, _, _) | SExpr::Field(_, v, _) => pin_globals(v, bound, globals, funcs),
+
+        SExpr::Record(_, fields) => {
+            for (_, value) in fields {
+                pin_globals(value, bound, globals, funcs);
+            }
+        }
+
+        SExpr::Assign(_, a, v) => {
+            pin_globals(v, bound, globals, funcs);
+            bound.push(a.clone());
+        }
+
+        SExpr::With(_, assigns, body) => {
+            let len = bound.len();
+            for assign in assigns {
+                pin_globals(assign, bound, globals, funcs);
+            }
+            pin_globals(body, bound, globals, funcs);
+            bound.truncate(len);
+        }
+
+        SExpr::Match(_, value, arms) => {
+            pin_globals(value, bound, globals, funcs);
+            for (_, arm, _) in arms {
+                pin_globals(arm, bound, globals, funcs);
+            }
+        }
+
+        SExpr::Empty(_) | SExpr::TypeAlias(_, _) => (),
+    }
+}
+
+// convert_asts(&mut IrModule, Vec<Ast>) -> Vec<IrError>
+// Converts top level asts and adds them to a module, returning any errors.
+fn convert_asts(module: &mut IrModule, asts: Vec<Ast>) -> Vec<IrError> {
+    let filename = &module.filename.clone();
+    let (asts, mut errors) = macros::expand_macros(asts, &mut module.macros, filename);
+    extract_types_to_ir(&asts, module);
 
-    let mut module_name = String::with_capacity(0);
-    let mut errors = vec![];
     let mut purity = Purity::Default;
+    let mut public = false;
+    let mut signature = None;
 
     let mut generic_uids = HashMap::new();
     let mut last_uid = 0;
 
     // Iterate over every ast node
     for ast in asts {
+        let newtype = matches!(ast, Ast::AssignNewtype(_, _, _));
+
         // Deal with the header
         if let Ast::Header(_, name, _exports, imports) = ast {
             // Get module name
@@ -1214,7 +1705,9 @@ pub fn convert_ast_to_ir(
                 full_name.push(v);
             }
             full_name.reverse();
-            module_name = full_name.join("::");
+            if !full_name.is_empty() {
+                module.name = full_name.join("::");
+            }
 
             // Deal with exports
             /*
@@ -1322,18 +1815,64 @@ pub fn convert_ast_to_ir(
                     }
                 }
             }
+        } else if let Ast::Pub(_) = ast {
+            // Visibility applies to the definition or type that follows it
+            public = true;
         } else if let Ast::Annotation(span, a) = ast {
             // Purity tags
             if a == "@pure" {
                 purity = Purity::Pure;
             } else if a == "@impure" {
                 purity = Purity::Impure;
+            } else if a == "@no_runtime" {
+                // Applies to the whole module rather than the next definition
+                module.no_runtime = Some(Location::new(span, filename));
             } else {
                 errors.push(IrError::UnsupportedAnnotation(
                     Location::new(span, filename),
                     a,
                 ));
             }
+        } else if let Ast::Signature(span, name, t) = ast {
+            // Signatures apply to the definition that immediately follows them
+            if let Some((loc, name, _)) = signature.take() {
+                errors.push(IrError::SignatureWithoutDefinition(loc, name));
+            }
+
+            let t = types::convert_ast_to_type(*t, filename, &mut generic_uids, &mut last_uid);
+            if let Type::UndeclaredTypeError(s) = t {
+                errors.push(IrError::InvalidType(s));
+            } else if let Type::DuplicateTypeError(s1, s2, t2) = t {
+                errors.push(IrError::DuplicateTypeInUnion(s1, s2, t2));
+            } else {
+                signature = Some((Location::new(span, filename), name, arc::new(t)));
+            }
+        } else if let Ast::AssignType(span, name, t) | Ast::AssignNewtype(span, name, t) = ast {
+            if public {
+                module.private_types.remove(&name);
+            } else {
+                module
+                    .private_types
+                    .insert(name.clone(), Location::new(span, filename));
+            }
+            public = false;
+
+            // Type declarations may refer to types declared later, since names are resolved when
+            // checking
+            let mut generic_uids = HashMap::new();
+            let t = types::convert_ast_to_type(*t, filename, &mut generic_uids, &mut last_uid);
+            if let Type::UndeclaredTypeError(s) = t {
+                errors.push(IrError::InvalidType(s));
+            } else if let Type::DuplicateTypeError(s1, s2, t2) = t {
+                errors.push(IrError::DuplicateTypeInUnion(s1, s2, t2));
+            } else if newtype {
+                // Newtypes are distinct from the type they wrap, but are erased after checking
+                module
+                    .types
+                    .insert(name.clone(), arc::new(Type::Newtype(name, arc::new(t))));
+            } else {
+                module.types.insert(name, arc::new(t));
+            }
         } else if let Ast::Extern(span, c, n, t) = ast {
             // let ts = t.get_span().clone();
             let mut last_uid = 0;
@@ -1370,43 +1909,137 @@ pub fn convert_ast_to_ir(
             }
 
             purity = Purity::Default;
+            public = false;
+        } else if let Some((span, construct)) = find_unsupported(&ast) {
+            // The signature belonged to the definition being skipped
+            errors.push(IrError::Unsupported(
+                Location::new(span, filename),
+                construct,
+            ));
+            signature = None;
+            purity = Purity::Default;
+            public = false;
         } else {
-            let v = convert_node(
-                ast,
-                filename,
-                &mut module.funcs,
-                true,
-                &mut seen_funcs,
-                &mut module.types,
-                &mut generic_uids,
-                &mut last_uid,
-            );
+            // Destructuring assigns several globals
+            let sexprs = if let Ast::AssignTuple(span, names, v) = ast {
+                convert_destructure(
+                    span,
+                    names,
+                    *v,
+                    filename,
+                    &mut module.funcs,
+                    true,
+                    &mut module.seen_funcs,
+                    &mut module.types,
+                    &mut generic_uids,
+                    &mut last_uid,
+                )
+            } else {
+                vec![convert_node(
+                    ast,
+                    filename,
+                    &mut module.funcs,
+                    true,
+                    &mut module.seen_funcs,
+                    &mut module.types,
+                    &mut generic_uids,
+                    &mut last_uid,
+                )]
+            };
+
+            for v in sexprs {
+                if let SExpr::Assign(_, a, v) = v {
+                    if let SExpr::Function(_, f) = *v {
+                        match signature.take() {
+                            Some((loc, name, t)) if name == a => {
+                                module.signatures.insert(f.clone(), (loc, t));
+                            }
+
+                            Some((loc, name, _)) => {
+                                errors.push(IrError::SignatureWithoutDefinition(loc, name))
+                            }
+
+                            None => (),
+                        }
+
+                        let mut func = module.funcs.remove(&f).unwrap();
+                        func.impure = matches!(purity, Purity::Impure);
 
-            if let SExpr::Assign(_, a, v) = v {
-                if let SExpr::Function(_, f) = *v {
-                    module.funcs.get_mut(&f).unwrap().impure = matches!(purity, Purity::Impure);
-                    module.globals.insert(a, f);
+                        // Functions with arguments may refer to themselves; values refer to the
+                        // previous definition of their name
+                        if !func.args.is_empty() {
+                            module.globals.insert(a.clone(), f.clone());
+                        }
+
+                        let mut bound = func.args.iter().map(|v| v.0.clone()).collect();
+                        pin_globals(
+                            &mut func.body,
+                            &mut bound,
+                            &module.globals,
+                            &mut module.funcs,
+                        );
+
+                        if public {
+                            module.private.remove(&a);
+                        } else {
+                            module.private.insert(a.clone(), func.loc.clone());
+                        }
+
+                        module.funcs.insert(f.clone(), func);
+                        module.globals.insert(a, f);
+                    }
                 }
             }
             purity = Purity::Default;
+            public = false;
         }
     }
 
+    if let Some((loc, name, _)) = signature {
+        errors.push(IrError::SignatureWithoutDefinition(loc, name));
+    }
+
+    errors
+}
+
+// extend_module(&mut IrModule, &str, Vec<Ast>) -> Result<(), Vec<IrError>>
+// Adds more top level asts to a module that has already been converted, so that only the new
+// definitions need to be converted and checked. The contents are the new contents of the whole
+// module, which the spans of the asts refer to.
+pub fn extend_module(
+    module: &mut IrModule,
+    contents: &str,
+    asts: Vec<Ast>,
+) -> Result<(), Vec<IrError>> {
+    module.contents = String::from(contents);
+    let errors = convert_asts(module, asts);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// convert_ast_to_ir(Vec<Ast>) -> Ir
+// Converts a list of asts into ir.
+pub fn convert_ast_to_ir(
+    filename: &str,
+    contents: &str,
+    asts: Vec<Ast>,
+    ir: &mut Ir,
+) -> Result<(), Vec<IrError>> {
+    // Set up
+    let mut module = IrModule::new(filename, contents);
+    let mut errors = convert_asts(&mut module, asts);
+
     // Check module name
-    if module_name.is_empty() {
-        module_name = filename
-            .split('/')
-            .last()
-            .unwrap()
-            .split('.')
-            .next()
-            .unwrap()
-            .to_string();
+    if module.name.is_empty() {
+        module.name = default_module_name(filename);
     }
-    module.name = module_name.clone();
 
     // Add module to ir root and error if already exists
-    match ir.modules.entry(module_name) {
+    match ir.modules.entry(module.name.clone()) {
         Entry::Occupied(e) => {
             if format!("{:?}", module) == format!("{:?}", e.get()) {
             } else {
@@ -1429,6 +2062,388 @@ pub fn convert_ast_to_ir(
     }
 }
 
+// default_module_name(&str) -> String
+// Gets the name of the module in a file without a module declaration.
+pub fn default_module_name(filename: &str) -> String {
+    filename
+        .split('/')
+        .last()
+        .unwrap()
+        .split('.')
+        .next()
+        .unwrap()
+        .to_string()
+}
+
+// The extensions of source files, tried in order when looking for an imported module.
+pub const SOURCE_EXTENSIONS: &[&str] = &["closey", "cly"];
+
+// import_paths(&str, &str) -> Vec<String>
+// Gets the paths of the files a module imported by the given file may be in. Modules are looked up
+// relative to the directory of the importing file, with `::` separating directories.
+pub fn import_paths(filename: &str, module: &str) -> Vec<String> {
+    let dir = Path::new(filename)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let base = module
+        .split("::")
+        .fold(dir.to_path_buf(), |path, v| path.join(v));
+    SOURCE_EXTENSIONS
+        .iter()
+        .map(|v| base.with_extension(v).to_string_lossy().into_owned())
+        .collect()
+}
+
+// resolve_imports(&mut Ir, &[String]) -> Result<(), Vec<IrError>>
+// Brings the definitions imported by the given modules into their scope. The functions of an
+// imported module are copied into the importing module under names qualified with the imported
+// module, and the imported names refer to the copies unless the importing module defines them
+// itself. Every definition can also be referred to by its qualified name, such as `a::f`. Modules
+// are resolved after the modules they import, so definitions can be imported through several
+// modules. Modules that are not given are assumed to be resolved already.
+pub fn resolve_imports(ir: &mut Ir, names: &[String]) -> Result<(), Vec<IrError>> {
+    let mut pending: HashSet<_> = names.iter().cloned().collect();
+    let mut errors = vec![];
+
+    for name in names {
+        resolve_module_imports(ir, name, &mut pending, &mut vec![], &mut errors);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// resolve_module_imports(&mut Ir, &str, &mut HashSet<String>, &mut Vec<(String, Location)>, &mut Vec<IrError>) -> ()
+// Resolves the imports of a module after resolving the modules it imports. The stack holds the
+// modules being resolved, which are the modules that would form a cycle if imported again, along
+// with the import each of them is following. An import cycle is reported with every import in it.
+fn resolve_module_imports(
+    ir: &mut Ir,
+    name: &str,
+    pending: &mut HashSet<String>,
+    stack: &mut Vec<(String, Location)>,
+    errors: &mut Vec<IrError>,
+) {
+    if !pending.contains(name) {
+        return;
+    }
+
+    let module = ir.modules.get(name).unwrap();
+    let mut imports: Vec<_> = module.imports.values().cloned().collect();
+    imports.sort_by_key(|v| v.loc.span.start);
+    let filename = module.filename.clone();
+
+    stack.push((String::from(name), Location::empty()));
+    for import in imports {
+        let paths = import_paths(&filename, &import.name);
+        let dep = match ir.modules.get(&import.name) {
+            Some(v) => v.name.clone(),
+            None => match ir.modules.values().find(|v| paths.contains(&v.filename)) {
+                Some(v) => v.name.clone(),
+                None => {
+                    errors.push(IrError::UnknownModule(import.loc, import.name));
+                    continue;
+                }
+            },
+        };
+
+        stack.last_mut().unwrap().1 = import.loc.clone();
+        if let Some(i) = stack.iter().position(|v| v.0 == dep) {
+            let cycle = stack[i..]
+                .iter()
+                .enumerate()
+                .map(|(j, (module, loc))| {
+                    let next = stack.get(i + j + 1).map_or(&dep, |v| &v.0);
+                    (loc.clone(), module.clone(), next.clone())
+                })
+                .collect();
+            errors.push(IrError::ImportCycle(cycle));
+            continue;
+        }
+
+        resolve_module_imports(ir, &dep, pending, stack, errors);
+        import_module(ir, name, &dep, &import, errors);
+    }
+    stack.pop();
+
+    pending.remove(name);
+}
+
+// import_module(&mut Ir, &str, &str, &IrImport, &mut Vec<IrError>) -> ()
+// Copies the functions and types of an imported module into the importing module and binds the
+// imported names.
+fn import_module(ir: &mut Ir, name: &str, dep: &str, import: &IrImport, errors: &mut Vec<IrError>) {
+    let dep = ir.modules.get(dep).unwrap();
+    let qualify = |raw: &str| format!("{}.{}", dep.name, raw);
+
+    // Globals are pinned to the final definitions of the imported module, so that names left
+    // unresolved in it are not looked up in the importing module
+    let mut funcs = dep.funcs.clone();
+    let raws: Vec<_> = funcs
+        .iter()
+        .filter(|v| v.1.global)
+        .map(|v| v.0.clone())
+        .collect();
+    for raw in raws {
+        let mut func = funcs.remove(&raw).unwrap();
+        let mut bound = func.args.iter().map(|v| v.0.clone()).collect();
+        pin_globals(&mut func.body, &mut bound, &dep.globals, &mut funcs);
+        funcs.insert(raw, func);
+    }
+
+    let funcs: Vec<_> = funcs
+        .into_iter()
+        .map(|(raw, mut func)| {
+            func.name = qualify(&raw);
+            qualify_functions(&mut func.body, &qualify);
+            (func.name.clone(), func)
+        })
+        .collect();
+    let signatures: Vec<_> = dep
+        .signatures
+        .iter()
+        .map(|(raw, v)| (qualify(raw), v.clone()))
+        .collect();
+    let types: Vec<_> = dep
+        .types
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+
+    // Private definitions are not bound, but referring to them is reported rather than left to
+    // fail as an unknown name
+    let module = ir.modules.get(name).unwrap();
+    let mut globals = vec![];
+    let mut private = HashMap::new();
+    for (global, raw) in dep.globals.iter() {
+        let qualified = format!("{}::{}", import.name, global);
+        let unqualified = import.imports.is_empty() || import.imports.contains_key(global);
+        if let Some(loc) = dep.private.get(global) {
+            private.insert(qualified, (global.clone(), loc.clone()));
+            if import.imports.is_empty() && !module.globals.contains_key(global) {
+                private.insert(global.clone(), (global.clone(), loc.clone()));
+            }
+            continue;
+        }
+
+        globals.push((qualified, qualify(raw)));
+        if unqualified {
+            globals.push((global.clone(), qualify(raw)));
+        }
+    }
+
+    let mut uses = vec![];
+    for (v, _) in import.imports.iter() {
+        if let Some(loc) = dep.private.get(v) {
+            uses.push((import.loc.clone(), v.clone(), loc.clone()));
+        }
+    }
+
+    for func in module.funcs.values() {
+        if func.global && func.loc.filename == module.filename {
+            let mut bound = func.args.iter().map(|v| v.0.clone()).collect();
+            find_private_uses(&func.body, &mut bound, &private, &module.funcs, &mut uses);
+        }
+    }
+
+    // Private types are only found in signatures and argument types, since the types stored for
+    // type declarations do not record where they were written
+    for (loc, t) in module
+        .signatures
+        .values()
+        .map(|v| (&v.0, &v.1))
+        .chain(
+            module
+                .funcs
+                .values()
+                .flat_map(|f| f.args.iter().map(move |a| (&f.loc, &a.1))),
+        )
+        .filter(|v| v.0.filename == module.filename)
+    {
+        let mut symbols = vec![];
+        t.get_symbols(&mut symbols);
+        for symbol in symbols {
+            if let Some(def) = dep.private_types.get(symbol) {
+                if !module.types.contains_key(symbol) {
+                    uses.push((loc.clone(), String::from(symbol), def.clone()));
+                }
+            }
+        }
+    }
+
+    uses.sort_by(|a, b| (a.0.span.start, &a.1).cmp(&(b.0.span.start, &b.1)));
+    uses.dedup_by(|a, b| a.0 == b.0 && a.1 == b.1);
+    for (loc, v, def) in uses {
+        errors.push(IrError::PrivateItem(loc, def, import.name.clone(), v));
+    }
+
+    let mut missing: Vec<_> = import
+        .imports
+        .keys()
+        .filter(|v| !dep.globals.contains_key(*v))
+        .cloned()
+        .collect();
+    missing.sort();
+    for v in missing {
+        errors.push(IrError::UnknownImport(
+            import.loc.clone(),
+            import.name.clone(),
+            v,
+        ));
+    }
+
+    let module = ir.modules.get_mut(name).unwrap();
+    module.funcs.extend(funcs);
+    module.signatures.extend(signatures);
+    for (k, v) in types {
+        module.types.entry(k).or_insert(v);
+    }
+    for (k, v) in globals {
+        module.globals.entry(k).or_insert(v);
+    }
+}
+
+// find_private_uses(&SExpr, &mut Vec<String>, &HashMap<String, (String, Location)>, &HashMap<String, IrFunction>, &mut Vec<(Location, String, Location)>) -> ()
+// Finds every symbol that is not bound by an argument or let expression and names a private
+// definition of another module, recording where it is used, the name of the definition, and where
+// the definition is.
+fn find_private_uses(
+    sexpr: &SExpr,
+    bound: &mut Vec<String>,
+    private: &HashMap<String, (String, Location)>,
+    funcs: &HashMap<String, IrFunction>,
+    uses: &mut Vec<(Location, String, Location)>,
+) {
+    match sexpr {
+        SExpr::Symbol(m, s) => {
+            if !bound.contains(s) {
+                if let Some((name, loc)) = private.get(s) {
+                    uses.push((m.loc.clone(), name.clone(), loc.clone()));
+                }
+            }
+        }
+
+        SExpr::Function(_, f) => {
+            // Lambdas are checked with their arguments bound
+            if let Some(func) = funcs.get(f) {
+                if !func.global {
+                    let len = bound.len();
+                    bound.extend(func.args.iter().map(|v| v.0.clone()));
+                    find_private_uses(&func.body, bound, private, funcs, uses);
+                    bound.truncate(len);
+                }
+            }
+        }
+
+        SExpr::ExternalFunc(_, _, args) | SExpr::Tuple(_, args) => {
+            for arg in args {
+                find_private_uses(arg, bound, private, funcs, uses);
+            }
+        }
+
+        SExpr::Chain(_, l, r) => {
+            find_private_uses(l, bound, private, funcs, uses);
+            find_private_uses(r, bound, private, funcs, uses);
+        }
+
+        SExpr::As(_, v) | SExpr::Index(_, v, _, _) | SExpr::Field(_, v, _) => {
+            find_private_uses(v, bound, private, funcs, uses)
+        }
+
+        SExpr::Application(_, func, args) => {
+            find_private_uses(func, bound, private, funcs, uses);
+            for arg in args {
+                find_private_uses(arg, bound, private, funcs, uses);
+            }
+        }
+
+        SExpr::Record(_, fields) => {
+            for (_, value) in fields {
+                find_private_uses(value, bound, private, funcs, uses);
+            }
+        }
+
+        SExpr::Assign(_, a, v) => {
+            find_private_uses(v, bound, private, funcs, uses);
+            bound.push(a.clone());
+        }
+
+        SExpr::With(_, assigns, body) => {
+            let len = bound.len();
+            for assign in assigns {
+                find_private_uses(assign, bound, private, funcs, uses);
+            }
+            find_private_uses(body, bound, private, funcs, uses);
+            bound.truncate(len);
+        }
+
+        SExpr::Match(_, value, arms) => {
+            find_private_uses(value, bound, private, funcs, uses);
+            for (_, arm, _) in arms {
+                find_private_uses(arm, bound, private, funcs, uses);
+            }
+        }
+
+        SExpr::Empty(_) | SExpr::TypeAlias(_, _) => (),
+    }
+}
+
+// qualify_functions(&mut SExpr, &impl Fn(&str) -> String) -> ()
+// Renames every function referred to by an expression.
+fn qualify_functions(sexpr: &mut SExpr, qualify: &impl Fn(&str) -> String) {
+    match sexpr {
+        SExpr::Function(_, f) => *f = qualify(f),
+
+        SExpr::ExternalFunc(_, _, args) | SExpr::Tuple(_, args) => {
+            for arg in args {
+                qualify_functions(arg, qualify);
+            }
+        }
+
+        SExpr::Chain(_, l, r) => {
+            qualify_functions(l, qualify);
+            qualify_functions(r, qualify);
+        }
+
+        SExpr::As(_, v)
+        | SExpr::Assign(_, _, v)
+        | SExpr::Index(_, v, _, _)
+        | SExpr::Field(_, v, _) => qualify_functions(v, qualify),
+
+        SExpr::Application(_, func, args) => {
+            qualify_functions(func, qualify);
+            for arg in args {
+                qualify_functions(arg, qualify);
+            }
+        }
+
+        SExpr::Record(_, fields) => {
+            for (_, value) in fields {
+                qualify_functions(value, qualify);
+            }
+        }
+
+        SExpr::With(_, assigns, body) => {
+            for assign in assigns {
+                qualify_functions(assign, qualify);
+            }
+            qualify_functions(body, qualify);
+        }
+
+        SExpr::Match(_, value, arms) => {
+            qualify_functions(value, qualify);
+            for (_, arm, _) in arms {
+                qualify_functions(arm, qualify);
+            }
+        }
+
+        SExpr::Empty(_) | SExpr::TypeAlias(_, _) | SExpr::Symbol(_, _) => (),
+    }
+}
+
 /*
 pub fn convert_library_header(
     filename: &str,
@@ -0,0 +1,422 @@
+use logos::Span;
+use std::collections::HashMap;
+
+use super::ir::{IrError, Location};
+use super::parser::Ast;
+
+// The number of macros that may be expanded inside each other before expansion stops.
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+// Represents a macro definition.
+#[derive(Debug, Clone)]
+pub struct Macro {
+    pub loc: Location,
+    pub params: Vec<String>,
+    pub body: Ast,
+}
+
+// expand_macros(Vec<Ast>, &mut HashMap<String, Macro>, &str) -> (Vec<Ast>, Vec<IrError>)
+// Removes macro definitions from a list of top level asts and expands every use of a macro after
+// its definition. Macros already in the map, such as those defined by earlier REPL entries, are
+// also expanded.
+pub fn expand_macros(
+    asts: Vec<Ast>,
+    macros: &mut HashMap<String, Macro>,
+    filename: &str,
+) -> (Vec<Ast>, Vec<IrError>) {
+    let mut expanded = vec![];
+    let mut errors = vec![];
+    let mut last_uid = 0;
+
+    for ast in asts {
+        if let Ast::Macro(span, name, params, body) = ast {
+            macros.insert(
+                name,
+                Macro {
+                    loc: Location::new(span, filename),
+                    params,
+                    body: *body,
+                },
+            );
+        } else {
+            let mut expander = Expander {
+                macros,
+                filename,
+                errors: &mut errors,
+                bound: vec![],
+                last_uid: &mut last_uid,
+            };
+            expanded.push(expander.expand(ast, 0));
+        }
+    }
+
+    (expanded, errors)
+}
+
+// Expands the macros used in an ast.
+struct Expander<'a> {
+    macros: &'a HashMap<String, Macro>,
+    filename: &'a str,
+    errors: &'a mut Vec<IrError>,
+
+    // Local names, which hide macros with the same name
+    bound: Vec<String>,
+
+    // Used to give locals bound by macro bodies unique names
+    last_uid: &'a mut usize,
+}
+
+impl<'a> Expander<'a> {
+    // expand(&mut self, Ast, usize) -> Ast
+    // Expands every macro used in an ast.
+    fn expand(&mut self, ast: Ast, depth: usize) -> Ast {
+        match ast {
+            Ast::Symbol(span, name) if self.is_macro(&name) => {
+                self.expand_use(span, name, vec![], depth)
+            }
+
+            Ast::Application(span, func, args) => match *func {
+                Ast::Symbol(_, name) if self.is_macro(&name) => {
+                    self.expand_use(span, name, args, depth)
+                }
+
+                func => Ast::Application(
+                    span,
+                    Box::new(self.expand(func, depth)),
+                    args.into_iter().map(|v| self.expand(v, depth)).collect(),
+                ),
+            },
+
+            Ast::Lambda(span, args, body) => {
+                let len = self.bound.len();
+                self.bound.extend(args.iter().map(|v| v.0.clone()));
+                let body = self.expand(*body, depth);
+                self.bound.truncate(len);
+                Ast::Lambda(span, args, Box::new(body))
+            }
+
+            Ast::AssignFunction(span, name, args, body) => {
+                let len = self.bound.len();
+                self.bound.push(name.clone());
+                self.bound.extend(args.iter().map(|v| v.0.clone()));
+                let body = self.expand(*body, depth);
+                self.bound.truncate(len);
+                Ast::AssignFunction(span, name, args, Box::new(body))
+            }
+
+            Ast::With(span, assigns, body) => {
+                // Let bindings stay in scope until the end of the body
+                let len = self.bound.len();
+                let assigns = assigns
+                    .into_iter()
+                    .map(|v| {
+                        let v = self.expand(v, depth);
+                        match &v {
+                            Ast::Assign(_, name, _)
+                            | Ast::AssignTyped(_, name, _, _)
+                            | Ast::AssignFunction(_, name, _, _) => self.bound.push(name.clone()),
+                            Ast::AssignTuple(_, names, _) => {
+                                self.bound.extend(names.iter().cloned())
+                            }
+                            _ => (),
+                        }
+                        v
+                    })
+                    .collect();
+                let body = self.expand(*body, depth);
+                self.bound.truncate(len);
+                Ast::With(span, assigns, Box::new(body))
+            }
+
+            Ast::Assign(span, name, value) => {
+                Ast::Assign(span, name, Box::new(self.expand(*value, depth)))
+            }
+
+            Ast::AssignTyped(span, name, _type, value) => {
+                Ast::AssignTyped(span, name, _type, Box::new(self.expand(*value, depth)))
+            }
+
+            Ast::AssignTuple(span, names, value) => {
+                Ast::AssignTuple(span, names, Box::new(self.expand(*value, depth)))
+            }
+
+            Ast::Walrus(span, name, value) => {
+                Ast::Walrus(span, name, Box::new(self.expand(*value, depth)))
+            }
+
+            Ast::Prefix(span, op, value) => {
+                Ast::Prefix(span, op, Box::new(self.expand(*value, depth)))
+            }
+
+            Ast::Infix(span, op, left, right) => Ast::Infix(
+                span,
+                op,
+                Box::new(self.expand(*left, depth)),
+                Box::new(self.expand(*right, depth)),
+            ),
+
+            Ast::As(span, value, _type) => {
+                Ast::As(span, Box::new(self.expand(*value, depth)), _type)
+            }
+
+            Ast::List(span, values) => Ast::List(
+                span,
+                values.into_iter().map(|v| self.expand(v, depth)).collect(),
+            ),
+
+            Ast::Tuple(span, values) => Ast::Tuple(
+                span,
+                values.into_iter().map(|v| self.expand(v, depth)).collect(),
+            ),
+
+            Ast::Record(span, fields) => Ast::Record(
+                span,
+                fields
+                    .into_iter()
+                    .map(|(name, v)| (name, self.expand(v, depth)))
+                    .collect(),
+            ),
+
+            Ast::Field(span, value, name) => {
+                Ast::Field(span, Box::new(self.expand(*value, depth)), name)
+            }
+
+            Ast::Match(span, value, arms) => Ast::Match(
+                span,
+                Box::new(self.expand(*value, depth)),
+                arms.into_iter()
+                    .map(|(pattern, arm)| (pattern, self.expand(arm, depth)))
+                    .collect(),
+            ),
+
+            ast => ast,
+        }
+    }
+
+    // is_macro(&self, &str) -> bool
+    // Checks whether a name refers to a macro rather than a local.
+    fn is_macro(&self, name: &str) -> bool {
+        self.macros.contains_key(name) && !self.bound.iter().any(|v| v == name)
+    }
+
+    // expand_use(&mut self, Span, String, Vec<Ast>, usize) -> Ast
+    // Expands a macro applied to some arguments. Extra arguments are applied to the expansion.
+    fn expand_use(&mut self, span: Span, name: String, mut args: Vec<Ast>, depth: usize) -> Ast {
+        let macros = self.macros;
+        let mac = macros.get(&name).unwrap();
+        let loc = Location::new(span.clone(), self.filename);
+
+        if depth >= MAX_EXPANSION_DEPTH {
+            self.errors.push(IrError::RecursiveMacro(loc, name));
+            return Ast::Symbol(span, String::from("<error>"));
+        }
+
+        if args.len() < mac.params.len() {
+            self.errors
+                .push(IrError::MacroArity(loc, name, mac.params.len(), args.len()));
+            return Ast::Symbol(span, String::from("<error>"));
+        }
+
+        let rest = args.split_off(mac.params.len());
+        let params = mac.params.iter().cloned().zip(args).collect();
+        let body = mac.body.clone();
+        let expansion = self.substitute(body, &params, &HashMap::new(), &span);
+        let expansion = self.expand(expansion, depth + 1);
+
+        if rest.is_empty() {
+            expansion
+        } else {
+            Ast::Application(
+                span,
+                Box::new(expansion),
+                rest.into_iter().map(|v| self.expand(v, depth)).collect(),
+            )
+        }
+    }
+
+    // substitute(&mut self, Ast, &HashMap<String, Ast>, &HashMap<String, String>, &Span) -> Ast
+    // Replaces the parameters of a macro body with their arguments. Locals bound in the body are
+    // renamed so that they cannot capture names used in the arguments, and every node from the
+    // body is given the span of the use so that errors in it point to where the macro was used.
+    // Arguments keep their own spans.
+    fn substitute(
+        &mut self,
+        ast: Ast,
+        params: &HashMap<String, Ast>,
+        renames: &HashMap<String, String>,
+        span: &Span,
+    ) -> Ast {
+        let span = span.clone();
+        match ast {
+            Ast::Symbol(_, name) => match (renames.get(&name), params.get(&name)) {
+                (Some(renamed), _) => Ast::Symbol(span, renamed.clone()),
+                (None, Some(arg)) => arg.clone(),
+                (None, None) => Ast::Symbol(span, name),
+            },
+
+            Ast::Lambda(_, args, body) => {
+                let mut renames = renames.clone();
+                let args = args
+                    .into_iter()
+                    .map(|(name, _type)| {
+                        let renamed = self.fresh_name(&name);
+                        renames.insert(name, renamed.clone());
+                        (renamed, self.substitute(_type, params, &renames, &span))
+                    })
+                    .collect();
+                let body = self.substitute(*body, params, &renames, &span);
+                Ast::Lambda(span, args, Box::new(body))
+            }
+
+            Ast::With(_, assigns, body) => {
+                let mut renames = renames.clone();
+                let assigns = assigns
+                    .into_iter()
+                    .map(|v| match v {
+                        Ast::Assign(_, name, value) => {
+                            let value = self.substitute(*value, params, &renames, &span);
+                            let renamed = self.fresh_name(&name);
+                            renames.insert(name, renamed.clone());
+                            Ast::Assign(span.clone(), renamed, Box::new(value))
+                        }
+
+                        Ast::AssignTyped(_, name, _type, value) => {
+                            let _type = self.substitute(*_type, params, &renames, &span);
+                            let value = self.substitute(*value, params, &renames, &span);
+                            let renamed = self.fresh_name(&name);
+                            renames.insert(name, renamed.clone());
+                            Ast::AssignTyped(
+                                span.clone(),
+                                renamed,
+                                Box::new(_type),
+                                Box::new(value),
+                            )
+                        }
+
+                        Ast::AssignTuple(_, names, value) => {
+                            let value = self.substitute(*value, params, &renames, &span);
+                            let names = names
+                                .into_iter()
+                                .map(|name| {
+                                    let renamed = self.fresh_name(&name);
+                                    renames.insert(name, renamed.clone());
+                                    renamed
+                                })
+                                .collect();
+                            Ast::AssignTuple(span.clone(), names, Box::new(value))
+                        }
+
+                        Ast::AssignFunction(_, name, args, body) => {
+                            let renamed = self.fresh_name(&name);
+                            renames.insert(name, renamed.clone());
+                            let lambda = Ast::Lambda(span.clone(), args, body);
+                            match self.substitute(lambda, params, &renames, &span) {
+                                Ast::Lambda(_, args, body) => {
+                                    Ast::AssignFunction(span.clone(), renamed, args, body)
+                                }
+                                _ => unreachable!("always a lambda"),
+                            }
+                        }
+
+                        v => self.substitute(v, params, &renames, &span),
+                    })
+                    .collect();
+                let body = self.substitute(*body, params, &renames, &span);
+                Ast::With(span, assigns, Box::new(body))
+            }
+
+            Ast::Application(_, func, args) => Ast::Application(
+                span.clone(),
+                Box::new(self.substitute(*func, params, renames, &span)),
+                args.into_iter()
+                    .map(|v| self.substitute(v, params, renames, &span))
+                    .collect(),
+            ),
+
+            Ast::Prefix(_, op, value) => Ast::Prefix(
+                span.clone(),
+                op,
+                Box::new(self.substitute(*value, params, renames, &span)),
+            ),
+
+            Ast::Infix(_, op, left, right) => Ast::Infix(
+                span.clone(),
+                op,
+                Box::new(self.substitute(*left, params, renames, &span)),
+                Box::new(self.substitute(*right, params, renames, &span)),
+            ),
+
+            Ast::As(_, value, _type) => Ast::As(
+                span.clone(),
+                Box::new(self.substitute(*value, params, renames, &span)),
+                Box::new(self.substitute(*_type, params, renames, &span)),
+            ),
+
+            Ast::Walrus(_, name, value) => Ast::Walrus(
+                span.clone(),
+                name,
+                Box::new(self.substitute(*value, params, renames, &span)),
+            ),
+
+            Ast::List(_, values) => Ast::List(
+                span.clone(),
+                values
+                    .into_iter()
+                    .map(|v| self.substitute(v, params, renames, &span))
+                    .collect(),
+            ),
+
+            Ast::Tuple(_, values) => Ast::Tuple(
+                span.clone(),
+                values
+                    .into_iter()
+                    .map(|v| self.substitute(v, params, renames, &span))
+                    .collect(),
+            ),
+
+            Ast::Record(_, fields) => Ast::Record(
+                span.clone(),
+                fields
+                    .into_iter()
+                    .map(|(name, v)| (name, self.substitute(v, params, renames, &span)))
+                    .collect(),
+            ),
+
+            Ast::Field(_, value, name) => Ast::Field(
+                span.clone(),
+                Box::new(self.substitute(*value, params, renames, &span)),
+                name,
+            ),
+
+            Ast::Match(_, value, arms) => Ast::Match(
+                span.clone(),
+                Box::new(self.substitute(*value, params, renames, &span)),
+                arms.into_iter()
+                    .map(|(pattern, arm)| {
+                        (
+                            self.substitute(pattern, params, renames, &span),
+                            self.substitute(arm, params, renames, &span),
+                        )
+                    })
+                    .collect(),
+            ),
+
+            Ast::Int(_, v) => Ast::Int(span, v),
+            Ast::Float(_, v) => Ast::Float(span, v),
+            Ast::Word(_, v) => Ast::Word(span, v),
+            Ast::Char(_, v) => Ast::Char(span, v),
+            Ast::String(_, v) => Ast::String(span, v),
+            Ast::Generic(_, v) => Ast::Generic(span, v),
+            Ast::Enum(_, v) => Ast::Enum(span, v),
+
+            ast => ast,
+        }
+    }
+
+    // fresh_name(&mut self, &str) -> String
+    // Creates a name for a local bound by a macro body that cannot be written in source code.
+    fn fresh_name(&mut self, name: &str) -> String {
+        *self.last_uid += 1;
+        format!("{}#{}", name, self.last_uid)
+    }
+}
@@ -1,14 +1,59 @@
-use logos::{Lexer, Logos, Span};
+use logos::{Filter, Lexer, Logos, Span};
+use std::fmt::Display;
+
+use crate::messages::Catalog;
+
+// block_comment(&mut Lexer<Token>) -> Filter<()>
+// Skips a block comment, which may contain other block comments. Unclosed comments are kept as a
+// token so that they can be reported.
+fn block_comment(lex: &mut Lexer<Token>) -> Filter<()> {
+    let rest = lex.remainder().as_bytes();
+    let mut depth = 1;
+    let mut i = 0;
+
+    while i < rest.len() {
+        match &rest[i..] {
+            [b'{', b'-', ..] => {
+                depth += 1;
+                i += 2;
+            }
+
+            [b'-', b'}', ..] => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    lex.bump(i);
+                    return Filter::Skip;
+                }
+            }
+
+            _ => i += 1,
+        }
+    }
+
+    lex.bump(rest.len());
+    Filter::Emit(())
+}
 
 // convert_chars(&str) -> String
-// Converts escaped characters into an unescaped string.
+// Converts escaped characters into an unescaped string. `\u{...}` is replaced by the unicode
+// character with the given hexadecimal code point.
 fn convert_chars(s: &str, off: usize) -> String {
     let mut iter = s[off..s.len() - off].chars();
     let mut s = String::new();
 
     while let Some(c) = iter.next() {
         if c == '\\' {
-            match iter.next().unwrap() {
+            // Raw strings may end with a backslash, which is kept as is
+            let c = match iter.next() {
+                Some(v) => v,
+                None => {
+                    s.push('\\');
+                    break;
+                }
+            };
+
+            match c {
                 '\\' => s.push('\\'),
                 '\"' => s.push('\"'),
                 '\'' => s.push('\''),
@@ -16,6 +61,14 @@ fn convert_chars(s: &str, off: usize) -> String {
                 'r' => s.push('\r'),
                 't' => s.push('\t'),
                 '0' => s.push('\0'),
+                'u' => match unicode_escape(iter.as_str()) {
+                    Some((c, len)) => {
+                        s.push(c);
+                        iter.nth(len - 1);
+                    }
+
+                    None => s.push_str("\\u"),
+                },
                 c => {
                     s.push('\\');
                     s.push(c)
@@ -29,6 +82,20 @@ fn convert_chars(s: &str, off: usize) -> String {
     s
 }
 
+// unicode_escape(&str) -> Option<(char, usize)>
+// Parses the `{...}` part of a `\u{...}` escape, returning the character and the length of the
+// escape after the `\u`.
+fn unicode_escape(s: &str) -> Option<(char, usize)> {
+    let end = s.strip_prefix('{')?.find('}')? + 1;
+    let digits = &s[1..end];
+    if digits.is_empty() || digits.len() > 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let c = char::from_u32(u32::from_str_radix(digits, 16).ok()?)?;
+    Some((c, end + 1))
+}
+
 // The tokens parsed by the lexer.
 #[derive(Logos, PartialEq, Debug, Clone)]
 pub enum Token {
@@ -59,9 +126,12 @@ pub enum Token {
     Whitespace,
 
     #[regex(r"#[^\n]*", logos::skip)]
-    #[regex(r"\{-([^-]*-+)+\}", logos::skip)]
     Comment,
 
+    // Only unclosed block comments become tokens
+    #[token("{-", block_comment)]
+    BlockComment,
+
     // Error
     #[error]
     Error,
@@ -77,6 +147,7 @@ pub enum Token {
     Comma,
 
     #[token("\\")]
+    #[token("λ")]
     Backslash,
 
     #[token(".")]
@@ -120,14 +191,14 @@ pub enum Token {
     })]
     Word(u64),
 
-    #[regex(r#"'([^\\']|\\[nrt'"0])'"#, |lex| convert_chars(lex.slice(), 1).bytes().next().unwrap())]
+    #[regex(r#"'([^\\']|\\[nrt'"0])'"#, |lex| convert_chars(lex.slice(), 1).bytes().next())]
     Char(u8),
 
-    #[regex(r"'[a-zA-Z_0-9]+", |lex| lex.slice()[1..].to_owned())]
+    #[regex(r"'[_\p{L}\p{N}]+", |lex| lex.slice()[1..].to_owned())]
     Generic(String),
 
-    // Symbols (variables and stuff)
-    #[regex(r"[a-zA-Z_][a-zA-Z0-9_']*")]
+    // Symbols (variables and stuff). `λ` is excluded since it is an alias for `\`.
+    #[regex(r"[_\p{L}--λ][_\p{L}\p{N}'--λ]*")]
     Symbol,
 
     // Annotations
@@ -144,9 +215,11 @@ pub enum Token {
     PlusArrow,
 
     #[token("->")]
+    #[token("→")]
     RightArrow,
 
     #[token("=>")]
+    #[token("⇒")]
     ThiccArrow,
 
     // Keywords
@@ -168,6 +241,9 @@ pub enum Token {
     #[token("type")]
     Type,
 
+    #[token("newtype")]
+    Newtype,
+
     #[token("ptr")]
     Pointer,
 
@@ -177,6 +253,15 @@ pub enum Token {
     #[token("to")]
     To,
 
+    #[token("macro")]
+    Macro,
+
+    #[token("where")]
+    Where,
+
+    #[token("pub")]
+    Pub,
+
     Unreachable,
 }
 
@@ -299,9 +384,21 @@ pub enum Ast {
     // Annotations (@pure, @impure, @memoize, etc)
     Annotation(Span, String),
 
+    // Visibility (pub)
+    Pub(Span),
+
     // Lists
     List(Span, Vec<Ast>),
 
+    // Tuples
+    Tuple(Span, Vec<Ast>),
+
+    // Records
+    Record(Span, Vec<(String, Ast)>),
+
+    // Field access (r.x)
+    Field(Span, Box<Ast>, String),
+
     // Function Application
     Application(Span, Box<Ast>, Vec<Ast>),
 
@@ -317,12 +414,24 @@ pub enum Ast {
     // Assignments
     Assign(Span, String, Box<Ast>),
 
+    // Tuple destructuring
+    AssignTuple(Span, Vec<String>, Box<Ast>),
+
     // Assignments with types
     AssignTyped(Span, String, Box<Ast>, Box<Ast>),
 
     // Assignment of types
     AssignType(Span, String, Box<Ast>),
 
+    // Declaration of distinct types wrapping another type
+    AssignNewtype(Span, String, Box<Ast>),
+
+    // Type signatures
+    Signature(Span, String, Box<Ast>),
+
+    // Macro definitions
+    Macro(Span, String, Vec<String>, Box<Ast>),
+
     // Assignment of functions
     AssignFunction(Span, String, Vec<(String, Ast)>, Box<Ast>),
 
@@ -357,17 +466,25 @@ impl Ast {
             | Self::Char(s, _)
             | Self::String(s, _)
             | Self::List(s, _)
+            | Self::Tuple(s, _)
+            | Self::Record(s, _)
+            | Self::Field(s, _, _)
             | Self::Symbol(s, _)
             | Self::Generic(s, _)
             | Self::Enum(s, _)
             | Self::Annotation(s, _)
+            | Self::Pub(s)
             | Self::Application(s, _, _)
             | Self::Prefix(s, _, _)
             | Self::Infix(s, _, _, _)
             | Self::As(s, _, _)
             | Self::Assign(s, _, _)
+            | Self::AssignTuple(s, _, _)
             | Self::AssignTyped(s, _, _, _)
             | Self::AssignType(s, _, _)
+            | Self::AssignNewtype(s, _, _)
+            | Self::Signature(s, _, _)
+            | Self::Macro(s, _, _, _)
             | Self::AssignFunction(s, _, _, _)
             | Self::Match(s, _, _)
             | Self::Lambda(s, _, _)
@@ -379,7 +496,20 @@ impl Ast {
             | Self::LibHeader(s, _, _)
             | Self::Extern(s, _, _, _) => s.clone(),
 
-            Self::Empty => panic!("uwu moment"),
+            // Empty asts are placeholders that are never written in code
+            Self::Empty => Span { start: 0, end: 0 },
+        }
+    }
+
+    // qualified_name(&self) -> Option<String>
+    // Gets the name of a symbol or of symbols joined by `::`, such as `a::b`.
+    pub fn qualified_name(&self) -> Option<String> {
+        match self {
+            Self::Symbol(_, v) => Some(v.clone()),
+            Self::Infix(_, op, l, r) if op == "::" => {
+                Some(format!("{}::{}", l.qualified_name()?, r.qualified_name()?))
+            }
+            _ => None,
         }
     }
 }
@@ -387,17 +517,28 @@ impl Ast {
 #[derive(Debug)]
 pub struct ParseError {
     pub span: Span,
-    pub msg: String,
+
+    // The key of the message in the message catalog, and the arguments substituted into it
+    pub key: &'static str,
+    pub args: Vec<String>,
     fatal: bool,
 }
 
 impl ParseError {
+    // message(&self, &Catalog) -> String
+    // Gets the message of the error from a message catalog.
+    pub fn message(&self, catalog: &Catalog) -> String {
+        let args: Vec<&dyn Display> = self.args.iter().map(|v| v as &dyn Display).collect();
+        catalog.message(self.key, &args)
+    }
+
     // empty<T>() -> Result<T, ParseError>
     // Creates an empty ParseError.
     fn empty<T>() -> Result<T, ParseError> {
         Err(ParseError {
             span: Span { start: 0, end: 0 },
-            msg: String::with_capacity(0),
+            key: "",
+            args: vec![],
             fatal: false,
         })
     }
@@ -421,14 +562,15 @@ macro_rules! call_func {
 // Calls a function and returns a fatal error if unsuccessful.
 macro_rules! call_func_fatal
 {
-    ($func: ident, $parser: ident, $format: literal $(,$vs: expr),*) => {
+    ($func: ident, $parser: ident, $key: literal $(,$vs: expr),*) => {
         match $func($parser)
         {
             Ok(v) => v,
             Err(e) if e.fatal => return Err(e),
             Err(_) => return Err(ParseError {
                 span: $parser.span(),
-                msg: format!($format $(,$vs),*),
+                key: $key,
+                args: vec![$($vs.to_string()),*],
                 fatal: true
             })
         }
@@ -451,7 +593,7 @@ macro_rules! call_optional {
 // Consumes a token without saving it, returning if an error was encountered.
 macro_rules! consume_nosave
 {
-    ($parser: ident, $token: ident, $state: ident, $fatal: literal, $format: literal $(,$vs: expr),*) => {
+    ($parser: ident, $token: ident, $state: ident, $fatal: literal, $key: literal $(,$vs: expr),*) => {
         match $parser.peek()
         {
             Some((Token::$token, _)) => {
@@ -463,7 +605,8 @@ macro_rules! consume_nosave
                 $parser.return_state($state);
                 return Err(ParseError {
                     span,
-                    msg: format!($format $(,$vs),*),
+                    key: $key,
+                args: vec![$($vs.to_string()),*],
                     fatal: $fatal
                 });
             }
@@ -475,7 +618,7 @@ macro_rules! consume_nosave
 // Consumes a token and saves it, returning if an error was encountered.
 macro_rules! consume_save
 {
-    ($parser: ident, $token: ident, $state: ident, $fatal: literal, $format: literal $(,$vs: expr),*) => {
+    ($parser: ident, $token: ident, $state: ident, $fatal: literal, $key: literal $(,$vs: expr),*) => {
         match $parser.peek()
         {
             Some((Token::$token, s)) => {
@@ -489,7 +632,8 @@ macro_rules! consume_save
                 $parser.return_state($state);
                 return Err(ParseError {
                     span,
-                    msg: format!($format $(,$vs),*),
+                    key: $key,
+                args: vec![$($vs.to_string()),*],
                     fatal: $fatal
                 })
             }
@@ -524,8 +668,7 @@ macro_rules! infixl_op {
                 newline($parser);
 
                 // Get right hand side
-                let right =
-                    call_func_fatal!($subfunc, $parser, "Expected value after infix operator");
+                let right = call_func_fatal!($subfunc, $parser, "syntax.operand");
 
                 // Build ast
                 left = Ast::Infix(
@@ -579,8 +722,7 @@ macro_rules! infixr_op {
                 newline($parser);
 
                 // Get right hand side
-                let right =
-                    call_func_fatal!($subfunc, $parser, "Expected value after infix operator");
+                let right = call_func_fatal!($subfunc, $parser, "syntax.operand");
                 last = right.get_span();
 
                 #[allow(unused_assignments)]
@@ -668,7 +810,7 @@ fn value(parser: &mut Parser) -> Result<Ast, ParseError> {
     }
 
     // Get token
-    let (token, _span) = match parser.peek() {
+    let (token, span) = match parser.peek() {
         Some(v) => v,
         None => return ParseError::empty(),
     };
@@ -728,25 +870,72 @@ fn value(parser: &mut Parser) -> Result<Ast, ParseError> {
         parser.next();
         Ok(Ast::False(span))
 
-    // Parenthesised expressions
+    // Parenthesised expressions and tuples
     } else */
     if let Token::LParen = token {
-        // Get value
         let state = parser.save_state();
         parser.next();
-        newline(parser);
-        let value = match expression(parser) {
-            Ok(v) => v,
-            Err(e) => {
-                parser.return_state(state);
-                return Err(e);
+        let mut values = vec![];
+
+        loop {
+            // Get value
+            newline(parser);
+            let value = if values.is_empty() {
+                match expression(parser) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        parser.return_state(state);
+                        return Err(e);
+                    }
+                }
+            } else {
+                call_func_fatal!(expression, parser, "syntax.value_after_comma")
+            };
+
+            // Get type ascription
+            newline(parser);
+            let value = if let Some((Token::Colon, _)) = parser.peek() {
+                parser.next();
+                newline(parser);
+                let _type = call_func_fatal!(type_expr, parser, "syntax.type_after_colon");
+                newline(parser);
+
+                Ast::As(
+                    Span {
+                        start: value.get_span().start,
+                        end: _type.get_span().end,
+                    },
+                    Box::new(value),
+                    Box::new(_type),
+                )
+            } else {
+                value
+            };
+            values.push(value);
+
+            // Get comma
+            match parser.peek() {
+                Some((Token::Comma, _)) => {
+                    parser.next();
+                }
+
+                _ => break,
             }
-        };
+        }
 
         // Get right parenthesis
-        newline(parser);
-        consume_nosave!(parser, RParen, state, true, "");
-        Ok(value)
+        let (_, end) = consume_save!(parser, RParen, state, true, "syntax.rparen");
+        if values.len() == 1 {
+            Ok(values.pop().unwrap())
+        } else {
+            Ok(Ast::Tuple(
+                Span {
+                    start: span.start,
+                    end: end.end,
+                },
+                values,
+            ))
+        }
 
     // Not a value
     } else {
@@ -754,12 +943,45 @@ fn value(parser: &mut Parser) -> Result<Ast, ParseError> {
     }
 }
 
+// field_access(&mut Parser) -> Result<Ast, ParseError>
+// Parses a value followed by any number of field accesses (r.x).
+fn field_access(parser: &mut Parser) -> Result<Ast, ParseError> {
+    let mut value = value(parser)?;
+
+    while let Some((Token::Dot, _)) = parser.peek() {
+        let state = parser.save_state();
+        parser.next();
+
+        match parser.peek() {
+            Some((Token::Symbol, span)) => {
+                let field = parser.slice();
+                parser.next();
+                value = Ast::Field(
+                    Span {
+                        start: value.get_span().start,
+                        end: span.end,
+                    },
+                    Box::new(value),
+                    field,
+                );
+            }
+
+            _ => {
+                parser.return_state(state);
+                break;
+            }
+        }
+    }
+
+    Ok(value)
+}
+
 fn _as(parser: &mut Parser) -> Result<Ast, ParseError> {
-    let value = value(parser)?;
+    let value = field_access(parser)?;
 
     if let Some((Token::Colon, _)) = parser.peek() {
         parser.next();
-        let _type = call_func_fatal!(type_expr, parser, "Expected type after `:`");
+        let _type = call_func_fatal!(type_expr, parser, "syntax.type_after_colon");
 
         Ok(Ast::As(
             Span {
@@ -829,13 +1051,7 @@ fn list(parser: &mut Parser) -> Result<Ast, ParseError> {
     }
 
     newline(parser);
-    let (_, end) = consume_save!(
-        parser,
-        RBrack,
-        state,
-        true,
-        "Expected `]` after end of list"
-    );
+    let (_, end) = consume_save!(parser, RBrack, state, true, "syntax.list_end");
 
     Ok(Ast::List(
         Span {
@@ -846,6 +1062,88 @@ fn list(parser: &mut Parser) -> Result<Ast, ParseError> {
     ))
 }
 
+// record(&mut Parser) -> Result<Ast, ParseError>
+// Parses a record ({x = a, y = b}).
+fn record(parser: &mut Parser) -> Result<Ast, ParseError> {
+    let state = parser.save_state();
+    let (_, start) = consume_save!(parser, LBrace, state, false, "");
+    let mut fields = vec![];
+
+    loop {
+        if !fields.is_empty() {
+            match parser.peek() {
+                Some((Token::Comma, _)) => {
+                    parser.next();
+                }
+
+                _ => break,
+            }
+        }
+
+        newline(parser);
+        let (name, span) = match parser.peek() {
+            Some((Token::Symbol, span)) => (parser.slice(), span),
+            _ if fields.is_empty() => break,
+            _ => {
+                return Err(ParseError {
+                    span: parser.span(),
+                    key: "syntax.field_after_comma",
+                    args: vec![],
+                    fatal: true,
+                })
+            }
+        };
+        parser.next();
+        check_duplicate_field(&fields, &name, span)?;
+
+        consume_nosave!(parser, Assign, state, true, "syntax.record_assign");
+        newline(parser);
+        let value = call_func_fatal!(expression, parser, "syntax.value_after_assign");
+        fields.push((name, value));
+        newline(parser);
+    }
+
+    // Check that there is at least one field
+    newline(parser);
+    if fields.is_empty() {
+        return Err(ParseError {
+            span: parser.span(),
+            key: "syntax.field_after_brace",
+            args: vec![],
+            fatal: true,
+        });
+    }
+
+    let (_, end) = consume_save!(parser, RBrace, state, true, "syntax.record_end");
+
+    Ok(Ast::Record(
+        Span {
+            start: start.start,
+            end: end.end,
+        },
+        fields,
+    ))
+}
+
+// check_duplicate_field(&[(String, Ast)], &str, Span) -> Result<(), ParseError>
+// Returns an error if a field of a record or record type is given twice.
+fn check_duplicate_field(
+    fields: &[(String, Ast)],
+    name: &str,
+    span: Span,
+) -> Result<(), ParseError> {
+    if fields.iter().any(|v| v.0 == name) {
+        Err(ParseError {
+            span,
+            key: "syntax.duplicate_field",
+            args: vec![name.to_string()],
+            fatal: true,
+        })
+    } else {
+        Ok(())
+    }
+}
+
 // lambda(&mut Parser) -> Result<Ast, ParseError>
 // Parses a lambda function.
 fn lambda(parser: &mut Parser) -> Result<Ast, ParseError> {
@@ -882,18 +1180,19 @@ fn lambda(parser: &mut Parser) -> Result<Ast, ParseError> {
         parser.return_state(state);
         return Err(ParseError {
             span: parser.span(),
-            msg: String::from("Expected argument after `lambda`"),
+            key: "syntax.lambda_arg",
+            args: vec![],
             fatal: true,
         });
     }
 
     // Get the assign operator
     let slice = parser.slice();
-    consume_nosave!(parser, Dot, state, true, "Expected `.`, got `{}`", slice);
+    consume_nosave!(parser, Dot, state, true, "syntax.lambda_dot", slice);
 
     // Get the value
     newline(parser);
-    let body = call_func_fatal!(apply_op, parser, "Expected function body after `=`");
+    let body = call_func_fatal!(apply_op, parser, "syntax.function_body");
 
     Ok(Ast::Lambda(
         Span {
@@ -912,17 +1211,17 @@ fn matchy(parser: &mut Parser) -> Result<Ast, ParseError> {
     let (_, span) = consume_save!(parser, Match, state, false, "");
 
     // Get value
-    let value = call_func_fatal!(apply_op, parser, "Expected expression after `match`");
+    let value = call_func_fatal!(apply_op, parser, "syntax.match_value");
     let mut arms = vec![];
     newline(parser);
 
     while let Some((Token::To, _)) = parser.peek() {
         parser.next();
-        let _type = call_func_fatal!(type_expr, parser, "Expected type after `to`");
+        let _type = call_func_fatal!(type_expr, parser, "syntax.type_after_to");
         newline(parser);
-        consume_nosave!(parser, ThiccArrow, state, true, "Expected `=>` after type");
+        consume_nosave!(parser, ThiccArrow, state, true, "syntax.match_arrow");
         newline(parser);
-        let value = call_func_fatal!(apply_op, parser, "Expected expression after `=>`");
+        let value = call_func_fatal!(apply_op, parser, "syntax.match_arm");
         arms.push((_type, value));
         newline(parser);
     }
@@ -934,7 +1233,8 @@ fn matchy(parser: &mut Parser) -> Result<Ast, ParseError> {
                 start: span.start,
                 end: value.get_span().end,
             },
-            msg: String::from("Expected `to` after match value"),
+            key: "syntax.match_to",
+            args: vec![],
             fatal: true,
         });
     }
@@ -958,6 +1258,8 @@ fn expression_values(parser: &mut Parser) -> Result<Ast, ParseError> {
         Ok(lambda)
     } else if let Ok(list) = call_optional!(list, parser) {
         Ok(list)
+    } else if let Ok(record) = call_optional!(record, parser) {
+        Ok(record)
     } else if let Ok(matchy) = call_optional!(matchy, parser) {
         Ok(matchy)
     } else {
@@ -985,6 +1287,14 @@ fn annotation(parser: &mut Parser) -> Result<Ast, ParseError> {
     Ok(Ast::Annotation(span, annotation))
 }
 
+// visibility(&mut Parser) -> Result<Ast, ParseError>
+// Parses a `pub` marker, which makes the definition or type after it visible to other modules.
+fn visibility(parser: &mut Parser) -> Result<Ast, ParseError> {
+    let state = parser.save_state();
+    let (_, span) = consume_save!(parser, Pub, state, false, "");
+    Ok(Ast::Pub(span))
+}
+
 // assignment_raw(&mut Parser) -> Result<Ast, ParseError>
 // Parses an assignment without any types or arguments.
 fn assignment_raw(parser: &mut Parser) -> Result<Ast, ParseError> {
@@ -997,7 +1307,8 @@ fn assignment_raw(parser: &mut Parser) -> Result<Ast, ParseError> {
 
     // Get the value
     newline(parser);
-    let value = call_func_fatal!(expression, parser, "Expected value after `=`");
+    let value = call_func_fatal!(expression, parser, "syntax.value_after_assign");
+    let value = where_clause(parser, value)?;
 
     Ok(Ast::Assign(
         Span {
@@ -1010,7 +1321,7 @@ fn assignment_raw(parser: &mut Parser) -> Result<Ast, ParseError> {
 }
 
 // type_symbol(&mut Parser) -> Result<Ast, ParseError>
-// Parses a type symbol or parenthesised type.
+// Parses a type symbol, parenthesised type, tuple type, or record type.
 fn type_symbol(parser: &mut Parser) -> Result<Ast, ParseError> {
     let (token, span) = match parser.peek() {
         Some(v) => v,
@@ -1029,25 +1340,112 @@ fn type_symbol(parser: &mut Parser) -> Result<Ast, ParseError> {
         parser.next();
         Ok(value)
 
-    // Parenthesised types
+    // Parenthesised types and tuple types
     } else if let Token::LParen = token {
-        // Get value
         let state = parser.save_state();
         parser.next();
-        newline(parser);
+        let mut values = vec![];
 
-        let value = match type_expr(parser) {
-            Ok(v) => v,
-            Err(e) => {
-                parser.return_state(state);
-                return Err(e);
+        loop {
+            // Get value
+            newline(parser);
+            let value = if values.is_empty() {
+                match type_expr(parser) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        parser.return_state(state);
+                        return Err(e);
+                    }
+                }
+            } else {
+                call_func_fatal!(type_expr, parser, "syntax.type_after_comma")
+            };
+            values.push(value);
+
+            // Get comma
+            newline(parser);
+            match parser.peek() {
+                Some((Token::Comma, _)) => {
+                    parser.next();
+                }
+
+                _ => break,
             }
-        };
+        }
 
         // Get right parenthesis
-        newline(parser);
-        consume_nosave!(parser, RParen, state, true, "Expected right parenthesis");
-        Ok(value)
+        let (_, end) = consume_save!(parser, RParen, state, true, "syntax.rparen");
+        if values.len() == 1 {
+            Ok(values.pop().unwrap())
+        } else {
+            Ok(Ast::Tuple(
+                Span {
+                    start: span.start,
+                    end: end.end,
+                },
+                values,
+            ))
+        }
+
+    // Record types
+    } else if let Token::LBrace = token {
+        let state = parser.save_state();
+        parser.next();
+        let mut fields = vec![];
+
+        loop {
+            // Get field
+            newline(parser);
+            let (name, span) = match parser.peek() {
+                Some((Token::Symbol, span)) => (parser.slice(), span),
+                _ if fields.is_empty() => break,
+                _ => {
+                    return Err(ParseError {
+                        span: parser.span(),
+                        key: "syntax.field_after_comma",
+                        args: vec![],
+                        fatal: true,
+                    })
+                }
+            };
+            parser.next();
+            check_duplicate_field(&fields, &name, span)?;
+
+            consume_nosave!(parser, Colon, state, true, "syntax.record_type_colon");
+            newline(parser);
+            let _type = call_func_fatal!(type_expr, parser, "syntax.type_after_colon");
+            fields.push((name, _type));
+
+            // Get comma
+            newline(parser);
+            match parser.peek() {
+                Some((Token::Comma, _)) => {
+                    parser.next();
+                }
+
+                _ => break,
+            }
+        }
+
+        // Check that there is at least one field
+        if fields.is_empty() {
+            return Err(ParseError {
+                span: parser.span(),
+                key: "syntax.field_after_brace",
+                args: vec![],
+                fatal: true,
+            });
+        }
+
+        // Get right brace
+        let (_, end) = consume_save!(parser, RBrace, state, true, "syntax.rbrace");
+        Ok(Ast::Record(
+            Span {
+                start: span.start,
+                end: end.end,
+            },
+            fields,
+        ))
 
     // Not a value
     } else {
@@ -1061,7 +1459,7 @@ fn type_tagged(parser: &mut Parser) -> Result<Ast, ParseError> {
     let state = parser.save_state();
     let s = call_func!(symbol, parser, state);
     consume_nosave!(parser, Colon, state, false, "");
-    let t = call_func_fatal!(type_symbol, parser, "Expected type after `:`");
+    let t = call_func_fatal!(type_symbol, parser, "syntax.type_after_colon");
 
     Ok(Ast::Infix(
         Span {
@@ -1097,31 +1495,44 @@ fn type_expr(parser: &mut Parser) -> Result<Ast, ParseError> {
 }
 
 // type_assignment(&mut Parser) -> Result<Ast, ParseError>
-// Parses an assignment of a type.
+// Parses an assignment of a type, or a declaration of a newtype.
 fn type_assignment(parser: &mut Parser) -> Result<Ast, ParseError> {
-    // Get type keyword
+    // Get type or newtype keyword
     let state = parser.save_state();
-    let (_, span) = consume_save!(parser, Type, state, false, "");
+    let (newtype, span) = match parser.peek() {
+        Some((Token::Type, s)) => (false, s),
+        Some((Token::Newtype, s)) => (true, s),
+        _ => {
+            return Err(ParseError {
+                span: parser.span(),
+                key: "",
+                args: vec![],
+                fatal: false,
+            })
+        }
+    };
+    parser.next();
 
     // Get name of type
-    let (name, _) = consume_save!(parser, Symbol, state, true, "Expected symbol after type");
+    let (name, _) = consume_save!(parser, Symbol, state, true, "syntax.type_alias_name");
 
     // Get assignment operator
-    consume_nosave!(parser, Assign, state, true, "Expected `=` after type name");
+    consume_nosave!(parser, Assign, state, true, "syntax.type_alias_assign");
     newline(parser);
 
     // Get type
-    let _type = call_func_fatal!(type_expr, parser, "Expected type after `=`");
+    let _type = call_func_fatal!(type_expr, parser, "syntax.type_after_assign");
 
     // Successfully return
-    Ok(Ast::AssignType(
-        Span {
-            start: span.start,
-            end: _type.get_span().end,
-        },
-        name,
-        Box::new(_type),
-    ))
+    let span = Span {
+        start: span.start,
+        end: _type.get_span().end,
+    };
+    if newtype {
+        Ok(Ast::AssignNewtype(span, name, Box::new(_type)))
+    } else {
+        Ok(Ast::AssignType(span, name, Box::new(_type)))
+    }
 }
 
 // declaration(&mut Parser) -> Result<(Span, String, Ast), ParseError>
@@ -1135,11 +1546,61 @@ fn declaration(parser: &mut Parser) -> Result<(Span, String, Ast), ParseError> {
     consume_nosave!(parser, Colon, state, false, "");
 
     // Get the type
-    let type_val = call_func_fatal!(type_expr, parser, "Expected type after `:`");
+    let type_val = call_func_fatal!(type_expr, parser, "syntax.type_after_colon");
 
     Ok((span, name, type_val))
 }
 
+// signature(&mut Parser) -> Result<Ast, ParseError>
+// Parses a type signature for a top level definition.
+fn signature(parser: &mut Parser) -> Result<Ast, ParseError> {
+    let (span, name, _type) = declaration(parser)?;
+
+    Ok(Ast::Signature(
+        Span {
+            start: span.start,
+            end: _type.get_span().end,
+        },
+        name,
+        Box::new(_type),
+    ))
+}
+
+// macro_def(&mut Parser) -> Result<Ast, ParseError>
+// Parses a macro definition.
+fn macro_def(parser: &mut Parser) -> Result<Ast, ParseError> {
+    // Get the macro keyword and name
+    let state = parser.save_state();
+    let span = parser.span();
+    consume_nosave!(parser, Macro, state, false, "");
+    let (name, _) = consume_save!(parser, Symbol, state, true, "syntax.macro_name");
+
+    // Get parameters
+    let mut params = vec![];
+    while let Some((Token::Symbol, _)) = parser.peek() {
+        params.push(parser.slice());
+        parser.next();
+    }
+
+    // Get the assign operator
+    let slice = parser.slice();
+    consume_nosave!(parser, Assign, state, true, "syntax.assign", slice);
+
+    // Get the body
+    newline(parser);
+    let body = call_func_fatal!(expression, parser, "syntax.macro_body");
+
+    Ok(Ast::Macro(
+        Span {
+            start: span.start,
+            end: body.get_span().end,
+        },
+        name,
+        params,
+        Box::new(body),
+    ))
+}
+
 // assignment_func(&mut Parser) -> Result<Ast, ParseError>
 // Parses an assignment for a function.
 fn assignment_func(parser: &mut Parser) -> Result<Ast, ParseError> {
@@ -1180,11 +1641,12 @@ fn assignment_func(parser: &mut Parser) -> Result<Ast, ParseError> {
 
     // Get the assign operator
     let slice = parser.slice();
-    consume_nosave!(parser, Assign, state, true, "Expected `=`, got `{}`", slice);
+    consume_nosave!(parser, Assign, state, true, "syntax.assign", slice);
 
     // Get the value
     newline(parser);
-    let value = call_func_fatal!(expression, parser, "Expected function body after `=`");
+    let value = call_func_fatal!(expression, parser, "syntax.function_body");
+    let value = where_clause(parser, value)?;
 
     Ok(Ast::AssignFunction(
         Span {
@@ -1197,11 +1659,108 @@ fn assignment_func(parser: &mut Parser) -> Result<Ast, ParseError> {
     ))
 }
 
+// assignment_tuple(&mut Parser) -> Result<Ast, ParseError>
+// Parses an assignment that destructures a tuple, such as `(a, b) = value`.
+fn assignment_tuple(parser: &mut Parser) -> Result<Ast, ParseError> {
+    // Get the names
+    let state = parser.save_state();
+    let (_, span) = consume_save!(parser, LParen, state, false, "");
+    let mut names = vec![];
+    loop {
+        let (name, _) = consume_save!(parser, Symbol, state, false, "");
+        names.push(name);
+
+        match parser.peek() {
+            Some((Token::Comma, _)) => {
+                parser.next();
+            }
+
+            _ => break,
+        }
+    }
+    consume_nosave!(parser, RParen, state, false, "");
+
+    // A single name in parentheses is not a tuple
+    if names.len() < 2 {
+        parser.return_state(state);
+        return ParseError::empty();
+    }
+
+    // Get the assign operator
+    consume_nosave!(parser, Assign, state, false, "");
+
+    // Get the value
+    newline(parser);
+    let value = call_func_fatal!(expression, parser, "syntax.value_after_assign");
+    let value = where_clause(parser, value)?;
+
+    Ok(Ast::AssignTuple(
+        Span {
+            start: span.start,
+            end: value.get_span().end,
+        },
+        names,
+        Box::new(value),
+    ))
+}
+
+// where_clause(&mut Parser, Ast) -> Result<Ast, ParseError>
+// Parses an optional where clause after the value of a definition, such as `f x = g y where y = x`.
+// Bindings are separated by commas and scoped to the value, which becomes the body of a with
+// expression.
+fn where_clause(parser: &mut Parser, value: Ast) -> Result<Ast, ParseError> {
+    let state = parser.save_state();
+    newline(parser);
+    match parser.peek() {
+        Some((Token::Where, _)) => {
+            parser.next();
+        }
+
+        _ => {
+            parser.return_state(state);
+            return Ok(value);
+        }
+    }
+
+    // Get assignments
+    let mut assigns = vec![];
+    loop {
+        newline(parser);
+        let assign = call_func_fatal!(assignment, parser, "syntax.where_definition");
+        assigns.push(assign);
+
+        // Comma
+        let state = parser.save_state();
+        newline(parser);
+        match parser.peek() {
+            Some((Token::Comma, _)) => {
+                parser.next();
+            }
+
+            _ => {
+                parser.return_state(state);
+                break;
+            }
+        }
+    }
+
+    Ok(Ast::With(
+        Span {
+            start: value.get_span().start,
+            end: assigns.last().unwrap().get_span().end,
+        },
+        assigns,
+        Box::new(value),
+    ))
+}
+
 // assignment(&mut Parser) -> Result<Ast, ParseError>
 // Parses an assignment.
 fn assignment(parser: &mut Parser) -> Result<Ast, ParseError> {
     if let Ok(typed) = call_optional!(assignment_raw, parser) {
         Ok(typed)
+    } else if let Ok(tuple) = call_optional!(assignment_tuple, parser) {
+        Ok(tuple)
     } else {
         assignment_func(parser)
     }
@@ -1236,7 +1795,7 @@ fn with(parser: &mut Parser) -> Result<Ast, ParseError> {
     }
 
     // Get the body
-    consume_nosave!(parser, In, state, true, "Expected `in` after let bindings");
+    consume_nosave!(parser, In, state, true, "syntax.let_in");
     newline(parser);
     let body = call_func!(expression, parser, state);
 
@@ -1250,202 +1809,95 @@ fn with(parser: &mut Parser) -> Result<Ast, ParseError> {
     ))
 }
 
-/*
 // import(&mut Parser) -> Result<Ast, ParseError>
-// Parses an import statement.
+// Parses an import statement, such as `import a::b` or `import a::b (c, d)`. Without a list of
+// names, every definition of the module is imported.
 fn import(parser: &mut Parser) -> Result<Ast, ParseError> {
     let state = parser.save_state();
     let (_, span) = consume_save!(parser, Import, state, false, "");
-    let start = span.start;
-
-    let name = call_func_fatal!(access_member, parser, "Expected module name after `import`");
+    let name = call_func_fatal!(access_member, parser, "syntax.import_module");
     let mut end = name.get_span().end;
 
-    let qualified = !matches!(
-        parser.peek(),
-        Some((Token::LParen, _))
-    );
-
-    if qualified {
-        let mut alias = String::with_capacity(0);
-        if let Some((Token::As, _)) = parser.peek() {
-            parser.next();
-            let (a, s) = consume_save!(parser, Symbol, state, true, "Expected alias after `as`");
-            end = s.end;
-            alias = a
-        }
-
-        Ok(Ast::QualifiedImport(
-            Span { start, end },
-            Box::new(name),
-            alias,
-        ))
-    } else {
-        let mut imports = vec![];
+    let mut imports = vec![];
+    if let Some((Token::LParen, _)) = parser.peek() {
         parser.next();
         loop {
             newline(parser);
-            if parser.peek().is_none() {
-                parser.return_state(state);
-                return Err(ParseError {
-                    span: parser.span(),
-                    msg: String::from(
-                        "Expected imported item or right parenthesis, got end of file",
-                    ),
-                    fatal: true,
-                });
-            }
+            let (import, _) = consume_save!(parser, Symbol, state, true, "syntax.imported_name");
+            imports.push(import);
 
-            if imports.is_empty() {
-                if let Some((Token::Mul, _)) = parser.peek() {
+            newline(parser);
+            match parser.peek() {
+                Some((Token::Comma, _)) => {
                     parser.next();
-                    consume_nosave!(parser, RParen, state, true, "Expected right parenthesis");
-                    break;
                 }
-            } else {
-                match parser.peek() {
-                    Some((Token::Comma, _)) => {
-                        parser.next();
-                    },
 
-                    Some((Token::RParen, _)) => {
-                        parser.next();
-                        break;
-                    }
-
-                    _ => {
-                        return Err(ParseError {
-                            span: parser.span(),
-                            msg: String::from("Expected comma or right parenthesis"),
-                            fatal: true,
-                        });
-                    }
+                Some((Token::RParen, s)) => {
+                    end = s.end;
+                    parser.next();
+                    break;
                 }
-            }
-
-            let (token, span) = parser.peek().unwrap();
-            end = span.end;
 
-            match token {
-                Token::Symbol => imports.push(parser.slice()),
                 _ => {
-                    parser.return_state(state);
                     return Err(ParseError {
                         span: parser.span(),
-                        msg: String::from("Expected imported item"),
+                        key: "syntax.import_separator",
+                        args: vec![],
                         fatal: true,
-                    });
+                    })
                 }
             }
-
-            parser.next();
         }
-
-        parser.next();
-        Ok(Ast::Import(Span { start, end }, Box::new(name), imports))
     }
+
+    Ok(Ast::Import(
+        Span {
+            start: span.start,
+            end,
+        },
+        Box::new(name),
+        imports,
+    ))
 }
 
 // header(&mut Parser) -> Result<Ast, ParseError>
-// Parses a header entry.
+// Parses the header of a file, which is an optional module declaration followed by imports.
 fn header(parser: &mut Parser) -> Result<Ast, ParseError> {
     let state = parser.save_state();
-    let (_, span) = consume_save!(parser, Module, state, false, "");
-    let start = span.start;
-    let name = call_func_fatal!(access_member, parser, "Expected module name after `module`");
-    let mut end = name.get_span().end;
+    let start = parser.span().start;
+    let mut end = start;
 
-    let mut exports = vec![];
-    newline(parser);
-    let mut comma = false;
-    if let Some((Token::LParen, _)) = parser.peek() {
+    let name = if let Some((Token::Module, _)) = parser.peek() {
         parser.next();
-
-        loop {
-            newline(parser);
-            if comma {
-                match parser.peek() {
-                    Some((Token::Comma, _)) => {
-                        parser.next();
-                    }
-                    Some((Token::RParen, _)) => break,
-                    _ => (),
-                }
-            } else {
-                comma = true;
-            }
-
-            newline(parser);
-            if parser.peek().is_none() {
-                let span = parser.span();
-                parser.return_state(state);
-                return Err(ParseError {
-                    span,
-                    msg: String::from(
-                        "Expected exported item or right parenthesis, got end of file",
-                    ),
-                    fatal: true,
-                });
-            }
-
-            let (token, span) = parser.peek().unwrap();
-            end = span.end;
-
-            match token {
-                Token::RParen => break,
-                Token::Symbol => exports.push(match declaration(parser) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        parser.return_state(state);
-                        return Err(e);
-                    }
-                }),
-
-                Token::Type => {
-                    parser.next();
-                    if let Some((Token::Symbol, _)) = parser.peek() {
-                        exports.push((parser.span(), parser.slice(), Ast::Empty));
-                        parser.next();
-                    } else {
-                        return Err(ParseError {
-                            span: parser.span(),
-                            msg: String::from("Expected type name after `type`"),
-                            fatal: true,
-                        });
-                    }
-                }
-
-                _ => {
-                    let span = parser.span();
-                    parser.return_state(state);
-                    return Err(ParseError {
-                        span,
-                        msg: String::from("Expected exported item or right parenthesis"),
-                        fatal: true,
-                    });
-                }
-            }
-        }
-
-        parser.next();
-    }
-
-    newline(parser);
+        let name = call_func_fatal!(access_member, parser, "syntax.module_name");
+        end = name.get_span().end;
+        newline(parser);
+        name
+    } else {
+        Ast::Empty
+    };
 
     let mut imports = vec![];
     while let Ok(v) = call_optional!(import, parser) {
+        end = v.get_span().end;
         imports.push(v);
         newline(parser);
     }
 
+    if name == Ast::Empty && imports.is_empty() {
+        parser.return_state(state);
+        return ParseError::empty();
+    }
+
     Ok(Ast::Header(
         Span { start, end },
         Box::new(name),
-        exports,
+        vec![],
         imports,
     ))
 }
 
+/*
 // externy(&mut Parser) -> Result<Ast, ParseError>
 // Parses an external function declaration.
 fn externy(parser: &mut Parser) -> Result<Ast, ParseError> {
@@ -1457,7 +1909,8 @@ fn externy(parser: &mut Parser) -> Result<Ast, ParseError> {
     } else {
         return Err(ParseError {
             span: parser.span(),
-            msg: String::from("Expected string literal after `extern`"),
+            key: "syntax.extern_string",
+            args: vec![],
             fatal: true,
         });
     };
@@ -1469,16 +1922,16 @@ fn externy(parser: &mut Parser) -> Result<Ast, ParseError> {
         Symbol,
         state,
         true,
-        "Expected symbol after external function declaration"
+        "syntax.extern_name"
     );
     consume_nosave!(
         parser,
         Colon,
         state,
         true,
-        "Expected `:` after foreign function declaration"
+        "syntax.extern_colon"
     );
-    let _type = call_func_fatal!(type_expr, parser, "Expected type after `:`");
+    let _type = call_func_fatal!(type_expr, parser, "syntax.type_after_colon");
 
     Ok(Ast::Extern(
         Span {
@@ -1492,68 +1945,241 @@ fn externy(parser: &mut Parser) -> Result<Ast, ParseError> {
 }
 */
 
-// parse(&str) -> Result<Ast, ParseError>
-// Parses curly code.
-pub fn parse(s: &str) -> Result<Vec<Ast>, ParseError> {
+// header_names(&str) -> (Option<String>, Vec<(Span, String)>)
+// Gets the name of the module declared by the header of some code and the names of the modules it
+// imports, along with the spans of the imports. Only the header is parsed, so errors in the rest
+// of the code are ignored.
+pub fn header_names(s: &str) -> (Option<String>, Vec<(Span, String)>) {
+    let mut parser = Parser::new(s);
+    newline(&mut parser);
+    match header(&mut parser) {
+        Ok(Ast::Header(_, name, _, imports)) => (
+            name.qualified_name(),
+            imports
+                .into_iter()
+                .filter_map(|v| match v {
+                    Ast::Import(span, name, _) => Some((span, name.qualified_name()?)),
+                    _ => None,
+                })
+                .collect(),
+        ),
+
+        _ => (None, vec![]),
+    }
+}
+
+// tokenize(&str) -> Vec<(Token, Span)>
+// Splits code into tokens along with their spans. Whitespace and comments are skipped.
+pub fn tokenize(s: &str) -> Vec<(Token, Span)> {
+    Token::lexer(s).spanned().collect()
+}
+
+// is_incomplete(&str) -> bool
+// Checks whether the code is clearly unfinished, ie it has unclosed brackets or block comments,
+// ends with an operator, or has a `let` without an `in` or a `match` without a `to`.
+pub fn is_incomplete(s: &str) -> bool {
+    let mut depth = 0isize;
+    let mut lets = 0usize;
+    let mut matches = 0usize;
+    let mut last = None;
+
+    for (token, _) in tokenize(s) {
+        match token {
+            Token::LParen | Token::LBrack | Token::LBrace => depth += 1,
+            Token::RParen | Token::RBrack | Token::RBrace => depth -= 1,
+            Token::Let => lets += 1,
+            Token::In => lets = lets.saturating_sub(1),
+            Token::Match => matches += 1,
+            Token::To => matches = matches.saturating_sub(1),
+            Token::Newline => continue,
+            Token::BlockComment => return true,
+            _ => (),
+        }
+        last = Some(token);
+    }
+
+    depth > 0
+        || lets > 0
+        || matches > 0
+        || matches!(
+            last,
+            Some(
+                Token::Assign
+                    | Token::Operator
+                    | Token::Comma
+                    | Token::Backslash
+                    | Token::Dot
+                    | Token::Colon
+                    | Token::ColonColon
+                    | Token::Semicolon
+                    | Token::Dollar
+                    | Token::Bar
+                    | Token::PlusArrow
+                    | Token::RightArrow
+                    | Token::ThiccArrow
+                    | Token::Let
+                    | Token::In
+                    | Token::Match
+                    | Token::To
+                    | Token::Macro
+                    | Token::Where
+            )
+        )
+}
+
+// top_level(&mut Parser) -> Result<Ast, ParseError>
+// Parses one top level item.
+fn top_level(parser: &mut Parser) -> Result<Ast, ParseError> {
+    if let Ok(annotation) = call_optional!(annotation, parser) {
+        Ok(annotation)
+    } else if let Ok(public) = call_optional!(visibility, parser) {
+        Ok(public)
+    } else if let Ok(assign) = call_optional!(assignment, parser) {
+        Ok(assign)
+    } else if let Ok(signature) = call_optional!(signature, parser) {
+        Ok(signature)
+    } else if let Ok(macro_def) = call_optional!(macro_def, parser) {
+        Ok(macro_def)
+    } else {
+        match type_assignment(parser) {
+            Ok(v) => Ok(v),
+            Err(e) if e.fatal => Err(e),
+            Err(_) => {
+                let peeked = if parser.peek().is_some() {
+                    parser.slice()
+                } else {
+                    String::from("eof")
+                };
+                Err(ParseError {
+                    span: parser.span(),
+                    key: "syntax.unexpected",
+                    args: vec![peeked.to_string()],
+                    fatal: true,
+                })
+            }
+        }
+    }
+    /*
+    } else if let Ok(_type) = call_optional!(type_assignment, parser) {
+        Ok(_type)
+    } else {
+        match externy(parser) {
+            Ok(v) => Ok(v),
+            Err(e) if e.fatal => Err(e),
+            Err(_) => {
+                let peeked = if parser.peek().is_some() {
+                    parser.slice()
+                } else {
+                    String::from("eof")
+                };
+                Err(ParseError {
+                    span: parser.span(),
+                    key: "syntax.unexpected",
+                    args: vec![peeked.to_string()],
+                    fatal: true,
+                })
+            }
+        }
+    */
+}
+
+// synchronise(&mut Parser, usize, &ParseError) -> ()
+// Skips past the line that caused an error so that parsing can continue. The parser moves to where
+// the error was found and then skips tokens until it reaches a newline or a `type`, `macro`, `pub`,
+// or annotation outside of brackets, or just past an unmatched closing bracket.
+fn synchronise(parser: &mut Parser, start: usize, error: &ParseError) {
+    parser.return_state(start);
+    while let Some((_, span)) = parser.peek() {
+        if span.start >= error.span.start {
+            break;
+        }
+        parser.next();
+    }
+
+    // Always skip at least one token so that the same error is not found again
+    if parser.save_state() == start {
+        parser.next();
+    }
+
+    let mut depth = 0usize;
+    while let Some((token, _)) = parser.peek() {
+        match token {
+            Token::Newline
+            | Token::Type
+            | Token::Newtype
+            | Token::Macro
+            | Token::Pub
+            | Token::Annotation
+                if depth == 0 =>
+            {
+                break
+            }
+
+            Token::LParen | Token::LBrack | Token::LBrace => depth += 1,
+
+            Token::RParen | Token::RBrack | Token::RBrace if depth == 0 => {
+                parser.next();
+                break;
+            }
+
+            Token::RParen | Token::RBrack | Token::RBrace => depth -= 1,
+
+            _ => (),
+        }
+        parser.next();
+    }
+}
+
+// parse(&str) -> Result<Vec<Ast>, Vec<ParseError>>
+// Parses curly code. After a syntax error the parser skips to the next line and carries on, so
+// every syntax error in the code is returned.
+pub fn parse(s: &str) -> Result<Vec<Ast>, Vec<ParseError>> {
+    if let Some((_, span)) = tokenize(s).into_iter().find(|v| v.0 == Token::BlockComment) {
+        return Err(vec![ParseError {
+            span: Span {
+                start: span.start,
+                end: span.start + 2,
+            },
+            key: "syntax.unclosed_comment",
+            args: vec![],
+            fatal: true,
+        }]);
+    }
+
     let mut parser = Parser::new(s);
     let mut lines = vec![];
+    let mut errors = vec![];
     let p = &mut parser;
 
     newline(p);
-    /*
-    if let Ok(header) = call_optional!(header, p) {
-        lines.push(header);
-    }*/
+    let start = p.save_state();
+    match header(p) {
+        Ok(header) => lines.push(header),
+        Err(e) if e.fatal => {
+            synchronise(p, start, &e);
+            errors.push(e);
+        }
+        Err(_) => (),
+    }
 
     while p.peek().is_some() {
         // Parse one line
-        if let Ok(annotation) = call_optional!(annotation, p) {
-            lines.push(annotation);
-        } else if let Ok(assign) = call_optional!(assignment, p) {
-            lines.push(assign);
-        } else {
-            lines.push(match type_assignment(p) {
-                Ok(v) => v,
-                Err(e) if e.fatal => return Err(e),
-                Err(_) => {
-                    let peeked = if p.peek().is_some() {
-                        p.slice()
-                    } else {
-                        String::from("eof")
-                    };
-                    return Err(ParseError {
-                        span: p.span(),
-                        msg: format!("Unexpected `{}`", peeked),
-                        fatal: true,
-                    });
-                }
-            });
+        let start = p.save_state();
+        match top_level(p) {
+            Ok(v) => lines.push(v),
+            Err(e) => {
+                synchronise(p, start, &e);
+                errors.push(e);
+            }
         }
-        /*
-        } else if let Ok(_type) = call_optional!(type_assignment, p) {
-            lines.push(_type);
-        } else {
-            lines.push(match externy(p) {
-                Ok(v) => v,
-                Err(e) if e.fatal => return Err(e),
-                Err(_) => {
-                    let peeked = if p.peek().is_some() {
-                        p.slice()
-                    } else {
-                        String::from("eof")
-                    };
-                    return Err(ParseError {
-                        span: p.span(),
-                        msg: format!("Unexpected `{}`", peeked),
-                        fatal: true,
-                    });
-                }
-            });
-            */
 
         // Skip newlines
         newline(p);
     }
 
-    Ok(lines)
+    if errors.is_empty() {
+        Ok(lines)
+    } else {
+        Err(errors)
+    }
 }
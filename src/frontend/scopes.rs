@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use super::ir::{ArityInfo, Location};
 use super::types::TypeRc;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Scope {
     pub variables: HashMap<String, (TypeRc, ArityInfo, Location, bool, String)>,
     pub parent: Option<Box<Scope>>,
@@ -80,6 +80,18 @@ impl Scope {
         }
     }
 
+    // names(&self) -> Vec<&str>
+    // Gets the names of every variable in the stack of scopes.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = vec![];
+        let mut scope = Some(self);
+        while let Some(v) = scope {
+            names.extend(v.variables.keys().map(|v| v.as_str()));
+            scope = v.parent.as_deref();
+        }
+        names
+    }
+
     // push_scope(&mut self, bool) -> ()
     // Pushes a new scope to the top of the scope stack.
     pub fn push_scope(&mut self, new_func: bool) {
@@ -49,6 +49,11 @@ pub enum Type {
     Generic(String, usize),
     Func(TypeRc, TypeRc),
     Union(HashSetWrapper<TypeRc>),
+    Tuple(Vec<TypeRc>),
+    Record(Vec<(String, TypeRc)>),
+
+    // A distinct type with the same representation as the type it wraps
+    Newtype(String, TypeRc),
 }
 
 impl Display for Type {
@@ -102,27 +107,66 @@ impl Display for Type {
             }
 
             // Union types
+            // Fields are sorted so that the same union is always printed the same way
             Type::Union(fields) => {
-                let mut bar = false;
-                for field in fields.0.iter() {
-                    if bar {
-                        write!(f, " | ")?;
-                    } else {
-                        bar = true;
+                let mut names: Vec<_> = fields
+                    .0
+                    .iter()
+                    .map(|field| {
+                        if let Type::Func(_, _) = **field {
+                            format!("({})", field)
+                        } else {
+                            field.to_string()
+                        }
+                    })
+                    .collect();
+                names.sort();
+                write!(f, "{}", names.join(" | "))?;
+            }
+
+            // Tuple types
+            Type::Tuple(fields) => {
+                write!(f, "(")?;
+                for (i, field) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
                     }
+                    write!(f, "{}", field)?;
+                }
+                write!(f, ")")?;
+            }
 
-                    if let Type::Func(_, _) = **field {
-                        write!(f, "({})", field)?;
-                    } else {
-                        write!(f, "{}", field)?;
+            // Record types
+            Type::Record(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, field)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
                     }
+                    write!(f, "{}: {}", name, field)?;
                 }
+                write!(f, "}}")?;
+            }
+
+            // Newtypes are only known by their name
+            Type::Newtype(name, _) => {
+                write!(f, "{}", name)?;
             }
         }
         Ok(())
     }
 }
 
+// Represents why a type is not a subtype of another.
+#[derive(Debug, Clone)]
+pub enum Conflict {
+    // The first part of the subtype conflicts with the second part of the supertype
+    Mismatch(TypeRc, TypeRc),
+
+    // The generic would have to be bound to a type that contains it
+    Occurs(TypeRc, TypeRc),
+}
+
 #[derive(Clone, Hash, Eq, PartialEq)]
 pub struct GenericPair {
     generic: String,
@@ -150,6 +194,13 @@ impl Type {
             return true;
         }
 
+        // Type aliases
+        if let Some(t) = supertype.resolve_alias(types) {
+            return self.is_subtype(&t, types, generics_map);
+        } else if let Some(t) = self.resolve_alias(types) {
+            return t.is_subtype(supertype, types, generics_map);
+        }
+
         match supertype {
             // Primitives
             Type::Int => *self == Type::Int,
@@ -222,6 +273,36 @@ impl Type {
                 false
             }
 
+            // Tuples
+            Type::Tuple(sfields) => {
+                if let Type::Tuple(fields) = self {
+                    fields.len() == sfields.len()
+                        && fields
+                            .iter()
+                            .zip(sfields.iter())
+                            .all(|(f, sf)| f.is_subtype(sf, types, generics_map))
+                } else {
+                    false
+                }
+            }
+
+            // Records must have the same fields
+            Type::Record(sfields) => {
+                if let Type::Record(fields) = self {
+                    fields.len() == sfields.len()
+                        && fields.iter().zip(sfields.iter()).all(|(f, sf)| {
+                            f.0 == sf.0 && f.1.is_subtype(&sf.1, types, generics_map)
+                        })
+                } else {
+                    false
+                }
+            }
+
+            // Newtypes are only subtypes of themselves, not of the type they wrap
+            Type::Newtype(sname, _) => {
+                matches!(self, Type::Newtype(name, _) if name == sname)
+            }
+
             // Everything else is to be ignored
             Type::Error
             | Type::UndeclaredTypeError(_)
@@ -231,6 +312,115 @@ impl Type {
         }
     }
 
+    // find_conflict(&self, &Type, &HashMap<String, TypeRc>, &mut HashMap<GenericPair, TypeRc>) -> Conflict
+    // Finds the smallest parts of self and the passed in type that make self not a subtype of it.
+    // Should only be called with the generics map as it was before is_subtype returned false; the
+    // map is updated the same way is_subtype updates it.
+    pub fn find_conflict(
+        &self,
+        supertype: &Type,
+        types: &HashMap<String, TypeRc>,
+        generics_map: &mut HashMap<GenericPair, TypeRc>,
+    ) -> Conflict {
+        if let Some(t) = supertype.resolve_alias(types) {
+            return self.find_conflict(&t, types, generics_map);
+        } else if let Some(t) = self.resolve_alias(types) {
+            return t.find_conflict(supertype, types, generics_map);
+        }
+
+        match (self, supertype) {
+            (Type::Func(f, a), Type::Func(sf, sa)) => {
+                if f.is_subtype(sf, types, generics_map) {
+                    a.find_conflict(sa, types, generics_map)
+                } else {
+                    f.find_conflict(sf, types, generics_map)
+                }
+            }
+
+            (Type::Tuple(fields), Type::Tuple(sfields)) if fields.len() == sfields.len() => {
+                for (f, sf) in fields.iter().zip(sfields.iter()) {
+                    if !f.is_subtype(sf, types, generics_map) {
+                        return f.find_conflict(sf, types, generics_map);
+                    }
+                }
+                Conflict::Mismatch(arc::new(self.clone()), arc::new(supertype.clone()))
+            }
+
+            (Type::Record(fields), Type::Record(sfields))
+                if fields.len() == sfields.len()
+                    && fields.iter().zip(sfields.iter()).all(|(f, sf)| f.0 == sf.0) =>
+            {
+                for (f, sf) in fields.iter().zip(sfields.iter()) {
+                    if !f.1.is_subtype(&sf.1, types, generics_map) {
+                        return f.1.find_conflict(&sf.1, types, generics_map);
+                    }
+                }
+                Conflict::Mismatch(arc::new(self.clone()), arc::new(supertype.clone()))
+            }
+
+            // Generics conflict with what they were bound to, or with types that contain them
+            (_, Type::Generic(g, uid)) => {
+                let generic_pair = GenericPair {
+                    generic: g.clone(),
+                    uid: *uid,
+                };
+
+                match generics_map.get(&generic_pair) {
+                    Some(t) if !matches!(**t, Type::Generic(_, _)) => {
+                        let t = t.clone();
+                        self.find_conflict(&t, types, generics_map)
+                    }
+
+                    Some(t) => Conflict::Mismatch(arc::new(self.clone()), t.clone()),
+
+                    None if self.contains_generic(&generic_pair) => {
+                        Conflict::Occurs(arc::new(supertype.clone()), arc::new(self.clone()))
+                    }
+
+                    None => Conflict::Mismatch(arc::new(self.clone()), arc::new(supertype.clone())),
+                }
+            }
+
+            _ => Conflict::Mismatch(arc::new(self.clone()), arc::new(supertype.clone())),
+        }
+    }
+
+    // newtype_inner(&self, &HashMap<String, TypeRc>) -> Option<TypeRc>
+    // Returns the type wrapped by a newtype, following aliases to it. Returns None if the type is
+    // not a newtype.
+    pub fn newtype_inner(&self, types: &HashMap<String, TypeRc>) -> Option<TypeRc> {
+        let resolved = self.resolve_alias(types);
+        match resolved.as_deref().unwrap_or(self) {
+            Type::Newtype(_, t) => Some(t.clone()),
+            _ => None,
+        }
+    }
+
+    // resolve_alias(&self, &HashMap<String, TypeRc>) -> Option<TypeRc>
+    // Returns the type that a declared type name refers to, following aliases of aliases. Returns
+    // None if the type is not the name of a declared type.
+    pub fn resolve_alias(&self, types: &HashMap<String, TypeRc>) -> Option<TypeRc> {
+        let mut resolved = match self {
+            Type::Symbol(s) => types.get(s)?.clone(),
+            _ => return None,
+        };
+
+        // Aliases that refer to each other stop after every alias has been followed once
+        for _ in 0..types.len() {
+            let next = match &*resolved {
+                Type::Symbol(s) => types.get(s),
+                _ => None,
+            };
+
+            match next {
+                Some(v) => resolved = v.clone(),
+                None => break,
+            }
+        }
+
+        Some(resolved)
+    }
+
     fn contains_generic(&self, generic: &GenericPair) -> bool {
         match self {
             Type::Error
@@ -248,7 +438,13 @@ impl Type {
 
             Type::Func(a, r) => a.contains_generic(generic) || r.contains_generic(generic),
 
-            Type::Union(_) => todo!(),
+            Type::Union(fields) => fields.0.iter().any(|v| v.contains_generic(generic)),
+
+            Type::Tuple(fields) => fields.iter().any(|v| v.contains_generic(generic)),
+
+            Type::Record(fields) => fields.iter().any(|v| v.1.contains_generic(generic)),
+
+            Type::Newtype(_, t) => t.contains_generic(generic),
         }
     }
 
@@ -273,10 +469,35 @@ impl Type {
             }
 
             // Union types
-            Type::Union(_fields) => {
-                todo!();
+            Type::Union(fields) => {
+                // Fields are hashed by value, so the set is rebuilt
+                fields.0 = fields
+                    .0
+                    .iter()
+                    .map(|v| {
+                        let mut v = v.clone();
+                        Arc::make_mut(&mut v).replace_generics(generics_map);
+                        v
+                    })
+                    .collect();
             }
 
+            // Tuple types
+            Type::Tuple(fields) => {
+                for field in fields.iter_mut() {
+                    Arc::make_mut(field).replace_generics(generics_map);
+                }
+            }
+
+            // Record types
+            Type::Record(fields) => {
+                for field in fields.iter_mut() {
+                    Arc::make_mut(&mut field.1).replace_generics(generics_map);
+                }
+            }
+
+            Type::Newtype(_, t) => Arc::make_mut(t).replace_generics(generics_map),
+
             // Everything else is to be ignored
             Type::Error
             | Type::UndeclaredTypeError(_)
@@ -291,6 +512,50 @@ impl Type {
         }
     }
 
+    // get_symbols(&self, &mut Vec<&str>) -> ()
+    // Gets the names of every declared type that this type refers to.
+    pub fn get_symbols<'a>(&'a self, v: &mut Vec<&'a str>) {
+        match self {
+            Type::Error
+            | Type::UndeclaredTypeError(_)
+            | Type::DuplicateTypeError(_, _, _)
+            | Type::Unknown
+            | Type::Int
+            | Type::Float
+            | Type::Bool
+            | Type::Word
+            | Type::Char
+            | Type::Generic(_, _) => (),
+
+            Type::Symbol(s) => v.push(s),
+
+            Type::Func(a, r) => {
+                a.get_symbols(v);
+                r.get_symbols(v);
+            }
+
+            Type::Union(fields) => {
+                for field in fields.0.iter() {
+                    field.get_symbols(v);
+                }
+            }
+
+            Type::Tuple(fields) => {
+                for field in fields.iter() {
+                    field.get_symbols(v);
+                }
+            }
+
+            Type::Record(fields) => {
+                for field in fields.iter() {
+                    field.1.get_symbols(v);
+                }
+            }
+
+            Type::Newtype(_, t) => t.get_symbols(v),
+        }
+    }
+
     pub fn get_generics<'a>(&'a self, v: &mut Vec<(&'a str, usize)>) {
         match self {
             Type::Error
@@ -311,7 +576,25 @@ impl Type {
                 r.get_generics(v);
             }
 
-            Type::Union(_) => todo!(),
+            Type::Union(fields) => {
+                for field in fields.0.iter() {
+                    field.get_generics(v);
+                }
+            }
+
+            Type::Tuple(fields) => {
+                for field in fields.iter() {
+                    field.get_generics(v);
+                }
+            }
+
+            Type::Record(fields) => {
+                for field in fields.iter() {
+                    field.1.get_generics(v);
+                }
+            }
+
+            Type::Newtype(_, t) => t.get_generics(v),
         }
     }
 }
@@ -381,12 +664,13 @@ pub fn convert_ast_to_type(
 
         // Generics
         Ast::Generic(_, g) => {
-            let uid = if generic_uids.contains_key(&g) {
-                *generic_uids.get(&g).unwrap()
-            } else {
-                *last_uid += 1;
-                generic_uids.insert(g.clone(), *last_uid);
-                *last_uid
+            let uid = match generic_uids.get(&g) {
+                Some(v) => *v,
+                None => {
+                    *last_uid += 1;
+                    generic_uids.insert(g.clone(), *last_uid);
+                    *last_uid
+                }
             };
 
             Type::Generic(g, uid)
@@ -440,6 +724,35 @@ pub fn convert_ast_to_type(
             }
         }
 
+        // Tuple types
+        Ast::Tuple(_, fields) => {
+            let mut types = vec![];
+            for field in fields {
+                match convert_ast_to_type(field, filename, generic_uids, last_uid) {
+                    Type::UndeclaredTypeError(s) => return Type::UndeclaredTypeError(s),
+                    Type::DuplicateTypeError(a, b, c) => return Type::DuplicateTypeError(a, b, c),
+                    t => types.push(arc::new(t)),
+                }
+            }
+
+            Type::Tuple(types)
+        }
+
+        // Record types, whose fields are sorted by name
+        Ast::Record(_, fields) => {
+            let mut types = vec![];
+            for (name, field) in fields {
+                match convert_ast_to_type(field, filename, generic_uids, last_uid) {
+                    Type::UndeclaredTypeError(s) => return Type::UndeclaredTypeError(s),
+                    Type::DuplicateTypeError(a, b, c) => return Type::DuplicateTypeError(a, b, c),
+                    t => types.push((name, arc::new(t))),
+                }
+            }
+
+            types.sort_by(|a, b| a.0.cmp(&b.0));
+            Type::Record(types)
+        }
+
         // Function types
         Ast::Infix(_, op, l, r) if op == "->" => {
             let l = convert_ast_to_type(*l, filename, generic_uids, last_uid);
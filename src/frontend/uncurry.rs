@@ -0,0 +1,205 @@
+use std::collections::{HashMap, HashSet};
+
+use super::ir::{ArityInfo, IrFunction, IrModule, SExpr, SExprMetadata};
+use super::types::TypeRc;
+
+// The suffix added to the name of a function to name its uncurried entry. Names written in source
+// code cannot contain a dot, so the entry cannot clash with a user definition.
+const WORKER_SUFFIX: &str = ".uncurried";
+
+// uncurry(&mut IrModule) -> ()
+// Finds functions that return lambdas, such as `k a: 'a = \b: 'b . a`, and rewrites every call
+// that passes all of their arguments at once to call an uncurried entry instead. The entry takes
+// every argument in one call and runs the body of the innermost lambda, so saturated calls no
+// longer build a closure per argument. The curried function is kept for partial applications.
+// Entries are only generated for functions with at least one saturated call. Must be run after
+// correctness checking, since it relies on the captured values of lambdas.
+pub fn uncurry(module: &mut IrModule) {
+    let mut chains = HashMap::new();
+    let mut names: Vec<_> = module.funcs.keys().cloned().collect();
+    names.sort();
+    for name in names {
+        if name.ends_with(WORKER_SUFFIX) {
+            continue;
+        }
+
+        if let Some((args, innermost)) = curried_chain(module, &name) {
+            chains.insert(name, (args, innermost));
+        }
+    }
+
+    if chains.is_empty() {
+        return;
+    }
+
+    // Rewrite saturated calls
+    let arities: HashMap<_, _> = chains.iter().map(|v| (v.0.clone(), v.1 .0.len())).collect();
+    let mut used = HashSet::new();
+    for func in module.funcs.values_mut() {
+        rewrite_calls(&mut func.body, &arities, &mut used);
+    }
+
+    // Generate the uncurried entries that are called
+    for name in used {
+        let worker_name = format!("{}{}", name, WORKER_SUFFIX);
+        if module.funcs.contains_key(&worker_name) {
+            continue;
+        }
+
+        let (args, innermost) = chains.remove(&name).unwrap();
+        let func = module.funcs.get(&name).unwrap();
+        let inner = module.funcs.get(&innermost).unwrap();
+        let worker = IrFunction {
+            loc: func.loc.clone(),
+            name: worker_name.clone(),
+            _type: func._type.clone(),
+            args,
+            captured: HashMap::with_capacity(0),
+            captured_names: Vec::with_capacity(0),
+            body: inner.body.clone(),
+            global: func.global,
+            checked: true,
+            written: false,
+            impure: inner.impure,
+        };
+        module.funcs.insert(worker_name, worker);
+    }
+}
+
+// curried_chain(&IrModule, &str) -> Option<(Vec<(String, TypeRc)>, String)>
+// Checks whether a function directly returns lambdas that only capture the arguments of the
+// functions around them. If it does, returns the arguments of every function in the chain and the
+// name of the innermost lambda.
+fn curried_chain(module: &IrModule, name: &str) -> Option<(Vec<(String, TypeRc)>, String)> {
+    let func = module.funcs.get(name)?;
+    if !func.checked || func.args.is_empty() || !func.captured_names.is_empty() {
+        return None;
+    }
+
+    let mut args = func.args.clone();
+    let mut last = name;
+    while let SExpr::Function(_, next) = &module.funcs.get(last)?.body {
+        let inner = match module.funcs.get(next) {
+            Some(v) => v,
+            None => break,
+        };
+
+        // Arguments with the same name would be ambiguous once they are in one function
+        if inner.args.is_empty()
+            || !inner
+                .captured_names
+                .iter()
+                .all(|v| args.iter().any(|a| &a.0 == v))
+            || inner.args.iter().any(|v| args.iter().any(|a| a.0 == v.0))
+        {
+            break;
+        }
+
+        args.extend(inner.args.iter().cloned());
+        last = next;
+    }
+
+    if last == name {
+        None
+    } else {
+        Some((args, String::from(last)))
+    }
+}
+
+// rewrite_calls(&mut SExpr, &HashMap<String, usize>, &mut HashSet<String>) -> ()
+// Replaces every application of a curried function to exactly all of its arguments with a call to
+// its uncurried entry, recording which functions were called this way.
+fn rewrite_calls(sexpr: &mut SExpr, arities: &HashMap<String, usize>, used: &mut HashSet<String>) {
+    match sexpr {
+        SExpr::Application(_, func, args) => {
+            rewrite_calls(func, arities, used);
+            for arg in args.iter_mut() {
+                rewrite_calls(arg, arities, used);
+            }
+        }
+
+        SExpr::ExternalFunc(_, _, args) | SExpr::Tuple(_, args) => {
+            for arg in args.iter_mut() {
+                rewrite_calls(arg, arities, used);
+            }
+        }
+
+        SExpr::Chain(_, a, b) => {
+            rewrite_calls(a, arities, used);
+            rewrite_calls(b, arities, used);
+        }
+
+        SExpr::As(_, v)
+        | SExpr::Assign(_, _, v)
+        | SExpr::Index(_, v, _, _)
+        | SExpr::Field(_, v, _) => rewrite_calls(v, arities, used),
+
+        SExpr::Record(_, fields) => {
+            for (_, value) in fields.iter_mut() {
+                rewrite_calls(value, arities, used);
+            }
+        }
+
+        SExpr::With(_, assigns, body) => {
+            for assign in assigns.iter_mut() {
+                rewrite_calls(assign, arities, used);
+            }
+            rewrite_calls(body, arities, used);
+        }
+
+        SExpr::Match(_, value, arms) => {
+            rewrite_calls(value, arities, used);
+            for (_, arm, _) in arms.iter_mut() {
+                rewrite_calls(arm, arities, used);
+            }
+        }
+
+        SExpr::Empty(_) | SExpr::TypeAlias(_, _) | SExpr::Symbol(_, _) | SExpr::Function(_, _) => {
+            ()
+        }
+    }
+
+    let (name, func_meta, args) = match flatten_application(sexpr) {
+        Some(v) => v,
+        None => return,
+    };
+
+    let arity = match arities.get(name) {
+        Some(v) if *v == args.len() => *v,
+        _ => return,
+    };
+
+    let name = name.clone();
+    let mut func_meta = func_meta.clone();
+    func_meta.arity = ArityInfo::Known(arity);
+    let args: Vec<_> = args.into_iter().cloned().collect();
+
+    let mut meta = sexpr.get_metadata().clone();
+    meta.arity = ArityInfo::Known(0);
+    *sexpr = SExpr::Application(
+        meta,
+        Box::new(SExpr::Function(
+            func_meta,
+            format!("{}{}", name, WORKER_SUFFIX),
+        )),
+        args,
+    );
+    used.insert(name);
+}
+
+// flatten_application(&SExpr) -> Option<(&String, &SExprMetadata, Vec<&SExpr>)>
+// Gets the function at the bottom of a chain of applications, such as `(f a) b`, along with all the
+// arguments it is applied to in order.
+fn flatten_application(sexpr: &SExpr) -> Option<(&String, &SExprMetadata, Vec<&SExpr>)> {
+    match sexpr {
+        SExpr::Application(_, func, args) => {
+            let (name, meta, mut all) = flatten_application(func)?;
+            all.extend(args.iter());
+            Some((name, meta, all))
+        }
+
+        SExpr::Function(meta, name) => Some((name, meta, vec![])),
+
+        _ => None,
+    }
+}
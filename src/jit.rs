@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::backends::{x86_64, GeneratedCode, RelocationError, DEFAULT_ARCH};
+
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+const MAP_JIT: i32 = 0x0800;
+#[cfg(all(unix, not(all(target_os = "macos", target_arch = "aarch64"))))]
+const MAP_JIT: i32 = 0;
+
+#[cfg(unix)]
+extern "C" {
+    fn pthread_jit_write_protect_np(_: bool);
+}
+
+#[cfg(all(unix, target_arch = "aarch64"))]
+extern "C" {
+    fn __clear_cache(start: *mut libc::c_char, end: *mut libc::c_char);
+}
+
+#[cfg(windows)]
+mod win32 {
+    use std::ffi::c_void;
+
+    pub const MEM_COMMIT: u32 = 0x1000;
+    pub const MEM_RESERVE: u32 = 0x2000;
+    pub const MEM_RELEASE: u32 = 0x8000;
+    pub const PAGE_READWRITE: u32 = 0x04;
+    pub const PAGE_EXECUTE_READ: u32 = 0x20;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn VirtualAlloc(
+            address: *mut c_void,
+            size: usize,
+            allocation_type: u32,
+            protect: u32,
+        ) -> *mut c_void;
+        pub fn VirtualProtect(
+            address: *mut c_void,
+            size: usize,
+            new_protect: u32,
+            old_protect: *mut u32,
+        ) -> i32;
+        pub fn VirtualFree(address: *mut c_void, size: usize, free_type: u32) -> i32;
+        pub fn GetCurrentProcess() -> *mut c_void;
+        pub fn FlushInstructionCache(
+            process: *mut c_void,
+            address: *const c_void,
+            size: usize,
+        ) -> i32;
+    }
+}
+
+/// An error encountered while loading generated code into executable memory.
+#[derive(Debug)]
+pub enum JitError {
+    /// The host architecture has no backend that can be run in memory.
+    UnsupportedArch(&'static str),
+
+    /// Executable memory could not be allocated or protected.
+    Memory(std::io::Error),
+
+    /// The generated code could not be relocated to its address in memory.
+    Relocation(RelocationError),
+}
+
+impl Display for JitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JitError::UnsupportedArch(arch) => {
+                write!(f, "backend not supported on this arch ({})", arch)
+            }
+            JitError::Memory(e) => write!(f, "could not map executable memory: {}", e),
+            JitError::Relocation(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<RelocationError> for JitError {
+    fn from(e: RelocationError) -> Self {
+        JitError::Relocation(e)
+    }
+}
+
+/// Generated code loaded into executable memory. More code can be loaded later; it is linked
+/// against the functions loaded before it, so code that is already loaded is never compiled or
+/// relocated again. The runtime is not linked into the compiler, so code that calls into it cannot
+/// be loaded.
+pub struct Jit {
+    chunks: Vec<(GeneratedCode, *mut u8)>,
+    symbols: HashMap<String, u64>,
+}
+
+impl Jit {
+    /// Copies generated code into newly allocated executable memory, relocating it for its new
+    /// address. Returns an error if the host architecture is not supported or if the code could
+    /// not be relocated.
+    pub fn new(code: GeneratedCode) -> Result<Jit, JitError> {
+        let mut jit = Jit::empty()?;
+        jit.load(code)?;
+        Ok(jit)
+    }
+
+    /// Creates a JIT with no code loaded. Returns an error if the host architecture is not
+    /// supported.
+    pub fn empty() -> Result<Jit, JitError> {
+        if !matches!(DEFAULT_ARCH, "aarch64" | "x86_64") {
+            return Err(JitError::UnsupportedArch(DEFAULT_ARCH));
+        }
+
+        Ok(Jit {
+            chunks: vec![],
+            symbols: HashMap::new(),
+        })
+    }
+
+    /// Returns true if a function with the given name has been loaded.
+    pub fn contains(&self, func: &str) -> bool {
+        self.symbols.contains_key(func)
+    }
+
+    /// Copies generated code into newly allocated executable memory. References to functions the
+    /// code does not define are resolved against the functions already loaded, and the functions
+    /// it defines replace any loaded functions with the same name. Returns an error if the code
+    /// could not be relocated, such as when it refers to a function that is not loaded, in which
+    /// case nothing is loaded.
+    pub fn load(&mut self, mut code: GeneratedCode) -> Result<(), JitError> {
+        if code.is_empty() {
+            return Ok(());
+        }
+
+        let mem = unsafe { map_writable(code.len()) }?;
+
+        // The aarch64 backend does not emit any references to relocate yet
+        let relocated = match DEFAULT_ARCH {
+            "x86_64" => x86_64::codegen::relocate_with_symbols(&mut code, mem, &self.symbols),
+            _ => Ok(()),
+        };
+
+        if let Err(e) = relocated {
+            unsafe {
+                unmap(mem, code.len());
+            }
+            return Err(e.into());
+        }
+
+        unsafe {
+            if let Err(e) = make_executable(mem, code.data()) {
+                unmap(mem, code.len());
+                return Err(e);
+            }
+        }
+
+        for (func, range) in code.get_funcs() {
+            if range.end != 0 {
+                self.symbols
+                    .insert(String::from(func), mem as u64 + range.start as u64);
+            }
+        }
+
+        self.chunks.push((code, mem));
+        Ok(())
+    }
+
+    /// Calls the loaded function with the given name, returning `None` if no such function exists.
+    ///
+    /// # Safety
+    /// The function is called with no arguments, so it must be a function that takes none.
+    pub unsafe fn call(&self, func: &str) -> Option<*const u8> {
+        self.chunks
+            .iter()
+            .rev()
+            .find(|(code, _)| code.get_funcs().any(|v| v.0 == func && v.1.end != 0))
+            .and_then(|(code, mem)| code.get_fn(func, *mem))
+            .map(|v| v())
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        for (code, mem) in self.chunks.iter() {
+            unsafe {
+                unmap(*mem, code.len());
+            }
+        }
+    }
+}
+
+// map_writable(usize) -> Result<*mut u8, JitError>
+// Allocates readable and writable memory that can later be made executable.
+#[cfg(unix)]
+unsafe fn map_writable(len: usize) -> Result<*mut u8, JitError> {
+    let mem = libc::mmap(
+        std::ptr::null_mut(),
+        len,
+        libc::PROT_WRITE | libc::PROT_READ,
+        libc::MAP_ANONYMOUS | libc::MAP_PRIVATE | MAP_JIT,
+        -1,
+        0,
+    );
+
+    if mem == libc::MAP_FAILED {
+        Err(JitError::Memory(std::io::Error::last_os_error()))
+    } else {
+        Ok(mem as *mut u8)
+    }
+}
+
+#[cfg(windows)]
+unsafe fn map_writable(len: usize) -> Result<*mut u8, JitError> {
+    let mem = win32::VirtualAlloc(
+        std::ptr::null_mut(),
+        len,
+        win32::MEM_COMMIT | win32::MEM_RESERVE,
+        win32::PAGE_READWRITE,
+    );
+
+    if mem.is_null() {
+        Err(JitError::Memory(std::io::Error::last_os_error()))
+    } else {
+        Ok(mem as *mut u8)
+    }
+}
+
+// make_executable(*mut u8, &[u8]) -> Result<(), JitError>
+// Copies the code into memory, marks it as executable, and flushes the instruction cache.
+#[cfg(unix)]
+unsafe fn make_executable(mem: *mut u8, data: &[u8]) -> Result<(), JitError> {
+    pthread_jit_write_protect_np(false);
+    std::ptr::copy(data.as_ptr(), mem, data.len());
+    let protected = libc::mprotect(
+        mem as *mut libc::c_void,
+        data.len(),
+        libc::PROT_READ | libc::PROT_EXEC,
+    );
+    pthread_jit_write_protect_np(true);
+
+    if protected != 0 {
+        return Err(JitError::Memory(std::io::Error::last_os_error()));
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    __clear_cache(
+        mem as *mut libc::c_char,
+        mem.add(data.len()) as *mut libc::c_char,
+    );
+
+    Ok(())
+}
+
+#[cfg(windows)]
+unsafe fn make_executable(mem: *mut u8, data: &[u8]) -> Result<(), JitError> {
+    std::ptr::copy(data.as_ptr(), mem, data.len());
+
+    let mut old = 0;
+    if win32::VirtualProtect(
+        mem as *mut _,
+        data.len(),
+        win32::PAGE_EXECUTE_READ,
+        &mut old,
+    ) == 0
+    {
+        return Err(JitError::Memory(std::io::Error::last_os_error()));
+    }
+
+    win32::FlushInstructionCache(win32::GetCurrentProcess(), mem as *const _, data.len());
+    Ok(())
+}
+
+// unmap(*mut u8, usize) -> ()
+// Frees memory allocated by map_writable.
+#[cfg(unix)]
+unsafe fn unmap(mem: *mut u8, len: usize) {
+    libc::munmap(mem as *mut libc::c_void, len);
+}
+
+#[cfg(windows)]
+unsafe fn unmap(mem: *mut u8, _: usize) {
+    win32::VirtualFree(mem as *mut _, 0, win32::MEM_RELEASE);
+}
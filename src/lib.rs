@@ -6,23 +6,55 @@
 /// emitting code.
 pub mod backends;
 
+/// Module for collecting and reporting coverage. Coverage is collected by running a program in the
+/// interpreter and counting how many times each function is entered.
+pub mod coverage;
+
 /// Module that contains helper functions transforming the source text into higher level
 /// intermediate representation. This includes functions for parsing, functions for transforming
 /// the text into IR, and functions for checking the correctness of IR.
 pub mod frontend;
 
-use codespan_reporting::diagnostic::{Diagnostic, Label};
-use codespan_reporting::files::SimpleFiles;
+/// Module for loading generated code into executable memory and running it. This handles mapping
+/// memory on each supported platform and flushing the instruction cache where needed.
+pub mod jit;
+
+/// Module for linting source code. Each lint rule can be allowed, warned about, or denied in the
+/// manifest.
+pub mod lint;
+
+/// Module for the catalogs of diagnostic messages. Each catalog holds the messages in one language,
+/// so that diagnostics can be translated without changing the passes that produce them.
+pub mod messages;
+
+/// Module for profiling the compiler itself. Each pass is timed, so that the time spent compiling
+/// can be shown as a trace or a flamegraph.
+pub mod profile;
+
+/// Module for reading project manifests, which specify how to build a project so that it can be
+/// built without passing any files on the command line.
+pub mod manifest;
+
+/// Module for configuring the REPL. The configuration is read from the manifest, so every project
+/// can have its own aliases and startup commands.
+pub mod repl;
+
+use codespan_reporting::diagnostic::{Diagnostic, Label, LabelStyle, Severity};
+use codespan_reporting::files::{Files, SimpleFiles};
 use codespan_reporting::term;
-use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+use codespan_reporting::term::termcolor::{Color, ColorChoice, ColorSpec, StandardStream};
+use codespan_reporting::term::{Chars, Styles};
 use logos::Span;
 use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
 
+use crate::backends::{Feature, Unsupported};
+use crate::frontend::correctness::CorrectnessError;
 use crate::frontend::ir::{self, Ir, IrError};
 use crate::frontend::parser;
-
-/// Determines whether the compiler should output debug information or not.
-static DEBUG: bool = false;
+use crate::frontend::types::Conflict;
+use crate::messages::Catalog;
 
 /// The return type of check<>().
 pub type Res<'a> = Result<
@@ -30,13 +62,14 @@ pub type Res<'a> = Result<
     (Vec<Diagnostic<usize>>, SimpleFiles<&'a String, String>),
 >;
 
-/// Checks whether given code is valid.
+/// Checks whether given code is valid. Diagnostic messages are taken from the given catalog.
 pub fn check<'a>(
     filenames: &'a [(String, bool)],
     codes: &[String],
     ir: &mut Ir,
     _require_main: bool,
     emit: bool,
+    catalog: &Catalog,
 ) -> Res<'a> {
     // Set up codespan
     let mut files = SimpleFiles::new();
@@ -73,125 +106,34 @@ pub fn check<'a>(
             // TODO: remove this condition
             todo!("this should never be available");
         } else {
-            let ast = match parser::parse(code) {
+            let parsed = {
+                let _pass = profile::pass("parse");
+                parser::parse(code)
+            };
+            let ast = match parsed {
                 Ok(v) => v,
-                Err(e) => {
-                    let diagnostic = Diagnostic::error()
-                        .with_message(&e.msg)
-                        .with_labels(vec![Label::primary(file_id, e.span)]);
-                    if emit {
-                        term::emit(&mut writer.lock(), &config, &files, &diagnostic).unwrap();
+                Err(errors) => {
+                    for e in errors {
+                        let diagnostic = Diagnostic::error()
+                            .with_message(e.message(catalog))
+                            .with_labels(vec![Label::primary(file_id, e.span)]);
+                        if emit {
+                            term::emit(&mut writer.lock(), &config, &files, &diagnostic).unwrap();
+                        }
+                        diagnostics.push(diagnostic);
                     }
-                    diagnostics.push(diagnostic);
                     return Err((diagnostics, files));
                 }
             };
 
-            // Print out the ast
-            if DEBUG {
-                println!("{:#?}", &ast);
-            }
-            match ir::convert_ast_to_ir(&file.0, code, ast, ir) {
-                Ok(_) if DEBUG => {
-                    dbg!(&ir);
-                }
+            let converted = {
+                let _pass = profile::pass("convert");
+                ir::convert_ast_to_ir(&file.0, code, ast, ir)
+            };
+            match converted {
                 Ok(_) => (),
                 Err(e) => {
-                    for e in e {
-                        let mut diagnostic = Diagnostic::error();
-                        match e {
-                            IrError::InvalidType(s) => {
-                                diagnostic = diagnostic
-                                    .with_message("Invalid type used")
-                                    .with_labels(vec![Label::primary(
-                                        *file_hash.get(&s.filename).unwrap(),
-                                        s.span,
-                                    )
-                                    .with_message("Undeclared type")])
-                            }
-
-                            IrError::DuplicateTypeInUnion(s1, s2, t) => {
-                                diagnostic = diagnostic
-                                    .with_message("Duplicate type in union type declaration")
-                                    .with_labels(vec![
-                                        Label::secondary(
-                                            *file_hash.get(&s1.filename).unwrap(),
-                                            s1.span,
-                                        )
-                                        .with_message("Type used here first"),
-                                        Label::primary(
-                                            *file_hash.get(&s2.filename).unwrap(),
-                                            s2.span,
-                                        )
-                                        .with_message(
-                                            format!("Type `{}` used a second time here", t),
-                                        ),
-                                    ])
-                            }
-
-                            IrError::DoubleExport(s1, s2, e) => {
-                                diagnostic = diagnostic
-                                    .with_message("Value exported twice")
-                                    .with_labels(vec![
-                                        Label::secondary(
-                                            *file_hash.get(&s1.filename).unwrap(),
-                                            s1.span,
-                                        )
-                                        .with_message("Value exported here first"),
-                                        Label::primary(
-                                            *file_hash.get(&s2.filename).unwrap(),
-                                            s2.span,
-                                        )
-                                        .with_message(
-                                            format!("Value {} exported a second time here", e),
-                                        ),
-                                    ])
-                            }
-
-                            IrError::RedefineImportAlias(s1, s2, a) => {
-                                diagnostic = diagnostic
-                                    .with_message("Alias defined twice")
-                                    .with_labels(vec![
-                                        Label::secondary(
-                                            *file_hash.get(&s1.filename).unwrap(),
-                                            s1.span,
-                                        )
-                                        .with_message("Alias defined here first"),
-                                        Label::primary(
-                                            *file_hash.get(&s2.filename).unwrap(),
-                                            s2.span,
-                                        )
-                                        .with_message(
-                                            format!("Alias {} defined a second time here", a),
-                                        ),
-                                    ])
-                            }
-
-                            IrError::UnsupportedAnnotation(s, a) => {
-                                diagnostic = diagnostic
-                                    .with_message("Unsupported annotation used")
-                                    .with_labels(vec![Label::primary(
-                                        *file_hash.get(&s.filename).unwrap(),
-                                        s.span,
-                                    )
-                                    .with_message(format!("Annotation {} is unsupported", a))])
-                            }
-
-                            IrError::InvalidFFIType(s, t) => {
-                                diagnostic = diagnostic
-                                    .with_message("Unsupported type used for FFI")
-                                    .with_labels(vec![Label::primary(
-                                        *file_hash.get(&s.filename).unwrap(),
-                                        s.span,
-                                    )
-                                    .with_message(format!("Type {} is unsupported by FFI", t))])
-                            }
-
-                            IrError::DuplicateModule(v, _t) => {
-                                diagnostic =
-                                    diagnostic.with_message(format!("Duplicate module `{}`", v))
-                            }
-                        }
+                    for diagnostic in ir_diagnostics(filenames, e, catalog) {
                         if emit {
                             term::emit(&mut writer.lock(), &config, &files, &diagnostic).unwrap();
                         }
@@ -203,9 +145,599 @@ pub fn check<'a>(
         }
     }
 
+    // Imports are resolved once every module has been converted
+    if !fail {
+        let names: Vec<_> = filenames
+            .iter()
+            .filter_map(|f| ir.modules.values().find(|m| m.filename == f.0))
+            .map(|m| m.name.clone())
+            .collect();
+        let _pass = profile::pass("resolve imports");
+        if let Err(e) = ir::resolve_imports(ir, &names) {
+            for diagnostic in ir_diagnostics(filenames, e, catalog) {
+                if emit {
+                    term::emit(&mut writer.lock(), &config, &files, &diagnostic).unwrap();
+                }
+                diagnostics.push(diagnostic);
+            }
+            fail = true;
+        }
+    }
+
     if fail {
         Err((diagnostics, files))
     } else {
         Ok((diagnostics, files))
     }
 }
+
+/// Reads the files of the modules imported by the given sources and adds them to the end of the
+/// sources, along with the modules they import in turn. Modules are looked for relative to the
+/// file that imports them, and modules that are already among the sources are not read again.
+/// Returns a diagnostic for every import whose file cannot be read. File ids are the indices of the
+/// sources.
+pub fn load_imports(
+    sources: &mut Vec<(String, String)>,
+    catalog: &Catalog,
+) -> Vec<Diagnostic<usize>> {
+    let same_file = |a: &str, b: &str| {
+        a == b
+            || matches!(
+                (fs::canonicalize(a), fs::canonicalize(b)),
+                (Ok(a), Ok(b)) if a == b
+            )
+    };
+
+    let mut diagnostics = vec![];
+    let mut i = 0;
+    while i < sources.len() {
+        for (span, name) in parser::header_names(&sources[i].1).1 {
+            let paths = ir::import_paths(&sources[i].0, &name);
+            let loaded = sources.iter().any(|(filename, code)| {
+                paths.iter().any(|v| same_file(filename, v))
+                    || parser::header_names(code)
+                        .0
+                        .unwrap_or_else(|| ir::default_module_name(filename))
+                        == name
+            });
+            if loaded {
+                continue;
+            }
+
+            match paths
+                .iter()
+                .find_map(|v| fs::read_to_string(v).ok().map(|code| (v.clone(), code)))
+            {
+                Some(v) => sources.push(v),
+                None => diagnostics.push(
+                    Diagnostic::error()
+                        .with_code("E0015")
+                        .with_message(catalog.message("E0015", &[&name]))
+                        .with_labels(vec![Label::primary(i, span)
+                            .with_message(catalog.message("E0015.import", &[]))])
+                        .with_notes(vec![catalog.message("E0015.paths", &[&paths.join("`, `")])]),
+                ),
+            }
+        }
+
+        i += 1;
+    }
+
+    diagnostics
+}
+
+/// Converts errors found while converting code to IR into diagnostics. File ids are the indices of
+/// the files in `filenames`, the same as the ids used by check<>().
+pub fn ir_diagnostics(
+    filenames: &[(String, bool)],
+    errors: Vec<IrError>,
+    catalog: &Catalog,
+) -> Vec<Diagnostic<usize>> {
+    let id = |s: &ir::Location| {
+        filenames
+            .iter()
+            .position(|v| v.0 == s.filename)
+            .unwrap_or(0)
+    };
+
+    errors
+        .into_iter()
+        .map(|e| match e {
+            IrError::InvalidType(s) => Diagnostic::error()
+                .with_code("E0001")
+                .with_message(catalog.message("E0001", &[]))
+                .with_labels(vec![
+                    Label::primary(id(&s), s.span).with_message(catalog.message("E0001.type", &[]))
+                ]),
+
+            IrError::DuplicateTypeInUnion(s1, s2, t) => Diagnostic::error()
+                .with_code("E0002")
+                .with_message(catalog.message("E0002", &[]))
+                .with_labels(vec![
+                    Label::secondary(id(&s1), s1.span)
+                        .with_message(catalog.message("E0002.first", &[])),
+                    Label::primary(id(&s2), s2.span)
+                        .with_message(catalog.message("E0002.second", &[&t])),
+                ]),
+
+            IrError::DoubleExport(s1, s2, e) => Diagnostic::error()
+                .with_code("E0003")
+                .with_message(catalog.message("E0003", &[]))
+                .with_labels(vec![
+                    Label::secondary(id(&s1), s1.span)
+                        .with_message(catalog.message("E0003.first", &[])),
+                    Label::primary(id(&s2), s2.span)
+                        .with_message(catalog.message("E0003.second", &[&e])),
+                ]),
+
+            IrError::RedefineImportAlias(s1, s2, a) => Diagnostic::error()
+                .with_code("E0004")
+                .with_message(catalog.message("E0004", &[]))
+                .with_labels(vec![
+                    Label::secondary(id(&s1), s1.span)
+                        .with_message(catalog.message("E0004.first", &[])),
+                    Label::primary(id(&s2), s2.span)
+                        .with_message(catalog.message("E0004.second", &[&a])),
+                ]),
+
+            IrError::UnsupportedAnnotation(s, a) => Diagnostic::error()
+                .with_code("E0005")
+                .with_message(catalog.message("E0005", &[]))
+                .with_labels(vec![Label::primary(id(&s), s.span)
+                    .with_message(catalog.message("E0005.annotation", &[&a]))]),
+
+            IrError::InvalidFFIType(s, t) => Diagnostic::error()
+                .with_code("E0006")
+                .with_message(catalog.message("E0006", &[]))
+                .with_labels(vec![Label::primary(id(&s), s.span)
+                    .with_message(catalog.message("E0006.type", &[&t]))]),
+
+            IrError::DuplicateModule(v, _t) => Diagnostic::error()
+                .with_code("E0007")
+                .with_message(catalog.message("E0007", &[&v])),
+
+            IrError::SignatureWithoutDefinition(s, v) => Diagnostic::error()
+                .with_code("E0008")
+                .with_message(catalog.message("E0008", &[]))
+                .with_labels(vec![Label::primary(id(&s), s.span)
+                    .with_message(catalog.message("E0008.signature", &[&v]))]),
+
+            IrError::MacroArity(s, v, expected, found) => Diagnostic::error()
+                .with_code("E0011")
+                .with_message(catalog.message("E0011", &[&v]))
+                .with_labels(vec![Label::primary(id(&s), s.span).with_message(
+                    catalog.message("E0011.use", &[&v, &expected, &found]),
+                )]),
+
+            IrError::RecursiveMacro(s, v) => Diagnostic::error()
+                .with_code("E0012")
+                .with_message(catalog.message("E0012", &[&v]))
+                .with_labels(vec![
+                    Label::primary(id(&s), s.span).with_message(catalog.message("E0012.use", &[]))
+                ]),
+
+            IrError::UnknownModule(s, v) => Diagnostic::error()
+                .with_code("E0015")
+                .with_message(catalog.message("E0015", &[&v]))
+                .with_labels(vec![Label::primary(id(&s), s.span)
+                    .with_message(catalog.message("E0015.import", &[]))]),
+
+            // The last import in the cycle is the one that closes it
+            IrError::ImportCycle(cycle) => {
+                let mut path: Vec<_> = cycle.iter().map(|v| v.1.as_str()).collect();
+                path.push(&cycle[0].1);
+
+                let last = cycle.len() - 1;
+                Diagnostic::error()
+                    .with_code("E0016")
+                    .with_message(catalog.message("E0016", &[&path.join(" -> ")]))
+                    .with_labels(
+                        cycle
+                            .iter()
+                            .enumerate()
+                            .map(|(i, (s, from, to))| {
+                                if i == last {
+                                    Label::primary(id(s), s.span.clone())
+                                        .with_message(catalog.message("E0016.import", &[from, to]))
+                                } else {
+                                    Label::secondary(id(s), s.span.clone())
+                                        .with_message(catalog.message("E0016.step", &[from, to]))
+                                }
+                            })
+                            .collect(),
+                    )
+            }
+
+            IrError::UnknownImport(s, m, v) => Diagnostic::error()
+                .with_code("E0017")
+                .with_message(catalog.message("E0017", &[&v, &m]))
+                .with_labels(vec![Label::primary(id(&s), s.span)
+                    .with_message(catalog.message("E0017.import", &[]))]),
+
+            IrError::PrivateItem(s1, s2, m, v) => Diagnostic::error()
+                .with_code("E0019")
+                .with_message(catalog.message("E0019", &[&v, &m]))
+                .with_labels(vec![
+                    Label::primary(id(&s1), s1.span)
+                        .with_message(catalog.message("E0019.use", &[])),
+                    Label::secondary(id(&s2), s2.span)
+                        .with_message(catalog.message("E0019.definition", &[&v])),
+                ]),
+
+            IrError::Unsupported(s, construct) => Diagnostic::error()
+                .with_code("E0026")
+                .with_message(catalog.message("E0026", &[&construct]))
+                .with_labels(vec![
+                    Label::primary(id(&s), s.span).with_message(catalog.message("E0026.use", &[]))
+                ]),
+        })
+        .collect()
+}
+
+/// Converts errors found while checking correctness into diagnostics. File ids are the indices of
+/// the files in `filenames`, the same as the ids used by check<>().
+pub fn correctness_diagnostics(
+    filenames: &[(String, bool)],
+    errors: Vec<CorrectnessError>,
+    catalog: &Catalog,
+) -> Vec<Diagnostic<usize>> {
+    let file_id = |filename: &String| filenames.iter().position(|v| &v.0 == filename).unwrap_or(0);
+
+    errors
+        .into_iter()
+        .map(|e| match e {
+            CorrectnessError::AscriptionMismatch(s1, s2, found, expected) => Diagnostic::error()
+                .with_code("E0009")
+                .with_message(catalog.message("E0009", &[]))
+                .with_labels(vec![
+                    Label::primary(file_id(&s1.filename), s1.span)
+                        .with_message(catalog.message("E0009.expr", &[&found])),
+                    Label::secondary(file_id(&s2.filename), s2.span)
+                        .with_message(catalog.message("E0009.type", &[&expected])),
+                ]),
+
+            CorrectnessError::SignatureMismatch(s1, s2, found, expected) => Diagnostic::error()
+                .with_code("E0010")
+                .with_message(catalog.message("E0010", &[]))
+                .with_labels(vec![
+                    Label::primary(file_id(&s1.filename), s1.span)
+                        .with_message(catalog.message("E0010.definition", &[&found])),
+                    Label::secondary(file_id(&s2.filename), s2.span)
+                        .with_message(catalog.message("E0010.signature", &[&expected])),
+                ]),
+
+            CorrectnessError::DestructureMismatch(s, found, len) => Diagnostic::error()
+                .with_code("E0013")
+                .with_message(catalog.message("E0013", &[]))
+                .with_labels(vec![Label::primary(file_id(&s.filename), s.span)
+                    .with_message(catalog.message("E0013.value", &[&found, &len]))]),
+
+            CorrectnessError::NoSuchField(s, found, field) => Diagnostic::error()
+                .with_code("E0014")
+                .with_message(catalog.message("E0014", &[&field]))
+                .with_labels(vec![Label::primary(file_id(&s.filename), s.span)
+                    .with_message(catalog.message("E0014.value", &[&found, &field]))]),
+
+            // The conflicting parts are only noted if they are smaller than the whole types
+            CorrectnessError::ArgumentMismatch(s1, s2, found, expected, conflict) => {
+                let notes = match conflict {
+                    Conflict::Mismatch(a, b) if a == found && b == expected => vec![],
+                    Conflict::Mismatch(a, b) => {
+                        vec![catalog.message("E0020.conflict", &[&a, &b])]
+                    }
+                    Conflict::Occurs(g, t) => vec![catalog.message("E0020.occurs", &[&g, &t])],
+                };
+
+                Diagnostic::error()
+                    .with_code("E0020")
+                    .with_message(catalog.message("E0020", &[]))
+                    .with_labels(vec![
+                        Label::primary(file_id(&s1.filename), s1.span)
+                            .with_message(catalog.message("E0020.arg", &[&found])),
+                        Label::secondary(file_id(&s2.filename), s2.span)
+                            .with_message(catalog.message("E0020.func", &[&expected])),
+                    ])
+                    .with_notes(notes)
+            }
+
+            CorrectnessError::NotAFunction(s, found) => Diagnostic::error()
+                .with_code("E0021")
+                .with_message(catalog.message("E0021", &[]))
+                .with_labels(vec![Label::primary(file_id(&s.filename), s.span)
+                    .with_message(catalog.message("E0021.value", &[&found]))]),
+
+            CorrectnessError::TooManyArguments(s1, s2, _type, expected, given) => {
+                Diagnostic::error()
+                    .with_code("E0024")
+                    .with_message(catalog.message("E0024", &[&expected, &given]))
+                    .with_labels(vec![
+                        Label::primary(file_id(&s1.filename), s1.span)
+                            .with_message(catalog.message("E0024.arg", &[&(expected + 1)])),
+                        Label::secondary(file_id(&s2.filename), s2.span)
+                            .with_message(catalog.message("E0024.func", &[&_type, &expected])),
+                    ])
+            }
+
+            CorrectnessError::SymbolNotFound(s, name, suggestion) => Diagnostic::error()
+                .with_code("E0025")
+                .with_message(catalog.message("E0025", &[&name]))
+                .with_labels(vec![Label::primary(file_id(&s.filename), s.span)
+                    .with_message(catalog.message("E0025.use", &[]))])
+                .with_notes(
+                    suggestion
+                        .map(|v| vec![catalog.message("E0025.suggestion", &[&name, &v])])
+                        .unwrap_or_default(),
+                ),
+
+            CorrectnessError::RecursionWithoutSignature(s, name) => Diagnostic::error()
+                .with_code("E0023")
+                .with_message(catalog.message("E0023", &[&name]))
+                .with_labels(vec![Label::primary(file_id(&s.filename), s.span)
+                    .with_message(catalog.message("E0023.use", &[&name]))])
+                .with_notes(vec![catalog.message("E0023.help", &[&name])]),
+        })
+        .collect()
+}
+
+/// Converts uses of constructs that a backend cannot generate code for into diagnostics. Each
+/// diagnostic points at the definition of the function that uses the construct, if that function
+/// was written in one of the files in `filenames`. File ids are the same as the ids used by
+/// check<>().
+pub fn unsupported_diagnostics(
+    filenames: &[(String, bool)],
+    ir: &Ir,
+    unsupported: Vec<Unsupported>,
+    catalog: &Catalog,
+) -> Vec<Diagnostic<usize>> {
+    unsupported
+        .into_iter()
+        .map(|u| {
+            let diagnostic = Diagnostic::error()
+                .with_code("E0018")
+                .with_message(catalog.message("E0018", &[&u.arch, &u.feature]))
+                .with_notes(vec![catalog.message("E0018.help", &[])]);
+
+            match find_lowered_func(filenames, ir, &u.func) {
+                Some((file_id, loc)) => {
+                    diagnostic.with_labels(vec![Label::primary(file_id, loc.span.clone())
+                        .with_message(catalog.message("E0018.func", &[&u.func]))])
+                }
+                None => diagnostic,
+            }
+        })
+        .collect()
+}
+
+/// Converts uses of features that need the runtime in a freestanding build into diagnostics. Each
+/// diagnostic points at the function that uses the feature and at every `@no_runtime` annotation
+/// in the files in `filenames`. File ids are the same as the ids used by check<>().
+pub fn runtime_diagnostics(
+    filenames: &[(String, bool)],
+    ir: &Ir,
+    uses: Vec<(String, Feature)>,
+    catalog: &Catalog,
+) -> Vec<Diagnostic<usize>> {
+    let mut annotations: Vec<_> = ir
+        .modules
+        .values()
+        .filter_map(|m| {
+            let loc = m.no_runtime.as_ref()?;
+            let file_id = filenames.iter().position(|v| v.0 == loc.filename)?;
+            Some(
+                Label::secondary(file_id, loc.span.clone())
+                    .with_message(catalog.message("E0022.annotation", &[])),
+            )
+        })
+        .collect();
+    annotations.sort_by_key(|v| (v.file_id, v.range.start));
+
+    uses.into_iter()
+        .map(|(func, feature)| {
+            let mut labels = vec![];
+            if let Some((file_id, loc)) = find_lowered_func(filenames, ir, &func) {
+                labels.push(
+                    Label::primary(file_id, loc.span.clone())
+                        .with_message(catalog.message("E0022.func", &[&func])),
+                );
+            }
+            labels.extend(annotations.iter().cloned());
+
+            let mut notes = vec![];
+            if feature.allocates() {
+                notes.push(catalog.message("E0022.heap", &[&feature]));
+            }
+            notes.push(catalog.message("E0022.help", &[]));
+
+            Diagnostic::error()
+                .with_code("E0022")
+                .with_message(catalog.message("E0022", &[&feature]))
+                .with_labels(labels)
+                .with_notes(notes)
+        })
+        .collect()
+}
+
+// Finds the file id and location of the function a lowered function was generated from, if it was
+// written in one of the files in `filenames`.
+fn find_lowered_func<'a>(
+    filenames: &[(String, bool)],
+    ir: &'a Ir,
+    name: &str,
+) -> Option<(usize, &'a ir::Location)> {
+    // Functions from every module but the first are qualified with their module name when lowered
+    let func = ir.modules.values().find_map(|m| {
+        m.funcs.get(name).or_else(|| {
+            name.strip_prefix(&format!("{}::", m.name))
+                .and_then(|v| m.funcs.get(v))
+        })
+    })?;
+
+    let file_id = filenames.iter().position(|v| v.0 == func.loc.filename)?;
+    Some((file_id, &func.loc))
+}
+
+/// How human readable diagnostics are rendered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticStyle {
+    /// Colours and box drawing characters.
+    Default,
+
+    /// Box drawing characters without colours.
+    Plain,
+
+    /// ASCII characters without colours.
+    Ascii,
+
+    /// Bold colours that do not rely on telling red and green apart.
+    HighContrast,
+}
+
+impl DiagnosticStyle {
+    /// The names of the styles as given on the command line.
+    pub const NAMES: &'static [&'static str] = &["default", "plain", "ascii", "high-contrast"];
+
+    /// Gets the style with the given name.
+    pub fn from_name(name: &str) -> Option<DiagnosticStyle> {
+        match name {
+            "default" => Some(DiagnosticStyle::Default),
+            "plain" => Some(DiagnosticStyle::Plain),
+            "ascii" => Some(DiagnosticStyle::Ascii),
+            "high-contrast" => Some(DiagnosticStyle::HighContrast),
+            _ => None,
+        }
+    }
+
+    /// Gets whether diagnostics in this style may be coloured.
+    pub fn color_choice(self) -> ColorChoice {
+        match self {
+            DiagnosticStyle::Default | DiagnosticStyle::HighContrast => ColorChoice::Auto,
+            DiagnosticStyle::Plain | DiagnosticStyle::Ascii => ColorChoice::Never,
+        }
+    }
+
+    /// Gets the configuration used to render diagnostics in this style.
+    pub fn config(self) -> term::Config {
+        match self {
+            DiagnosticStyle::Default | DiagnosticStyle::Plain => term::Config::default(),
+
+            DiagnosticStyle::Ascii => term::Config {
+                chars: Chars::ascii(),
+                ..term::Config::default()
+            },
+
+            DiagnosticStyle::HighContrast => {
+                let bold = |color| {
+                    let mut spec = ColorSpec::new();
+                    spec.set_fg(Some(color)).set_bold(true).set_intense(true);
+                    spec
+                };
+
+                let mut styles = Styles::with_blue(Color::Cyan);
+                styles.header_bug = bold(Color::Magenta);
+                styles.header_error = bold(Color::Magenta);
+                styles.header_warning = bold(Color::Yellow);
+                styles.header_note = bold(Color::Cyan);
+                styles.header_help = bold(Color::White);
+                styles.primary_label_bug = bold(Color::Magenta);
+                styles.primary_label_error = bold(Color::Magenta);
+                styles.primary_label_warning = bold(Color::Yellow);
+                styles.primary_label_note = bold(Color::Cyan);
+                styles.primary_label_help = bold(Color::White);
+                styles.secondary_label = bold(Color::Cyan);
+
+                term::Config {
+                    styles,
+                    ..term::Config::default()
+                }
+            }
+        }
+    }
+}
+
+/// Renders a diagnostic as a single line of JSON containing its severity, message, notes, and
+/// labels. Each label contains the file name, the byte span, and the line and column of the start
+/// of the span.
+pub fn diagnostic_to_json(
+    diagnostic: &Diagnostic<usize>,
+    files: &SimpleFiles<&String, String>,
+) -> String {
+    let severity = match diagnostic.severity {
+        Severity::Bug => "bug",
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+        Severity::Help => "help",
+    };
+
+    let mut json = String::new();
+    write!(
+        json,
+        "{{\"severity\":\"{}\",\"message\":\"{}\",\"labels\":[",
+        severity,
+        escape_json(&diagnostic.message)
+    )
+    .unwrap();
+
+    for (i, label) in diagnostic.labels.iter().enumerate() {
+        if i != 0 {
+            json.push(',');
+        }
+
+        let style = match label.style {
+            LabelStyle::Primary => "primary",
+            LabelStyle::Secondary => "secondary",
+        };
+        let file = files
+            .name(label.file_id)
+            .map(|v| v.to_string())
+            .unwrap_or_default();
+        write!(
+            json,
+            "{{\"style\":\"{}\",\"file\":\"{}\",\"span\":{{\"start\":{},\"end\":{}}}",
+            style,
+            escape_json(&file),
+            label.range.start,
+            label.range.end
+        )
+        .unwrap();
+
+        if let Ok(location) = files.location(label.file_id, label.range.start) {
+            write!(
+                json,
+                ",\"line\":{},\"column\":{}",
+                location.line_number, location.column_number
+            )
+            .unwrap();
+        }
+
+        write!(json, ",\"message\":\"{}\"}}", escape_json(&label.message)).unwrap();
+    }
+
+    json.push_str("],\"notes\":[");
+    for (i, note) in diagnostic.notes.iter().enumerate() {
+        if i != 0 {
+            json.push(',');
+        }
+        write!(json, "\"{}\"", escape_json(note)).unwrap();
+    }
+    json.push_str("]}");
+
+    json
+}
+
+/// Escapes a string so that it can be put inside a JSON string literal.
+pub fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
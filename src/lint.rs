@@ -0,0 +1,475 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use logos::Span;
+
+use crate::frontend::parser::{self, Ast, Token};
+use crate::messages::Catalog;
+
+/// How deeply functions, match arms, and let bodies may be nested before the nesting rule reports
+/// them.
+pub const MAX_NESTING: usize = 4;
+
+/// A rule checked by the linter.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// Values should be named in snake case and types in upper camel case.
+    Naming,
+
+    /// Functions, match arms, and let bodies should not be nested more than `MAX_NESTING` deep.
+    Nesting,
+
+    /// Local names should not shadow other local names or top level definitions.
+    Shadowing,
+
+    /// Parentheses around a single value are redundant.
+    RedundantParens,
+
+    /// Arguments and let bindings should be used, unless their names start with `_`.
+    UnusedVariable,
+
+    /// Top level definitions should be used, unless they are `main` or marked `pub`.
+    UnusedFunction,
+
+    /// Match arms after an arm that matches every value are never reached.
+    Unreachable,
+}
+
+impl Rule {
+    /// Every rule, in the order they are listed.
+    pub const ALL: &'static [Rule] = &[
+        Rule::Naming,
+        Rule::Nesting,
+        Rule::Shadowing,
+        Rule::RedundantParens,
+        Rule::UnusedVariable,
+        Rule::UnusedFunction,
+        Rule::Unreachable,
+    ];
+
+    /// Gets the name of the rule as written in the manifest.
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::Naming => "naming",
+            Rule::Nesting => "nesting",
+            Rule::Shadowing => "shadowing",
+            Rule::RedundantParens => "redundant-parens",
+            Rule::UnusedVariable => "unused-variable",
+            Rule::UnusedFunction => "unused-function",
+            Rule::Unreachable => "unreachable",
+        }
+    }
+
+    /// Gets a rule by its name.
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.iter().copied().find(|v| v.name() == name)
+    }
+}
+
+/// How problems found by a rule are reported.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Level {
+    /// The rule is not checked.
+    Allow,
+
+    /// Problems are reported as warnings.
+    Warn,
+
+    /// Problems are reported as errors.
+    Deny,
+}
+
+impl Level {
+    /// Gets a level by its name as written in the manifest.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// The level of each rule. Rules that are not configured are warned about.
+#[derive(Debug, Default, Clone)]
+pub struct LintConfig {
+    levels: HashMap<Rule, Level>,
+    warnings_as_errors: bool,
+}
+
+impl LintConfig {
+    /// Sets the level of a rule.
+    pub fn set(&mut self, rule: Rule, level: Level) {
+        self.levels.insert(rule, level);
+    }
+
+    /// Makes every rule that would be warned about denied instead.
+    pub fn deny_warnings(&mut self) {
+        self.warnings_as_errors = true;
+    }
+
+    /// Gets the level of a rule.
+    pub fn level(&self, rule: Rule) -> Level {
+        match self.levels.get(&rule).copied().unwrap_or(Level::Warn) {
+            Level::Warn if self.warnings_as_errors => Level::Deny,
+            level => level,
+        }
+    }
+}
+
+/// A problem found by a rule.
+#[derive(Debug)]
+pub struct Lint {
+    pub rule: Rule,
+    pub level: Level,
+    pub span: Span,
+
+    /// The key of the message in the message catalog.
+    pub key: &'static str,
+
+    /// The arguments substituted into the message.
+    pub args: Vec<String>,
+}
+
+impl Lint {
+    /// Gets the message of the problem from a message catalog.
+    pub fn message(&self, catalog: &Catalog) -> String {
+        let args: Vec<&dyn Display> = self.args.iter().map(|v| v as &dyn Display).collect();
+        catalog.message(self.key, &args)
+    }
+}
+
+/// Runs every rule that is not allowed over a parsed file. The problems found are ordered by
+/// where they start.
+pub fn lint(contents: &str, asts: &[Ast], config: &LintConfig) -> Vec<Lint> {
+    let mut linter = Linter {
+        config,
+        lints: vec![],
+        globals: HashSet::new(),
+        locals: vec![],
+        used: HashSet::new(),
+        current: None,
+    };
+
+    // Definitions marked `pub` may be used by other modules, so they are never unused
+    let mut definitions = vec![];
+    let mut public = false;
+    for ast in asts {
+        match ast {
+            Ast::Assign(span, name, _)
+            | Ast::AssignTyped(span, name, _, _)
+            | Ast::AssignFunction(span, name, _, _) => {
+                linter.globals.insert(name.clone());
+                if !public {
+                    definitions.push((name, span));
+                }
+            }
+
+            Ast::AssignTuple(span, names, _) => {
+                linter.globals.extend(names.iter().cloned());
+                if !public {
+                    definitions.extend(names.iter().map(|v| (v, span)));
+                }
+            }
+
+            _ => (),
+        }
+
+        // `pub` applies to the next definition, past any annotations and signatures
+        public = match ast {
+            Ast::Pub(_) => true,
+            Ast::Annotation(_, _) | Ast::Signature(_, _, _) => public,
+            _ => false,
+        };
+    }
+
+    for ast in asts {
+        linter.top_level(ast);
+    }
+
+    for (name, span) in definitions {
+        if name != "main" && !name.starts_with('_') && !linter.used.contains(name) {
+            linter.report(
+                Rule::UnusedFunction,
+                span.clone(),
+                "unused-function",
+                &[&name],
+            );
+        }
+    }
+
+    // Redundant parentheses are not kept in the ast, so they are found from the tokens instead
+    let tokens = parser::tokenize(contents);
+    for window in tokens.windows(3) {
+        if let [(Token::LParen, open), (token, _), (Token::RParen, close)] = window {
+            if matches!(
+                token,
+                Token::Symbol
+                    | Token::Int(_)
+                    | Token::Float(_)
+                    | Token::Word(_)
+                    | Token::Char(_)
+                    | Token::Generic(_)
+            ) {
+                linter.report(
+                    Rule::RedundantParens,
+                    open.start..close.end,
+                    "redundant-parens",
+                    &[],
+                );
+            }
+        }
+    }
+
+    let mut lints = linter.lints;
+    lints.sort_by_key(|v| v.span.start);
+    lints
+}
+
+// Walks an ast and records the problems found.
+struct Linter<'a> {
+    config: &'a LintConfig,
+    lints: Vec<Lint>,
+    globals: HashSet<String>,
+    locals: Vec<(String, Span, bool)>,
+
+    // Top level definitions used outside of their own definition
+    used: HashSet<String>,
+    current: Option<String>,
+}
+
+impl<'a> Linter<'a> {
+    // report(&mut self, Rule, Span, &'static str, &[&dyn Display]) -> ()
+    // Records a problem with the message with the given key unless the rule is allowed.
+    fn report(&mut self, rule: Rule, span: Span, key: &'static str, args: &[&dyn Display]) {
+        let level = self.config.level(rule);
+        if level != Level::Allow {
+            self.lints.push(Lint {
+                rule,
+                level,
+                span,
+                key,
+                args: args.iter().map(|v| v.to_string()).collect(),
+            });
+        }
+    }
+
+    // top_level(&mut self, &Ast) -> ()
+    // Lints a top level definition.
+    fn top_level(&mut self, ast: &Ast) {
+        match ast {
+            Ast::Assign(span, name, value) | Ast::AssignTyped(span, name, _, value) => {
+                self.check_value_name(name, span);
+                self.current = Some(name.clone());
+                self.expr(value, 0);
+            }
+
+            Ast::AssignFunction(span, name, args, body) => {
+                self.check_value_name(name, span);
+                self.current = Some(name.clone());
+                self.function(span, args, body, 0);
+            }
+
+            Ast::AssignTuple(span, names, value) => {
+                for name in names {
+                    self.check_value_name(name, span);
+                }
+                self.current = None;
+                self.expr(value, 0);
+            }
+
+            // Macro arguments are not bound, so only uses of top level definitions are recorded
+            Ast::Macro(_, _, _, body) => {
+                self.current = None;
+                self.expr(body, 0);
+            }
+
+            Ast::AssignType(span, name, _) | Ast::AssignNewtype(span, name, _) => {
+                if !is_upper_camel_case(name) {
+                    self.report(Rule::Naming, span.clone(), "naming.type", &[&name]);
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    // expr(&mut self, &Ast, usize) -> ()
+    // Lints an expression nested `depth` functions, match arms, and let bodies deep.
+    fn expr(&mut self, ast: &Ast, depth: usize) {
+        match ast {
+            Ast::Lambda(span, args, body) => self.function(span, args, body, depth),
+
+            Ast::Symbol(_, name) => self.use_name(name),
+
+            // Qualified names refer to other modules
+            Ast::Infix(_, op, _, _) if op == "::" => (),
+
+            Ast::Application(_, func, args) => {
+                self.expr(func, depth);
+                for arg in args {
+                    self.expr(arg, depth);
+                }
+            }
+
+            Ast::Prefix(_, _, value)
+            | Ast::As(_, value, _)
+            | Ast::Walrus(_, _, value)
+            | Ast::Field(_, value, _) => self.expr(value, depth),
+
+            Ast::Record(_, fields) => {
+                for (_, value) in fields {
+                    self.expr(value, depth);
+                }
+            }
+
+            Ast::Infix(_, _, left, right) => {
+                self.expr(left, depth);
+                self.expr(right, depth);
+            }
+
+            Ast::List(_, values) | Ast::Tuple(_, values) => {
+                for value in values {
+                    self.expr(value, depth);
+                }
+            }
+
+            Ast::Match(span, value, arms) => {
+                self.expr(value, depth);
+                self.check_nesting(span, depth + 1);
+                for (_, arm) in arms {
+                    self.expr(arm, depth + 1);
+                }
+
+                // An arm for a bare generic matches every value
+                if let Some(i) = arms.iter().position(|v| matches!(v.0, Ast::Generic(_, _))) {
+                    for (_type, arm) in arms.iter().skip(i + 1) {
+                        self.report(
+                            Rule::Unreachable,
+                            _type.get_span().start..arm.get_span().end,
+                            "unreachable",
+                            &[],
+                        );
+                    }
+                }
+            }
+
+            Ast::With(span, assigns, body) => {
+                let len = self.locals.len();
+                for assign in assigns {
+                    match assign {
+                        Ast::Assign(span, name, value) | Ast::AssignTyped(span, name, _, value) => {
+                            self.expr(value, depth);
+                            self.bind(name, span);
+                        }
+
+                        Ast::AssignFunction(span, name, args, body) => {
+                            self.bind(name, span);
+                            self.function(span, args, body, depth);
+                        }
+
+                        Ast::AssignTuple(span, names, value) => {
+                            self.expr(value, depth);
+                            for name in names {
+                                self.bind(name, span);
+                            }
+                        }
+
+                        _ => (),
+                    }
+                }
+
+                self.check_nesting(span, depth + 1);
+                self.expr(body, depth + 1);
+                self.end_scope(len);
+            }
+
+            _ => (),
+        }
+    }
+
+    // function(&mut self, &Span, &[(String, Ast)], &Ast, usize) -> ()
+    // Lints a function with the given arguments and body.
+    fn function(&mut self, span: &Span, args: &[(String, Ast)], body: &Ast, depth: usize) {
+        let len = self.locals.len();
+        for (arg, _) in args {
+            self.bind(arg, span);
+        }
+
+        self.check_nesting(span, depth + 1);
+        self.expr(body, depth + 1);
+        self.end_scope(len);
+    }
+
+    // bind(&mut self, &str, &Span) -> ()
+    // Brings a local name into scope, checking its name and whether it shadows another name.
+    fn bind(&mut self, name: &str, span: &Span) {
+        self.check_value_name(name, span);
+
+        if self.locals.iter().any(|v| v.0 == name) {
+            self.report(Rule::Shadowing, span.clone(), "shadowing.local", &[&name]);
+        } else if self.globals.contains(name) {
+            self.report(
+                Rule::Shadowing,
+                span.clone(),
+                "shadowing.top-level",
+                &[&name],
+            );
+        }
+
+        self.locals.push((String::from(name), span.clone(), false));
+    }
+
+    // use_name(&mut self, &str) -> ()
+    // Records a use of the innermost local with the given name, or of the top level definition
+    // with that name if there is no such local.
+    fn use_name(&mut self, name: &str) {
+        if let Some(local) = self.locals.iter_mut().rev().find(|v| v.0 == name) {
+            local.2 = true;
+        } else if self.current.as_deref() != Some(name) {
+            self.used.insert(String::from(name));
+        }
+    }
+
+    // end_scope(&mut self, usize) -> ()
+    // Removes every local brought into scope after the first `len`, reporting the ones that were
+    // never used.
+    fn end_scope(&mut self, len: usize) {
+        for (name, span, used) in self.locals.split_off(len) {
+            if !used && !name.starts_with('_') {
+                self.report(Rule::UnusedVariable, span, "unused-variable", &[&name]);
+            }
+        }
+    }
+
+    // check_value_name(&mut self, &str, &Span) -> ()
+    // Checks that a value is named in snake case.
+    fn check_value_name(&mut self, name: &str, span: &Span) {
+        if !is_snake_case(name) {
+            self.report(Rule::Naming, span.clone(), "naming.value", &[&name]);
+        }
+    }
+
+    // check_nesting(&mut self, &Span, usize) -> ()
+    // Checks that an expression is not nested too deeply. Only the outermost expression that is
+    // too deep is reported.
+    fn check_nesting(&mut self, span: &Span, depth: usize) {
+        if depth == MAX_NESTING + 1 {
+            self.report(Rule::Nesting, span.clone(), "nesting", &[&MAX_NESTING]);
+        }
+    }
+}
+
+// is_snake_case(&str) -> bool
+// Checks whether a name only uses lowercase letters, digits, underscores, and primes.
+fn is_snake_case(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '\'')
+}
+
+// is_upper_camel_case(&str) -> bool
+// Checks whether a name starts with an uppercase letter and has no underscores.
+fn is_upper_camel_case(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase()) && !name.contains('_')
+}
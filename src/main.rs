@@ -1,28 +1,42 @@
-use clap::{crate_version, App, Arg, SubCommand};
-use faerie::{ArtifactBuilder, Decl, Link};
+use clap::{crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use codespan_reporting::files::SimpleFiles;
+use codespan_reporting::term;
+use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+use faerie::{ArtifactBuilder, Decl, Link, Reloc};
 use goblin::Object;
-use rustyline::{error::ReadlineError, Editor};
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{error::ReadlineError, Config, Context, Editor, Helper};
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
 use std::env;
 use std::fs::{self, File};
+use std::path::PathBuf;
 use std::process::exit;
 use target_lexicon::Triple;
 
 #[allow(unused_imports)]
 use closeyc::backends::{
-    aarch64, ir as backend_ir, riscv64, wasm64, x86_64, GeneratedCode, DEFAULT_ARCH,
+    self as backends, aarch64,
+    callgraph::{self, StackUsage},
+    escape, ir as backend_ir, riscv64, wasm64, x86_64, GeneratedCode, RelocationError,
+    DEFAULT_ARCH,
 };
+use closeyc::coverage::Coverage;
 use closeyc::frontend::correctness;
+use closeyc::frontend::interpreter::{Interpreter, Reduction};
 use closeyc::frontend::ir as frontend_ir;
 use closeyc::frontend::parser;
-
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-static MAP_JIT: i32 = 0x0800;
-#[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
-static MAP_JIT: i32 = 0;
-
-extern "C" {
-    fn pthread_jit_write_protect_np(_: bool);
-}
+use closeyc::jit::{Jit, JitError};
+use closeyc::lint::{Level, LintConfig, Rule};
+use closeyc::manifest::{Manifest, Profile, MANIFEST_NAME};
+use closeyc::messages::{self, Catalog, CATALOGS};
+use closeyc::profile;
+use closeyc::repl::ReplConfig;
+use closeyc::DiagnosticStyle;
 
 #[derive(Debug)]
 enum CloseyCode<'a> {
@@ -31,55 +45,206 @@ enum CloseyCode<'a> {
     Files(Vec<&'a str>),
 }
 
-struct Jit {
-    code: GeneratedCode,
-    mem: *const u8,
+#[derive(Default)]
+struct Limits {
+    memory: Option<u64>,
+    time: Option<u32>,
 }
 
-impl Jit {
-    fn new(mut code: GeneratedCode) -> Jit {
-        let mem = unsafe {
-            libc::mmap(
-                std::ptr::null_mut(),
-                code.len(),
-                libc::PROT_WRITE | libc::PROT_READ,
-                libc::MAP_ANONYMOUS | libc::MAP_PRIVATE | MAP_JIT,
-                -1,
-                0,
-            )
-        } as *mut u8;
+// Writes the passes recorded by the self profiler to a file when dropped. Files ending in
+// `.folded` get folded stacks for flamegraphs, and every other file gets a Chrome trace. Nothing is
+// written if the compiler exits early because of an error.
+struct SelfProfile {
+    path: String,
+}
 
-        match DEFAULT_ARCH {
-            "aarch64" => todo!(),
-            "riscv64" => todo!(),
-            "wasm64" => todo!(),
-            "x86_64" => x86_64::codegen::relocate(&mut code),
-            _ => panic!("unsupported architecture!"),
+impl Drop for SelfProfile {
+    fn drop(&mut self) {
+        let events = profile::take_events();
+        let contents = if self.path.ends_with(".folded") {
+            profile::to_folded(&events)
+        } else {
+            profile::to_chrome_trace(&events)
+        };
+
+        if let Err(e) = fs::write(&self.path, contents) {
+            eprintln!("Error writing self profile {}: {}", self.path, e);
         }
+    }
+}
 
-        unsafe {
-            pthread_jit_write_protect_np(false);
-            std::ptr::copy(code.data().as_ptr(), mem, code.len());
-            libc::mprotect(
-                mem as *mut libc::c_void,
-                code.len(),
-                libc::PROT_READ | libc::PROT_EXEC,
-            );
-            pthread_jit_write_protect_np(true);
+// The number of entries kept in the REPL history unless --history-size is given.
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+// Commands understood by the REPL along with their arguments and help text.
+const REPL_COMMANDS: &[(&str, &str, &str)] = &[
+    (
+        ":asm",
+        "[name]",
+        "Prints the assembly of a definition, or of the last one",
+    ),
+    (":clear", "", "Forgets every definition"),
+    (":env", "", "Prints every definition and its type"),
+    (":help", "", "Prints this list of commands"),
+    (
+        ":ir",
+        "[name]",
+        "Prints the frontend IR of a definition, or of the last one",
+    ),
+    (
+        ":llir",
+        "[name]",
+        "Prints the low level IR of a definition, or of the last one",
+    ),
+    (
+        ":paste",
+        "",
+        "Reads lines until `:end` or Ctrl-D and enters them as one block",
+    ),
+    (":quit", "", "Exits the REPL"),
+];
+
+// Keywords completed in the REPL.
+const KEYWORDS: &[&str] = &[
+    "extern", "import", "in", "let", "macro", "match", "module", "newtype", "pub", "to", "type",
+    "where",
+];
+
+// Completes names in the REPL from the definitions entered so far and highlights input.
+#[derive(Default)]
+struct ReplHelper {
+    names: Vec<String>,
+    color: bool,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !(c.is_alphanumeric() || c == '_' || c == '\'') || c == 'λ')
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+        let prefix = &line[start..pos];
+
+        // Commands are only completed at the start of the line
+        if start == 1 && line.starts_with(':') {
+            let candidates = REPL_COMMANDS
+                .iter()
+                .filter(|v| v.0[1..].starts_with(prefix))
+                .map(|v| String::from(v.0))
+                .collect();
+            return Ok((0, candidates));
+        }
+
+        let mut candidates: Vec<_> = self
+            .names
+            .iter()
+            .map(|v| v.as_str())
+            .chain(KEYWORDS.iter().copied())
+            .filter(|v| v.starts_with(prefix))
+            .map(String::from)
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _: usize) -> Cow<'l, str> {
+        use parser::Token;
+
+        if !self.color {
+            return Cow::Borrowed(line);
+        }
+
+        let mut highlighted = String::with_capacity(line.len());
+        let mut last = 0;
+        for (token, span) in parser::tokenize(line) {
+            let color = match token {
+                Token::Let
+                | Token::In
+                | Token::Import
+                | Token::Module
+                | Token::Extern
+                | Token::Type
+                | Token::Newtype
+                | Token::Pointer
+                | Token::Match
+                | Token::To
+                | Token::Macro
+                | Token::Where
+                | Token::Pub => "\x1b[1;35m",
+                Token::Int(_)
+                | Token::Float(_)
+                | Token::Word(_)
+                | Token::Char(_)
+                | Token::String(_) => "\x1b[32m",
+                Token::Generic(_) | Token::Annotation => "\x1b[36m",
+                Token::Operator
+                | Token::Assign
+                | Token::Backslash
+                | Token::Dot
+                | Token::Dollar
+                | Token::Semicolon
+                | Token::Bar
+                | Token::PlusArrow
+                | Token::RightArrow
+                | Token::ThiccArrow => "\x1b[33m",
+                Token::Error => "\x1b[31m",
+                _ => continue,
+            };
+
+            highlighted.push_str(&line[last..span.start]);
+            highlighted.push_str(color);
+            highlighted.push_str(&line[span.clone()]);
+            highlighted.push_str("\x1b[0m");
+            last = span.end;
         }
+        highlighted.push_str(&line[last..]);
 
-        Jit { code, mem }
+        Cow::Owned(highlighted)
     }
 
-    unsafe fn call(&self, func: &str) -> Option<*const u8> {
-        self.code.get_fn(func, self.mem).map(|v| v())
+    fn highlight_char(&self, _: &str, _: usize) -> bool {
+        self.color
     }
 }
 
-impl Drop for Jit {
-    fn drop(&mut self) {
-        unsafe {
-            libc::munmap(self.mem as *mut libc::c_void, self.code.len());
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ErrorFormat {
+    Human(DiagnosticStyle, &'static Catalog),
+    Json(&'static Catalog),
+
+    // Used by the machine REPL: diagnostics and results are printed to stdout as JSON objects
+    Machine(&'static Catalog),
+}
+
+impl ErrorFormat {
+    // catalog(self) -> &Catalog
+    // Gets the catalog diagnostic messages are taken from.
+    fn catalog(self) -> &'static Catalog {
+        match self {
+            ErrorFormat::Human(_, catalog)
+            | ErrorFormat::Json(catalog)
+            | ErrorFormat::Machine(catalog) => catalog,
         }
     }
 }
@@ -94,10 +259,73 @@ fn main() {
         .short("e")
         .min_values(1)
         .max_values(1);
+    let verbose = Arg::with_name("verbose")
+        .long("verbose")
+        .short("v")
+        .help("Reports how call sites were compiled and how to devirtualise them");
+    let error_format = Arg::with_name("error-format")
+        .long("error-format")
+        .help("The format diagnostics are printed in")
+        .possible_values(&["human", "json"])
+        .default_value("human");
+    let rule_names: Vec<_> = Rule::ALL.iter().map(|v| v.name()).collect();
+    let warn = Arg::with_name("warn")
+        .short("W")
+        .help("Warns about problems found by the given lint rule; `-Werror` reports every warning as an error instead")
+        .possible_values(&[rule_names.as_slice(), &["error"]].concat())
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1);
+    let allow = Arg::with_name("allow")
+        .short("A")
+        .help("Does not check the given lint rule")
+        .possible_values(&rule_names)
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1);
+    let json_repl = Arg::with_name("json-repl")
+        .long("json-repl")
+        .help("Runs the REPL in machine mode, printing every result as one line of JSON");
+    let history = Arg::with_name("history")
+        .long("history")
+        .help("The file REPL history is kept in; by default this is in the user's data directory")
+        .takes_value(true);
+    let history_size = Arg::with_name("history-size")
+        .long("history-size")
+        .help("The number of entries kept in the REPL history; by default this is 1000")
+        .takes_value(true);
+    let init = Arg::with_name("init")
+        .long("init")
+        .help("A file whose definitions are entered into the REPL before the first prompt")
+        .takes_value(true);
     let app =
         App::new("closeyc")
             .version(crate_version!())
             .about("Compiler for the Closey language.")
+            .setting(AppSettings::GlobalVersion)
+            .arg(
+                Arg::with_name("lang")
+                    .long("lang")
+                    .help("The language diagnostics are printed in")
+                    .possible_values(&CATALOGS.iter().map(|v| v.lang).collect::<Vec<_>>())
+                    .takes_value(true)
+                    .global(true),
+            )
+            .arg(
+                Arg::with_name("self-profile")
+                    .long("self-profile")
+                    .help("Writes how long each compiler pass took to the given file, as a Chrome trace or as folded stacks if the file ends with .folded")
+                    .takes_value(true)
+                    .global(true),
+            )
+            .arg(
+                Arg::with_name("diagnostic-style")
+                    .long("diagnostic-style")
+                    .help("The characters and colours used to print diagnostics")
+                    .possible_values(DiagnosticStyle::NAMES)
+                    .takes_value(true)
+                    .global(true),
+            )
             .subcommand(
                 SubCommand::with_name("build")
                     .about("Builds Closey code and exports as an object file.")
@@ -109,13 +337,97 @@ fn main() {
                             .min_values(1)
                             .max_values(1),
                     )
-                    .arg(files.clone().help("The Closey files to compile."))
+                    .arg(
+                        Arg::with_name("emit")
+                            .long("emit")
+                            .help("Emits the given artefact instead of an object file")
+                            .possible_values(&[
+                                "ast",
+                                "ir",
+                                "backend-ir",
+                                "asm",
+                                "callgraph",
+                                "stack-usage",
+                                "size",
+                            ])
+                            .min_values(1)
+                            .max_values(1),
+                    )
+                    .arg(
+                        Arg::with_name("verify-reproducible")
+                            .long("verify-reproducible")
+                            .help("Builds twice and checks that every phase gives the same output both times")
+                            .conflicts_with("emit"),
+                    )
+                    .arg(
+                        Arg::with_name("profile")
+                            .long("profile")
+                            .help("Builds under the restrictions of the given profile; `embedded` builds freestanding code that never allocates on the heap and reports the stack used by every function")
+                            .possible_values(&["default", "embedded"])
+                            .min_values(1)
+                            .max_values(1),
+                    )
+                    .arg(
+                        Arg::with_name("no-runtime")
+                            .long("no-runtime")
+                            .help("Builds freestanding code that never calls into the runtime, as if every file had `@no_runtime`"),
+                    )
+                    .arg(verbose.clone())
+                    .arg(error_format.clone())
+                    .arg(
+                        // Files are optional here since they can be given by the manifest
+                        files
+                            .clone()
+                            .required(false)
+                            .help("The Closey files to compile; `-` reads from stdin. If no files are given, the files in closey.toml are built."),
+                    )
                     .arg(exec.clone().help("A Closey command to compile.")),
             )
             .subcommand(
                 SubCommand::with_name("run")
                     .about("Runs Closey code by JIT compiling it.")
-                    .arg(files.clone().help("The Closey files to run."))
+                    .arg(verbose)
+                    .arg(
+                        Arg::with_name("coverage")
+                            .long("coverage")
+                            .help("Runs the program in the interpreter instead of compiling it and writes how many times each function of every file was entered to the given file; by default this is closey.cov. Resource limits do not apply, and code the interpreter does not support, such as match expressions, stops the run")
+                            .min_values(0)
+                            .max_values(1),
+                    )
+                    .arg(
+                        Arg::with_name("max-memory")
+                            .long("max-memory")
+                            .help("Limits the memory the program can map while it runs, in bytes with an optional K, M, or G suffix; without /proc the limit covers the whole compiler process")
+                            .takes_value(true)
+                            .validator(|v| match parse_memory(&v) {
+                                Some(_) => Ok(()),
+                                None => Err(format!("invalid memory size `{}`", v)),
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("max-time")
+                            .long("max-time")
+                            .help("Limits how long the program can run, in seconds")
+                            .takes_value(true)
+                            .validator(|v| match v.parse::<u32>() {
+                                Ok(_) => Ok(()),
+                                Err(_) => Err(format!("invalid number of seconds `{}`", v)),
+                            }),
+                    )
+                    .arg(error_format.clone())
+                    .arg(
+                        // Files come before `--` here so that everything after it goes to the program
+                        files
+                            .clone()
+                            .last(false)
+                            .help("The Closey files to run; `-` reads from stdin."),
+                    )
+                    .arg(
+                        Arg::with_name("args")
+                            .multiple(true)
+                            .last(true)
+                            .help("Arguments for the program, given after `--`; these are rejected until programs can receive them."),
+                    )
                     .arg(exec.clone().help("A Closey command to run.")),
             )
             .subcommand(
@@ -128,13 +440,38 @@ fn main() {
                             .short("i")
                             .help("Prints out the higher level IR"),
                     )
+                    .arg(error_format.clone())
                     .arg(files.clone().help("The Closey files to analyse."))
                     .arg(exec.clone().help("The Closey command to analyse.")),
             )
+            .subcommand(
+                SubCommand::with_name("check")
+                    .about("Checks the given Closey code for errors without generating code")
+                    .arg(
+                        Arg::with_name("explain-types")
+                            .long("explain-types")
+                            .help("Prints a step by step derivation of the type of each definition"),
+                    )
+                    .arg(warn.clone())
+                    .arg(allow.clone())
+                    .arg(error_format.clone())
+                    .arg(files.clone().help("The Closey files to check."))
+                    .arg(exec.clone().help("The Closey command to check.")),
+            )
+            .subcommand(
+                SubCommand::with_name("lint")
+                    .about("Checks Closey code for style problems using the levels in closey.toml")
+                    .arg(warn.clone())
+                    .arg(allow.clone())
+                    .arg(error_format.clone())
+                    .arg(files.clone().help("The Closey files to lint."))
+                    .arg(exec.clone().help("The Closey command to lint.")),
+            )
             .subcommand(
                 SubCommand::with_name("assembly")
                     .alias("asm")
                     .about("Prints out the assembly for the given Closey code")
+                    .arg(error_format.clone())
                     .arg(
                         files
                             .clone()
@@ -145,286 +482,1698 @@ fn main() {
                             .help("The Closey command to generate assembly for."),
                     ),
             )
+            .subcommand(
+                SubCommand::with_name("watch")
+                    .about("Checks Closey code again whenever the given files change")
+                    .arg(
+                        Arg::with_name("run")
+                            .long("run")
+                            .short("r")
+                            .help("Runs the code after each successful check"),
+                    )
+                    .arg(error_format.clone())
+                    .arg(files.clone().help("The Closey files to watch."))
+                    .arg(exec.clone().help("The Closey command to check.")),
+            )
+            .subcommand(
+                SubCommand::with_name("step")
+                    .about("Interprets Closey code one reduction at a time")
+                    .arg(
+                        Arg::with_name("break")
+                            .long("break")
+                            .short("b")
+                            .help("Runs until the given function is entered before stepping")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1),
+                    )
+                    .arg(error_format.clone())
+                    .arg(files.clone().help("The Closey files to step through."))
+                    .arg(exec.clone().help("The Closey command to step through.")),
+            )
             .subcommand(
                 SubCommand::with_name("llir")
                     .about("Prints out the low level IR for the given Closey code")
+                    .arg(error_format)
                     .arg(files.help("The Closey files to generate LLIR for."))
                     .arg(exec.help("The Closey command to generate LLIR for.")),
             )
-            .subcommand(SubCommand::with_name("repl").about(
-                "Runs the Closey REPL. If no subcommand is provided, the REPL will still run.",
-            ));
+            .subcommand(
+                SubCommand::with_name("cov")
+                    .about("Works with coverage files written by run --coverage")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        SubCommand::with_name("report")
+                            .about("Prints per line coverage of each covered file")
+                            .arg(
+                                Arg::with_name("coverage-file")
+                                    .help("The coverage file; by default this is closey.cov"),
+                            ),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("eval")
+                    .about("Runs a single Closey expression and prints its value and type")
+                    .arg(error_format.clone())
+                    .arg(
+                        Arg::with_name("expr")
+                            .required(true)
+                            .help("The expression to evaluate."),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("type")
+                    .about("Prints the type of a single Closey expression")
+                    .arg(error_format.clone())
+                    .arg(
+                        Arg::with_name("expr")
+                            .required(true)
+                            .help("The expression to get the type of."),
+                    ),
+            )
+            .arg(json_repl.clone())
+            .arg(history.clone())
+            .arg(history_size.clone())
+            .arg(init.clone())
+            .subcommand(
+                SubCommand::with_name("repl")
+                    .about(
+                        "Runs the Closey REPL. If no subcommand is provided, the REPL will still run.",
+                    )
+                    .arg(json_repl)
+                    .arg(history)
+                    .arg(history_size)
+                    .arg(init),
+            );
 
     let matches = app.get_matches();
 
     let code = match matches.subcommand_name() {
-        Some("repl") | None => CloseyCode::None,
+        Some("repl") | Some("cov") | Some("eval") | Some("type") | None => CloseyCode::None,
 
         Some(s) => {
             let matches = matches.subcommand_matches(s).unwrap();
             match matches.value_of("exec") {
                 Some(v) => CloseyCode::Exec(v),
-                None => CloseyCode::Files(matches.values_of("files").unwrap().collect()),
+                None => match matches.values_of("files") {
+                    Some(v) => CloseyCode::Files(v.collect()),
+                    None => CloseyCode::None,
+                },
             }
         }
     };
 
-    let contents = match code {
-        CloseyCode::Exec(s) => Some(s.to_owned()),
-        CloseyCode::Files(v) => match fs::read_to_string(v.first().unwrap()) {
-            Ok(s) => Some(s),
-            Err(e) => {
-                eprintln!("error reading file {}: {}", v.first().unwrap(), e);
-                exit(1);
-            }
-        },
+    // Global options may be given before or after the subcommand
+    let global_value = |name: &str| {
+        matches
+            .subcommand_name()
+            .and_then(|v| matches.subcommand_matches(v))
+            .and_then(|v| v.value_of(name))
+            .or_else(|| matches.value_of(name))
+    };
+
+    let catalog = global_value("lang")
+        .and_then(Catalog::get)
+        .unwrap_or(&messages::ENGLISH);
+    let error_format = match global_value("error-format") {
+        Some("json") => ErrorFormat::Json(catalog),
+        _ => ErrorFormat::Human(
+            global_value("diagnostic-style")
+                .and_then(DiagnosticStyle::from_name)
+                .unwrap_or(DiagnosticStyle::Default),
+            catalog,
+        ),
+    };
+
+    // The profile is written once every pass has ended, since locals are dropped in reverse order
+    let _self_profile = global_value("self-profile").map(|v| {
+        profile::enable();
+        SelfProfile {
+            path: String::from(v),
+        }
+    });
+    let _pass = profile::pass("closeyc");
+
+    let sources = match code {
+        CloseyCode::Exec(s) => Some(vec![(String::from("Main"), s.to_owned())]),
+        CloseyCode::Files(v) => Some(
+            v.into_iter()
+                .map(|f| match read_source(f) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        eprintln!("error reading file {}: {}", f, e);
+                        exit(1);
+                    }
+                })
+                .collect(),
+        ),
         CloseyCode::None => None,
     };
+    let sources = sources.map(|v| with_imports(v, error_format));
 
     match matches.subcommand_name() {
         Some("analyse") => {
-            let contents = contents.unwrap();
+            let sources = sources.unwrap();
             let mut root = frontend_ir::Ir::new();
-            check(&contents, "Main", &mut root);
+            check(&sources, &mut root, error_format);
             print!("{}", root);
         }
 
-        Some("assembly") => {
-            let contents = contents.unwrap();
+        Some("check") => {
+            let sources = sources.unwrap();
             let mut root = frontend_ir::Ir::new();
-            check(&contents, "Main", &mut root);
+            let check_matches = matches.subcommand_matches("check").unwrap();
+
+            if check_matches.is_present("explain-types") {
+                convert(&sources, &mut root, error_format);
+                match correctness::check_correctness_with_derivations(&mut root, true) {
+                    Ok(derivations) => {
+                        for derivation in derivations {
+                            println!("{}", derivation);
+                        }
+                    }
 
-            let mut module = backend_ir::convert_frontend_ir_to_backend_ir(
-                &root.modules.iter().next().unwrap().1,
-            );
+                    Err(errors) => {
+                        report_correctness_errors(&sources, errors, error_format);
+                        exit(1);
+                    }
+                }
+            } else {
+                check(&sources, &mut root, error_format);
+            }
 
-            let mut code = match compile(&mut module) {
-                Some(v) => v,
-                None => return,
-            };
+            // Code without errors is linted for warnings
+            lint(&sources, &lint_config(check_matches), error_format);
+        }
+
+        Some("lint") => {
+            let config = lint_config(matches.subcommand_matches("lint").unwrap());
+            lint(&sources.unwrap(), &config, error_format);
+        }
+
+        Some("assembly") => {
+            let sources = sources.unwrap();
+            let mut root = frontend_ir::Ir::new();
+            check(&sources, &mut root, error_format);
 
-            match DEFAULT_ARCH {
-                "aarch64" => todo!(),
-                "riscv64" => todo!(),
-                "wasm64" => todo!(),
-                "x86_64" => x86_64::codegen::relocate(&mut code),
-                _ => panic!("unsupported architecture!"),
+            let mut module = lower(&root, &sources);
+            if !supported_by_backend(&root, &sources, &module, error_format) {
+                exit(1);
             }
 
-            match DEFAULT_ARCH {
-                "aarch64" => todo!(),
-                "riscv64" => todo!(),
-                "wasm64" => todo!(),
-                "x86_64" => x86_64::disassemble(&code, std::ptr::null()),
-                _ => panic!("unsupported architecture!"),
+            if let Some(asm) = generate_assembly(&mut module) {
+                print!("{}", asm);
             }
         }
 
         Some("build") => {
-            let contents = contents.unwrap();
-            let mut root = frontend_ir::Ir::new();
-            check(&contents, "Main", &mut root);
+            let manifest = load_manifest();
+            let sources = match (sources, &manifest) {
+                (Some(v), _) => v,
+                (None, Some(manifest)) if !manifest.files.is_empty() => with_imports(
+                    manifest
+                        .files
+                        .iter()
+                        .map(|f| match read_source(f) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                eprintln!("error reading file {}: {}", f, e);
+                                exit(1);
+                            }
+                        })
+                        .collect(),
+                    error_format,
+                ),
+                (None, _) => {
+                    eprintln!("No files given and no files listed in {}", MANIFEST_NAME);
+                    exit(1);
+                }
+            };
+            let manifest = manifest.unwrap_or_default();
+
+            if let Some(backend) = &manifest.backend {
+                if backend != DEFAULT_ARCH {
+                    eprintln!(
+                        "Backend `{}` is not supported on this host; only `{}` is",
+                        backend, DEFAULT_ARCH
+                    );
+                    exit(1);
+                }
+            }
 
-            let mut module = backend_ir::convert_frontend_ir_to_backend_ir(
-                &root.modules.iter().next().unwrap().1,
-            );
+            let build_matches = matches.subcommand_matches("build").unwrap();
+            let emit = build_matches.value_of("emit");
+            let profile = build_matches
+                .value_of("profile")
+                .and_then(Profile::from_name)
+                .unwrap_or(manifest.profile);
 
-            let mut code = match compile(&mut module) {
-                Some(v) => v,
-                None => return,
-            };
+            // The runtime is the only code that allocates on the heap
+            let no_runtime = build_matches.is_present("no-runtime") || profile == Profile::Embedded;
 
-            match DEFAULT_ARCH {
-                "aarch64" => todo!(),
-                "riscv64" => todo!(),
-                "wasm64" => todo!(),
-                "x86_64" => x86_64::codegen::generate_start_func(&mut code),
-                _ => panic!("unsupported architecture!"),
+            if build_matches.is_present("verify-reproducible") {
+                verify_reproducible(&sources, manifest.far_calls, no_runtime, error_format);
+                return;
             }
 
-            let f = matches
-                .subcommand_matches("build")
-                .unwrap()
-                .value_of("output")
-                .unwrap_or("a.o")
-                .to_owned();
-
-            let mut artefact = ArtifactBuilder::new(Triple::host())
-                .name(f.clone())
-                .finish();
-
-            let mut funcs: Vec<_> = code.get_funcs().iter().collect();
-            funcs.sort_by(|a, b| a.1.start.cmp(&b.1.start));
-            match artefact.declarations({
-                funcs.iter().map(|v| {
-                    (
-                        v.0,
-                        if v.0 == "_start" || v.0 == "main" {
-                            Decl::function().global().into()
-                        } else if v.1.start == 0 && v.1.end == 0 {
-                            Decl::function_import().into()
-                        } else {
-                            Decl::function().into()
-                        },
-                    )
-                })
-            }) {
-                Ok(_) => (),
-                Err(e) => {
-                    eprintln!("Error declaring functions: {}", e);
-                    return;
+            if emit == Some("ast") {
+                let mut artefact = String::new();
+                for (filename, contents) in sources.iter() {
+                    let ast = parse(contents, filename, error_format);
+                    artefact.push_str(&format!("{:#?}\n", ast));
                 }
+                write_artefact(build_matches.value_of("output"), &artefact);
+                return;
             }
 
-            for (func, range) in funcs {
-                if range.start == 0 && range.end == 0 {
-                    continue;
-                }
+            let mut root = frontend_ir::Ir::new();
+            check(&sources, &mut root, error_format);
 
-                match artefact.define(func, code.data()[range.start..range.end].to_owned()) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        eprintln!("Error defining function: {}", e);
-                        return;
-                    }
-                }
+            if emit == Some("ir") {
+                write_artefact(build_matches.value_of("output"), &root.to_string());
+                return;
             }
 
-            for (addr, to) in code.get_relocation_table() {
-                for (from, range) in code.get_funcs() {
-                    if range.start <= *addr && *addr < range.end {
-                        match artefact.link(Link {
-                            from,
-                            to,
-                            at: (addr - range.start) as u64,
-                        }) {
-                            Ok(_) => (),
-                            Err(e) => {
-                                eprintln!("Error linking: {}", e);
-                                return;
-                            }
+            let mut module = lower(&root, &sources);
+
+            if build_matches.is_present("verbose") {
+                report_call_statistics(&module);
+            }
+
+            if let Some(emit) = emit {
+                let artefact = match emit {
+                    "backend-ir" => format!("{}\n", module),
+                    "callgraph" => callgraph::generate_callgraph(&module),
+                    "stack-usage" => match stack_usage_report(&mut module) {
+                        Some(v) => v,
+                        None => {
+                            eprintln!(
+                                "Stack usage cannot be computed for backend `{}` yet",
+                                DEFAULT_ARCH
+                            );
+                            exit(1);
+                        }
+                    },
+                    "asm" => {
+                        if !supported_by_backend(&root, &sources, &module, error_format) {
+                            exit(1);
+                        }
+
+                        match generate_assembly(&mut module) {
+                            Some(v) => v,
+                            None => return,
                         }
-                        break;
                     }
-                }
+                    "size" => {
+                        if !supported_by_backend(&root, &sources, &module, error_format) {
+                            exit(1);
+                        }
+
+                        match compile(&mut module) {
+                            Some(code) => code_size_report(&code),
+                            None => return,
+                        }
+                    }
+                    _ => unreachable!("Invalid artefact"),
+                };
+
+                write_artefact(build_matches.value_of("output"), &artefact);
+                return;
             }
 
-            match artefact.write(match File::create(&f) {
-                Ok(v) => v,
-                Err(e) => {
-                    eprintln!("Error getting file {}: {}", f, e);
-                    exit(1);
-                }
-            }) {
-                Ok(_) => (),
-                Err(e) => {
-                    eprintln!("Error writing artefact to file: {}", e);
+            if !supported_by_backend(&root, &sources, &module, error_format) {
+                exit(1);
+            }
+
+            let freestanding = is_freestanding(&root, no_runtime);
+            if freestanding && !runtime_free(&root, &sources, &module, error_format) {
+                exit(1);
+            }
+
+            let code = match generate_object_code(&mut module, manifest.far_calls, freestanding) {
+                Some(v) => v,
+                None => return,
+            };
+
+            if profile == Profile::Embedded {
+                if let Some(report) = stack_usage_report(&mut module) {
+                    eprint!("{}", report);
                 }
             }
+
+            let f = build_matches
+                .value_of("output")
+                .map(String::from)
+                .or(manifest.output)
+                .unwrap_or_else(|| String::from("a.o"));
+
+            if let Err(e) = write_object(&code, &f, freestanding) {
+                eprintln!("{}", e);
+                exit(1);
+            }
         }
 
         Some("llir") => {
-            let contents = contents.unwrap();
+            let sources = sources.unwrap();
             let mut root = frontend_ir::Ir::new();
-            check(&contents, "Main", &mut root);
+            check(&sources, &mut root, error_format);
 
-            let module = backend_ir::convert_frontend_ir_to_backend_ir(
-                &root.modules.iter().next().unwrap().1,
-            );
+            let module = lower(&root, &sources);
             println!("{}", module);
         }
 
         Some("run") => {
-            let contents = contents.unwrap();
+            let run_matches = matches.subcommand_matches("run").unwrap();
+            if run_matches.is_present("args") {
+                eprintln!("Error: programs cannot receive arguments yet; the only values at runtime are closures, so there is nothing to pass them as");
+                exit(1);
+            }
+
+            let sources = sources.unwrap();
             let mut root = frontend_ir::Ir::new();
-            check(&contents, "Main", &mut root);
+            check(&sources, &mut root, error_format);
 
-            let mut module = backend_ir::convert_frontend_ir_to_backend_ir(
-                &root.modules.iter().next().unwrap().1,
-            );
+            let mut module = lower(&root, &sources);
+
+            if run_matches.is_present("verbose") {
+                report_call_statistics(&module);
+            }
+
+            if run_matches.is_present("coverage") {
+                let output = run_matches.value_of("coverage").unwrap_or("closey.cov");
+                run_with_coverage(&root, &sources, output);
+                return;
+            }
+
+            if !supported_by_backend(&root, &sources, &module, error_format) {
+                exit(1);
+            }
 
-            let code = match compile(&mut module) {
+            let jit = match jit_compile(&mut module) {
                 Some(v) => v,
                 None => return,
             };
 
-            let jit = Jit::new(code);
-            println!("{:#x}", unsafe { jit.call("main") }.unwrap() as u64);
+            let limits = Limits {
+                memory: run_matches.value_of("max-memory").and_then(parse_memory),
+                time: run_matches
+                    .value_of("max-time")
+                    .and_then(|v| v.parse().ok()),
+            };
+
+            if limits.memory.is_none() && limits.time.is_none() {
+                println!("{:#x}", unsafe { jit.call("main") }.unwrap() as u64);
+            } else if !eval_in_child(&jit, "main", &limits, error_format) {
+                exit(1);
+            }
+        }
+
+        Some("watch") => {
+            let run = matches
+                .subcommand_matches("watch")
+                .unwrap()
+                .is_present("run");
+            watch(sources.unwrap(), run, error_format);
+        }
+
+        Some("step") => {
+            let sources = sources.unwrap();
+            let mut root = frontend_ir::Ir::new();
+            check(&sources, &mut root, error_format);
+
+            let breakpoints: Vec<_> = matches
+                .subcommand_matches("step")
+                .unwrap()
+                .values_of("break")
+                .map(|v| v.collect())
+                .unwrap_or_default();
+            step(&root, &sources[0].0, &breakpoints);
         }
 
-        Some("repl") | None => repl(),
+        Some("cov") => match matches.subcommand_matches("cov").unwrap().subcommand() {
+            ("report", Some(matches)) => {
+                report_coverage(matches.value_of("coverage-file").unwrap_or("closey.cov"))
+            }
+
+            _ => unreachable!("Invalid subcommand"),
+        },
+
+        Some(s @ "eval") | Some(s @ "type") => eval_expression(
+            matches
+                .subcommand_matches(s)
+                .unwrap()
+                .value_of("expr")
+                .unwrap(),
+            s == "eval",
+            error_format,
+        ),
+
+        Some("repl") | None => {
+            // REPL options are given after `repl`, or without a subcommand at the top level
+            let repl_matches = matches.subcommand_matches("repl").unwrap_or(&matches);
+            let history_size = repl_matches
+                .value_of("history-size")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_HISTORY_SIZE);
+            let init = repl_matches
+                .value_of("init")
+                .map(|f| match fs::read_to_string(f) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        eprintln!("error reading file {}: {}", f, e);
+                        exit(1);
+                    }
+                });
+
+            let config = load_manifest().map(|v| v.repl).unwrap_or_default();
+
+            if repl_matches.is_present("json-repl") {
+                repl(
+                    ErrorFormat::Machine(error_format.catalog()),
+                    None,
+                    0,
+                    init,
+                    &config,
+                )
+            } else {
+                let history = match repl_matches.value_of("history") {
+                    Some(v) => Some(PathBuf::from(v)),
+                    None => dirs_next::data_dir().map(|v| v.join("closey").join("history.txt")),
+                };
+                repl(error_format, history, history_size, init, &config)
+            }
+        }
 
         _ => unreachable!("Invalid subcommand"),
     }
 }
 
-fn check(s: &str, mod_name: &str, root: &mut frontend_ir::Ir) {
-    let ast = match parser::parse(s) {
-        Ok(v) => v,
+fn parse(s: &str, filename: &str, format: ErrorFormat) -> Vec<parser::Ast> {
+    match try_parse(s, filename, format) {
+        Some(v) => v,
+        None => exit(1),
+    }
+}
 
-        Err(_) => {
-            eprintln!("Error parsing!");
-            exit(1);
+// try_parse(&str, &str, ErrorFormat) -> Option<Vec<Ast>>
+// Parses a source file, reporting every syntax error if it does not parse.
+fn try_parse(s: &str, filename: &str, format: ErrorFormat) -> Option<Vec<parser::Ast>> {
+    match parser::parse(s) {
+        Ok(v) => Some(v),
+
+        Err(errors) => {
+            let filename = filename.to_owned();
+            let mut files = SimpleFiles::new();
+            let file_id = files.add(&filename, s.to_owned());
+            let diagnostics: Vec<_> = errors
+                .into_iter()
+                .map(|e| {
+                    Diagnostic::error()
+                        .with_message(e.message(format.catalog()))
+                        .with_labels(vec![Label::primary(file_id, e.span)])
+                })
+                .collect();
+            report_diagnostics(&diagnostics, &files, format);
+            None
         }
-    };
+    }
+}
 
-    match frontend_ir::convert_ast_to_ir(mod_name, &s, ast, root) {
-        Ok(v) => v,
-        Err(_) => {
-            eprintln!("Error creating ir!");
+// load_manifest() -> Option<Manifest>
+// Loads the manifest in the current directory if there is one.
+fn load_manifest() -> Option<Manifest> {
+    let contents = fs::read_to_string(MANIFEST_NAME).ok()?;
+    match Manifest::parse(&contents) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            eprintln!("Error reading {}: {}", MANIFEST_NAME, e);
             exit(1);
         }
-    };
-
-    let _ = correctness::check_correctness(root, true);
+    }
 }
 
-fn compile(module: &mut backend_ir::IrModule) -> Option<GeneratedCode> {
-    match DEFAULT_ARCH {
-        "aarch64" => Some(aarch64::codegen::generate_code(module)),
-        "riscv64" => todo!(),
-        "wasm64" => todo!(),
-        "x86_64" => Some(x86_64::codegen::generate_code(module)),
-        _ => panic!("unsupported architecture"),
+// read_source(&str) -> std::io::Result<(String, String)>
+// Reads a source file and returns its name and contents. `-` reads from stdin.
+fn read_source(path: &str) -> std::io::Result<(String, String)> {
+    if path == "-" {
+        use std::io::Read;
+
+        let mut contents = String::new();
+        std::io::stdin().read_to_string(&mut contents)?;
+        Ok((String::from("<stdin>"), contents))
+    } else {
+        Ok((path.to_owned(), fs::read_to_string(path)?))
     }
 }
 
-fn repl() {
-    let mut rl = Editor::<()>::new();
-    let mut root = frontend_ir::Ir::new();
-    let mut i = 0;
+// with_imports(Vec<(String, String)>, ErrorFormat) -> Vec<(String, String)>
+// Adds the files of the modules imported by the sources to the end of the sources, exiting if any
+// of them cannot be found.
+fn with_imports(mut sources: Vec<(String, String)>, format: ErrorFormat) -> Vec<(String, String)> {
+    let _pass = profile::pass("load imports");
+    let diagnostics = closeyc::load_imports(&mut sources, format.catalog());
+    if !diagnostics.is_empty() {
+        let mut files = SimpleFiles::new();
+        for (filename, contents) in sources.iter() {
+            files.add(filename, contents.clone());
+        }
 
-    loop {
-        let readline = rl.readline(">>> ");
-        match readline {
-            Ok(line) => {
-                rl.add_history_entry(&line);
+        report_diagnostics(&diagnostics, &files, format);
+        exit(1);
+    }
 
-                let mod_name = format!("m{}", i);
-                i += 1;
-                check(&line, &mod_name, &mut root);
-                let f_module = root.modules.get(&mod_name).unwrap();
+    sources
+}
 
-                let mut b_module = backend_ir::convert_frontend_ir_to_backend_ir(f_module);
+fn check(sources: &[(String, String)], root: &mut frontend_ir::Ir, format: ErrorFormat) {
+    convert(sources, root, format);
+    if !try_check_types(sources, root, format) {
+        exit(1);
+    }
+}
 
-                let code = match compile(&mut b_module) {
-                    Some(v) => v,
-                    None => return,
-                };
+// try_check_types(&[(String, String)], &mut Ir, ErrorFormat) -> bool
+// Checks the types of converted sources, reporting any errors. Returns false if there were errors.
+fn try_check_types(
+    sources: &[(String, String)],
+    root: &mut frontend_ir::Ir,
+    format: ErrorFormat,
+) -> bool {
+    let _pass = profile::pass("check types");
+    match correctness::check_correctness(root, true) {
+        Ok(_) => true,
+        Err(errors) => {
+            report_correctness_errors(sources, errors, format);
+            false
+        }
+    }
+}
 
-                let jit = Jit::new(code);
-                println!(
-                    "{:#x}",
-                    unsafe { jit.call(f_module.funcs.iter().next().unwrap().0) }.unwrap() as u64
-                );
-            }
+// report_correctness_errors(&[(String, String)], Vec<CorrectnessError>, ErrorFormat) -> ()
+// Reports errors found while checking correctness as diagnostics.
+fn report_correctness_errors(
+    sources: &[(String, String)],
+    errors: Vec<correctness::CorrectnessError>,
+    format: ErrorFormat,
+) {
+    let filenames: Vec<_> = sources.iter().map(|v| (v.0.clone(), false)).collect();
+    let mut files = SimpleFiles::new();
+    for (filename, contents) in sources {
+        files.add(filename, contents.clone());
+    }
 
-            Err(ReadlineError::Interrupted) => {
-                println!("^C");
+    let diagnostics = closeyc::correctness_diagnostics(&filenames, errors, format.catalog());
+    report_diagnostics(&diagnostics, &files, format);
+}
+
+fn convert(sources: &[(String, String)], root: &mut frontend_ir::Ir, format: ErrorFormat) {
+    if !try_convert(sources, root, format) {
+        exit(1);
+    }
+}
+
+fn try_convert(
+    sources: &[(String, String)],
+    root: &mut frontend_ir::Ir,
+    format: ErrorFormat,
+) -> bool {
+    let filenames: Vec<_> = sources.iter().map(|v| (v.0.clone(), false)).collect();
+    let codes: Vec<_> = sources.iter().map(|v| v.1.clone()).collect();
+
+    match closeyc::check(&filenames, &codes, root, true, false, format.catalog()) {
+        Ok((diagnostics, files)) => {
+            report_diagnostics(&diagnostics, &files, format);
+            true
+        }
+
+        Err((diagnostics, files)) => {
+            report_diagnostics(&diagnostics, &files, format);
+            false
+        }
+    }
+}
+
+// lower(&Ir, &[(String, String)]) -> IrModule
+// Lowers the modules of every source into a single low level IR module. The first source is the
+// entry point; functions from the others are qualified with their module name.
+fn lower(root: &frontend_ir::Ir, sources: &[(String, String)]) -> backend_ir::IrModule {
+    let _pass = profile::pass("lower");
+    backend_ir::merge_modules(
+        sources
+            .iter()
+            .filter_map(|(filename, _)| root.modules.values().find(|m| &m.filename == filename))
+            .map(|m| {
+                (
+                    m.name.clone(),
+                    backend_ir::convert_frontend_ir_to_backend_ir(m),
+                )
+            })
+            .collect(),
+    )
+}
+
+// supported_by_backend(&Ir, &[(String, String)], &IrModule, ErrorFormat) -> bool
+// Checks that the backend for the host architecture can generate code for every construct used by
+// a lowered module, reporting each one it cannot. Returns false if there were any.
+fn supported_by_backend(
+    root: &frontend_ir::Ir,
+    sources: &[(String, String)],
+    module: &backend_ir::IrModule,
+    format: ErrorFormat,
+) -> bool {
+    let unsupported = match backends::capabilities(DEFAULT_ARCH) {
+        Some(v) => v.find_unsupported(module),
+        None => return true,
+    };
+
+    if unsupported.is_empty() {
+        return true;
+    }
+
+    let filenames: Vec<_> = sources.iter().map(|v| (v.0.clone(), false)).collect();
+    let mut files = SimpleFiles::new();
+    for (filename, contents) in sources {
+        files.add(filename, contents.clone());
+    }
+
+    let diagnostics =
+        closeyc::unsupported_diagnostics(&filenames, root, unsupported, format.catalog());
+    report_diagnostics(&diagnostics, &files, format);
+    false
+}
+
+// is_freestanding(&Ir, bool) -> bool
+// Checks whether a build is freestanding, either because of `--no-runtime` or because some module
+// has the `@no_runtime` annotation.
+fn is_freestanding(root: &frontend_ir::Ir, no_runtime: bool) -> bool {
+    no_runtime || root.modules.values().any(|m| m.no_runtime.is_some())
+}
+
+// runtime_free(&Ir, &[(String, String)], &IrModule, ErrorFormat) -> bool
+// Checks that a lowered module never calls into the runtime, reporting every feature that would.
+// Returns false if there were any.
+fn runtime_free(
+    root: &frontend_ir::Ir,
+    sources: &[(String, String)],
+    module: &backend_ir::IrModule,
+    format: ErrorFormat,
+) -> bool {
+    let uses = backends::find_runtime_uses(module);
+    if uses.is_empty() {
+        return true;
+    }
+
+    let filenames: Vec<_> = sources.iter().map(|v| (v.0.clone(), false)).collect();
+    let mut files = SimpleFiles::new();
+    for (filename, contents) in sources {
+        files.add(filename, contents.clone());
+    }
+
+    let diagnostics = closeyc::runtime_diagnostics(&filenames, root, uses, format.catalog());
+    report_diagnostics(&diagnostics, &files, format);
+    false
+}
+
+// catch_ice<T>(ErrorFormat, impl FnOnce() -> T) -> Option<T>
+// Runs part of the compiler, reporting a panic in it as an internal compiler error instead of
+// exiting. Returns None if it panicked.
+fn catch_ice<T>(format: ErrorFormat, f: impl FnOnce() -> T) -> Option<T> {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::{Arc, Mutex};
+
+    // The panic is reported as a diagnostic, so the default message is not printed
+    let panicked = Arc::new(Mutex::new(String::new()));
+    let hook = panic::take_hook();
+    let message = panicked.clone();
+    panic::set_hook(Box::new(move |info| {
+        let payload = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|v| String::from(*v))
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        *message.lock().unwrap() = match info.location() {
+            Some(loc) => format!("{} at {}", payload, loc),
+            None => payload,
+        };
+    }));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    panic::set_hook(hook);
+
+    match result {
+        Ok(v) => Some(v),
+        Err(_) => {
+            let catalog = format.catalog();
+            let message = panicked.lock().unwrap().clone();
+            let diagnostic = Diagnostic::bug()
+                .with_message(catalog.message("ice", &[&message]))
+                .with_notes(vec![catalog.message("ice.note", &[])]);
+            report_diagnostics(&[diagnostic], &SimpleFiles::new(), format);
+            None
+        }
+    }
+}
+
+fn report_diagnostics(
+    diagnostics: &[Diagnostic<usize>],
+    files: &SimpleFiles<&String, String>,
+    format: ErrorFormat,
+) {
+    match format {
+        ErrorFormat::Human(style, _) => {
+            let writer = StandardStream::stderr(style.color_choice());
+            let config = style.config();
+            for diagnostic in diagnostics {
+                term::emit(&mut writer.lock(), &config, files, diagnostic).unwrap();
+            }
+        }
+
+        ErrorFormat::Json(_) => {
+            for diagnostic in diagnostics {
+                eprintln!("{}", closeyc::diagnostic_to_json(diagnostic, files));
+            }
+        }
+
+        ErrorFormat::Machine(_) => {
+            for diagnostic in diagnostics {
+                println!(
+                    "{{\"kind\":\"diagnostic\",\"diagnostic\":{}}}",
+                    closeyc::diagnostic_to_json(diagnostic, files)
+                );
+            }
+        }
+    }
+}
+
+fn report_call_statistics(module: &backend_ir::IrModule) {
+    let stats = callgraph::collect_call_statistics(module);
+    eprintln!(
+        "{} direct calls, {} calls with unknown arity, {} partial applications",
+        stats.direct, stats.dynamic, stats.applies
+    );
+
+    for hint in stats.hints {
+        eprintln!("note: {}", hint);
+    }
+
+    let stats = escape::collect_escape_statistics(module);
+    eprintln!(
+        "{} of {} closures never escape the function that creates them",
+        stats.non_escaping, stats.closures
+    );
+
+    for hint in stats.hints {
+        eprintln!("note: {}", hint);
+    }
+}
+
+// code_size_report(&GeneratedCode) -> String
+// Reports the number of bytes of machine code generated for every function, largest first, along
+// with the total. Functions from the runtime are not generated, so they are left out.
+fn code_size_report(code: &GeneratedCode) -> String {
+    let mut sizes: Vec<_> = code
+        .get_funcs()
+        .filter(|(_, range)| range.end != 0)
+        .map(|(name, range)| (name, range.len()))
+        .collect();
+    sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+    let mut report = String::new();
+    for (name, size) in sizes.iter() {
+        report.push_str(&format!("{:>8} {}\n", size, name));
+    }
+    report.push_str(&format!(
+        "{:>8} total\n",
+        sizes.iter().map(|v| v.1).sum::<usize>()
+    ));
+    report
+}
+
+// stack_usage_report(&mut IrModule) -> Option<String>
+// Reports the size of the stack frame of every function in a module, along with its worst case
+// stack usage including the functions it calls. Returns None if the backend for the host
+// architecture cannot compute frame sizes.
+fn stack_usage_report(module: &mut backend_ir::IrModule) -> Option<String> {
+    let frames = match DEFAULT_ARCH {
+        "x86_64" => x86_64::codegen::frame_sizes(module),
+        _ => return None,
+    };
+    let usage = callgraph::stack_usage(module, &frames);
+
+    let mut report = String::new();
+    for ((func, frame), usage) in module.funcs.iter().zip(frames).zip(usage) {
+        let total = match usage {
+            StackUsage::Fixed(v) => format!("{} bytes", v),
+            StackUsage::Recursive => String::from("unbounded (recursive)"),
+            StackUsage::Dynamic => String::from("unknown (calls an unknown function)"),
+        };
+        report.push_str(&format!(
+            "{}: frame {} bytes, worst case {}\n",
+            func.name, frame, total
+        ));
+    }
+
+    Some(report)
+}
+
+// verify_reproducible(&[(String, String)], bool, bool, ErrorFormat) -> ()
+// Builds the sources twice from scratch, writing each object file into its own temporary
+// directory, and compares hashes of the output of every phase. Exits with an error naming the first
+// phase whose output differs, since every later phase inherits the difference.
+fn verify_reproducible(
+    sources: &[(String, String)],
+    far_calls: bool,
+    no_runtime: bool,
+    format: ErrorFormat,
+) {
+    let first = build_hashes(sources, far_calls, no_runtime, format, 0);
+    let second = build_hashes(sources, far_calls, no_runtime, format, 1);
+
+    for ((phase, a), (_, b)) in first.iter().zip(second.iter()) {
+        if a != b {
+            eprintln!(
+                "error: build is not reproducible: {} differs between builds ({:016x} and {:016x})",
+                phase, a, b
+            );
+            exit(1);
+        }
+
+        println!("{:<10} {:016x}", phase, a);
+    }
+
+    println!("build is reproducible");
+}
+
+// build_hashes(&[(String, String)], bool, bool, ErrorFormat, usize) -> Vec<(&str, u64)>
+// Builds the sources from scratch into a fresh temporary directory, returning a hash of the output
+// of each phase in order. The directory is removed afterwards.
+fn build_hashes(
+    sources: &[(String, String)],
+    far_calls: bool,
+    no_runtime: bool,
+    format: ErrorFormat,
+    build: usize,
+) -> Vec<(&'static str, u64)> {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+
+    let hash = |bytes: &[u8]| {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(bytes);
+        hasher.finish()
+    };
+    let mut hashes = vec![];
+
+    let mut asts = String::new();
+    for (filename, contents) in sources.iter() {
+        asts.push_str(&format!("{:#?}\n", parse(contents, filename, format)));
+    }
+    hashes.push(("ast", hash(asts.as_bytes())));
+
+    let mut root = frontend_ir::Ir::new();
+    check(sources, &mut root, format);
+    hashes.push(("ir", hash(root.to_string().as_bytes())));
+
+    let mut module = lower(&root, sources);
+    hashes.push(("backend-ir", hash(module.to_string().as_bytes())));
+
+    if !supported_by_backend(&root, sources, &module, format) {
+        exit(1);
+    }
+
+    let freestanding = is_freestanding(&root, no_runtime);
+    if freestanding && !runtime_free(&root, sources, &module, format) {
+        exit(1);
+    }
+
+    let code = match generate_object_code(&mut module, far_calls, freestanding) {
+        Some(v) => v,
+        None => exit(1),
+    };
+    hashes.push(("code", hash(code.data())));
+
+    let dir = env::temp_dir().join(format!("closeyc-build-{}-{}", std::process::id(), build));
+    let object = dir.join("a.o");
+    let written = fs::create_dir_all(&dir)
+        .map_err(|e| format!("Error creating directory {}: {}", dir.display(), e))
+        .and_then(|_| write_object(&code, &object.to_string_lossy(), freestanding))
+        .and_then(|_| {
+            fs::read(&object).map_err(|e| format!("Error reading {}: {}", object.display(), e))
+        });
+    let _ = fs::remove_dir_all(&dir);
+
+    match written {
+        Ok(v) => hashes.push(("object", hash(&v))),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+
+    hashes
+}
+
+// generate_object_code(&mut IrModule, bool, bool) -> Option<GeneratedCode>
+// Compiles a lowered module, ready to be written to an object file. The start function is left out
+// of freestanding code, since it calls `exit` from the runtime.
+fn generate_object_code(
+    module: &mut backend_ir::IrModule,
+    far_calls: bool,
+    freestanding: bool,
+) -> Option<GeneratedCode> {
+    let mut code = if far_calls {
+        compile_with_far_calls(module)
+    } else {
+        compile(module)
+    }?;
+
+    if freestanding {
+        return Some(code);
+    }
+
+    match DEFAULT_ARCH {
+        "x86_64" => x86_64::codegen::generate_start_func(&mut code),
+        _ => return unsupported_arch(),
+    }
+
+    Some(code)
+}
+
+// write_object(&GeneratedCode, &str, bool) -> Result<(), String>
+// Writes generated code to an object file. Relocations are linked in order of address, so the same
+// code always gives the same file. Freestanding code exports every function so that it can be
+// called from the program it is embedded in.
+fn write_object(code: &GeneratedCode, f: &str, freestanding: bool) -> Result<(), String> {
+    let _pass = profile::pass("write object");
+    let mut artefact = ArtifactBuilder::new(Triple::host())
+        .name(String::from(f))
+        .finish();
+
+    let mut funcs: Vec<_> = code.get_funcs().collect();
+    funcs.sort_by(|a, b| a.1.start.cmp(&b.1.start));
+    artefact
+        .declarations({
+            funcs.iter().map(|v| {
+                (
+                    v.0,
+                    if v.1.start == 0 && v.1.end == 0 {
+                        Decl::function_import().into()
+                    } else if freestanding || v.0 == "_start" || v.0 == "main" {
+                        Decl::function().global().into()
+                    } else {
+                        Decl::function().into()
+                    },
+                )
+            })
+        })
+        .map_err(|e| format!("Error declaring functions: {}", e))?;
+
+    for (func, range) in funcs {
+        if range.start == 0 && range.end == 0 {
+            continue;
+        }
+
+        artefact
+            .define(func, code.data()[range.start..range.end].to_owned())
+            .map_err(|e| format!("Error defining function: {}", e))?;
+    }
+
+    // Far calls use absolute addresses, with the addend stored in the code
+    let far_refs = code.get_far_relocation_table().iter().map(|(addr, to)| {
+        let mut addend = [0; 8];
+        addend.copy_from_slice(&code.data()[*addr..*addr + 8]);
+        let reloc = Reloc::Raw {
+            reloc: goblin::elf::reloc::R_X86_64_64,
+            addend: i64::from_le_bytes(addend) as i32,
+        };
+        (addr, to, reloc)
+    });
+    let mut refs: Vec<_> = code
+        .get_relocation_table()
+        .iter()
+        .map(|(addr, to)| (addr, to, Reloc::Auto))
+        .chain(far_refs)
+        .collect();
+    refs.sort_by_key(|v| *v.0);
+
+    for (addr, to, reloc) in refs {
+        let to = code.get_func_name(*to);
+        let from = code
+            .get_funcs()
+            .find(|(_, range)| range.start <= *addr && *addr < range.end);
+
+        if let Some((from, range)) = from {
+            artefact
+                .link_with(
+                    Link {
+                        from,
+                        to,
+                        at: (addr - range.start) as u64,
+                    },
+                    reloc,
+                )
+                .map_err(|e| format!("Error linking: {}", e))?;
+        }
+    }
+
+    let file = File::create(f).map_err(|e| format!("Error getting file {}: {}", f, e))?;
+    artefact
+        .write(file)
+        .map_err(|e| format!("Error writing artefact to file: {}", e))
+}
+
+fn write_artefact(output: Option<&str>, artefact: &str) {
+    match output {
+        Some(f) => {
+            if let Err(e) = fs::write(f, artefact) {
+                eprintln!("Error writing artefact to file {}: {}", f, e);
+                exit(1);
+            }
+        }
+
+        None => print!("{}", artefact),
+    }
+}
+
+fn compile(module: &mut backend_ir::IrModule) -> Option<GeneratedCode> {
+    let _pass = profile::pass("codegen");
+    match DEFAULT_ARCH {
+        "aarch64" => Some(aarch64::codegen::generate_code(module)),
+        "x86_64" => Some(x86_64::codegen::generate_code(module)),
+        _ => unsupported_arch(),
+    }
+}
+
+// unsupported_arch() -> Option<T>
+// Reports that the host architecture has no backend that can generate this code yet.
+fn unsupported_arch<T>() -> Option<T> {
+    eprintln!(
+        "Error: backend not supported on this arch ({})",
+        DEFAULT_ARCH
+    );
+    None
+}
+
+fn generate_assembly(module: &mut backend_ir::IrModule) -> Option<String> {
+    let mut code = compile(module)?;
+
+    let relocated = match DEFAULT_ARCH {
+        "x86_64" => x86_64::codegen::relocate(&mut code, std::ptr::null()),
+        _ => return unsupported_arch(),
+    };
+
+    if let Err(e) = relocated {
+        eprintln!("Error relocating code: {}", e);
+        return None;
+    }
+
+    Some(x86_64::disassemble_to_string(&code, std::ptr::null()))
+}
+
+// watch(Vec<(String, String)>, bool, ErrorFormat) -> ()
+// Checks the sources whenever one of them changes, and runs them afterwards if requested. Only
+// the modules of changed files (and files that failed to check before) are converted again; the
+// rest of the IR is reused.
+fn watch(mut sources: Vec<(String, String)>, run: bool, format: ErrorFormat) {
+    use std::time::Duration;
+
+    let modified = |f: &str| fs::metadata(f).and_then(|v| v.modified()).ok();
+    let mut mtimes: Vec<_> = sources.iter().map(|v| modified(&v.0)).collect();
+    let mut dirty: HashSet<_> = (0..sources.len()).collect();
+    let mut root = frontend_ir::Ir::new();
+
+    loop {
+        if !dirty.is_empty() {
+            // Modules that import changed modules hold copies of their definitions, so they are
+            // converted again too
+            loop {
+                let changed: Vec<_> = root
+                    .modules
+                    .values()
+                    .filter(|m| dirty.iter().any(|i| sources[*i].0 == m.filename))
+                    .map(|m| m.name.clone())
+                    .collect();
+                let importers: Vec<_> = (0..sources.len())
+                    .filter(|i| {
+                        !dirty.contains(i)
+                            && root.modules.values().any(|m| {
+                                m.filename == sources[*i].0
+                                    && m.imports.values().any(|v| changed.contains(&v.name))
+                            })
+                    })
+                    .collect();
+
+                if importers.is_empty() {
+                    break;
+                }
+                dirty.extend(importers);
+            }
+
+            eprintln!("[watch] checking {} file(s)", dirty.len());
+
+            root.modules
+                .retain(|_, m| !dirty.iter().any(|i| sources[*i].0 == m.filename));
+            let changed: Vec<_> = dirty.iter().map(|i| sources[*i].clone()).collect();
+
+            // A panic while converting or checking is reported, and the files are checked again
+            // when they change
+            if catch_ice(format, || try_convert(&changed, &mut root, format)).unwrap_or(false)
+                && catch_ice(format, || try_check_types(&sources, &mut root, format))
+                    .unwrap_or(false)
+            {
+                dirty.clear();
+                eprintln!("[watch] ok");
+
+                if run {
+                    let mut module = lower(&root, &sources);
+                    if supported_by_backend(&root, &sources, &module, format) {
+                        if let Some(jit) = jit_compile(&mut module) {
+                            eval_in_child(&jit, "main", &Limits::default(), format);
+                        }
+                    }
+                }
+            }
+        }
+
+        std::thread::sleep(Duration::from_millis(500));
+
+        for (i, (filename, contents)) in sources.iter_mut().enumerate() {
+            let mtime = modified(filename.as_str());
+            if mtime != mtimes[i] {
+                mtimes[i] = mtime;
+                if let Ok(v) = fs::read_to_string(filename.as_str()) {
+                    *contents = v;
+                    dirty.insert(i);
+                }
+            }
+        }
+    }
+}
+
+// run_with_coverage(&Ir, &[(String, String)], &str) -> ()
+// Interprets the main function of the first source and writes how many times each function of
+// every source was entered to the output file.
+fn run_with_coverage(root: &frontend_ir::Ir, sources: &[(String, String)], output: &str) {
+    let modules: Vec<_> = sources
+        .iter()
+        .filter_map(|(filename, _)| root.modules.values().find(|m| &m.filename == filename))
+        .collect();
+    if modules.is_empty() {
+        return;
+    }
+
+    let (result, coverage) = Coverage::collect(&modules);
+    match result {
+        Ok(v) => println!("{}", v),
+        Err(e) => eprintln!("{}", e),
+    }
+
+    if let Err(e) = fs::write(output, coverage.to_string()) {
+        eprintln!("Error writing coverage to file {}: {}", output, e);
+        exit(1);
+    }
+}
+
+// lint_config(&ArgMatches) -> LintConfig
+// Gets the level of every lint rule from the manifest, overridden by `-W` and `-A` flags in the
+// order they were given.
+fn lint_config(matches: &ArgMatches) -> LintConfig {
+    let mut config = load_manifest().map(|v| v.lints).unwrap_or_default();
+
+    let mut flags = vec![];
+    for (name, level) in [("warn", Level::Warn), ("allow", Level::Allow)] {
+        if let (Some(indices), Some(values)) = (matches.indices_of(name), matches.values_of(name)) {
+            flags.extend(indices.zip(values).map(|(i, v)| (i, v, level)));
+        }
+    }
+    flags.sort_by_key(|v| v.0);
+
+    for (_, name, level) in flags {
+        match Rule::from_name(name) {
+            Some(rule) => config.set(rule, level),
+            None => config.deny_warnings(),
+        }
+    }
+
+    config
+}
+
+// lint(&[(String, String)], &LintConfig, ErrorFormat) -> ()
+// Lints every source with the given levels, exiting with an error if a denied rule is broken.
+fn lint(sources: &[(String, String)], config: &LintConfig, format: ErrorFormat) {
+    let mut files = SimpleFiles::new();
+    let mut diagnostics = vec![];
+    let mut denied = false;
+
+    for (filename, contents) in sources {
+        let asts = parse(contents, filename, format);
+        let file_id = files.add(filename, contents.clone());
+
+        for lint in closeyc::lint::lint(contents, &asts, config) {
+            let diagnostic = if lint.level == Level::Deny {
+                denied = true;
+                Diagnostic::error()
+            } else {
+                Diagnostic::warning()
+            };
+
+            diagnostics.push(
+                diagnostic
+                    .with_code(lint.rule.name())
+                    .with_message(lint.message(format.catalog()))
+                    .with_labels(vec![Label::primary(file_id, lint.span)]),
+            );
+        }
+    }
+
+    report_diagnostics(&diagnostics, &files, format);
+    if denied {
+        exit(1);
+    }
+}
+
+// report_coverage(&str) -> ()
+// Prints per line coverage for every file in a coverage file.
+fn report_coverage(path: &str) {
+    let coverage = match fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|v| Coverage::parse(&v))
+    {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error reading coverage file {}: {}", path, e);
+            exit(1);
+        }
+    };
+
+    for file in coverage.files() {
+        match fs::read_to_string(file) {
+            Ok(contents) => print!("{}:\n{}", file, coverage.report(file, &contents)),
+            Err(e) => eprintln!("Error reading file {}: {}", file, e),
+        }
+    }
+}
+
+// step(&Ir, &str, &[&str]) -> ()
+// Interprets the main function of a file, printing each reduction and waiting for a command. If
+// breakpoints are given, reductions are skipped until one of the functions is entered.
+fn step(root: &frontend_ir::Ir, filename: &str, breakpoints: &[&str]) {
+    use std::io::{self, BufRead, Write};
+
+    let module = match root.modules.values().find(|m| m.filename == filename) {
+        Some(v) => v,
+        None => return,
+    };
+
+    let mut stepping = breakpoints.is_empty();
+    let mut interpreter = Interpreter::with_hook(module, |reduction: &Reduction| {
+        if !stepping && !breakpoints.contains(&reduction.func) {
+            return true;
+        }
+
+        stepping = true;
+        println!(
+            "{:indent$}{}: {}",
+            "",
+            reduction.func,
+            reduction.body,
+            indent = reduction.depth * 2
+        );
+        for (name, value) in reduction.env.iter() {
+            println!(
+                "{:indent$}  {} = {}",
+                "",
+                name,
+                value,
+                indent = reduction.depth * 2
+            );
+        }
+
+        loop {
+            print!("(step) ");
+            let _ = io::stdout().flush();
+
+            let mut command = String::new();
+            match io::stdin().lock().read_line(&mut command) {
+                Ok(0) | Err(_) => return false,
+                Ok(_) => (),
+            }
+
+            match command.trim() {
+                "" | "s" | "step" => return true,
+                "c" | "continue" => {
+                    stepping = false;
+                    return true;
+                }
+                "q" | "quit" => return false,
+                _ => println!("commands: s(tep), c(ontinue), q(uit)"),
+            }
+        }
+    });
+
+    match interpreter.evaluate("main") {
+        Ok(v) => println!("{}", v),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+fn compile_with_far_calls(module: &mut backend_ir::IrModule) -> Option<GeneratedCode> {
+    let _pass = profile::pass("codegen");
+    match DEFAULT_ARCH {
+        "aarch64" => Some(aarch64::codegen::generate_code(module)),
+        "x86_64" => Some(x86_64::codegen::generate_code_with_far_calls(module, true)),
+        _ => unsupported_arch(),
+    }
+}
+
+// Compiles and loads a module into memory, falling back to far calls if a call is out of range of
+// a 32 bit displacement.
+fn jit_compile(module: &mut backend_ir::IrModule) -> Option<Jit> {
+    let result = match Jit::new(compile(module)?) {
+        Err(JitError::Relocation(e @ RelocationError::DisplacementOverflow { .. })) => {
+            eprintln!("warning: {}; recompiling with far calls", e);
+            Jit::new(compile_with_far_calls(module)?)
+        }
+
+        v => v,
+    };
+
+    match result {
+        Ok(v) => Some(v),
+        Err(e) => {
+            eprintln!("Error loading code: {}", e);
+            None
+        }
+    }
+}
+
+// jit_load(&mut Option<Jit>, &mut IrModule) -> Option<&Jit>
+// Compiles the functions of a module that are not loaded into the REPL's JIT yet and links them
+// against the ones that are, creating the JIT if there is none. Falls back to far calls if a call
+// is out of range of a 32 bit displacement.
+fn jit_load<'a>(jit: &'a mut Option<Jit>, module: &mut backend_ir::IrModule) -> Option<&'a Jit> {
+    if jit.is_none() {
+        match Jit::empty() {
+            Ok(v) => *jit = Some(v),
+            Err(e) => {
+                eprintln!("Error loading code: {}", e);
+                return None;
+            }
+        }
+    }
+
+    let jit = jit.as_mut().unwrap();
+    let loaded: HashSet<_> = module
+        .funcs
+        .iter()
+        .filter(|v| jit.contains(&v.name))
+        .map(|v| v.name.clone())
+        .collect();
+
+    let result = match jit.load(compile_excluding(module, &loaded, false)?) {
+        Err(JitError::Relocation(e @ RelocationError::DisplacementOverflow { .. })) => {
+            eprintln!("warning: {}; recompiling with far calls", e);
+            jit.load(compile_excluding(module, &loaded, true)?)
+        }
+
+        v => v,
+    };
+
+    match result {
+        Ok(_) => Some(jit),
+        Err(e) => {
+            eprintln!("Error loading code: {}", e);
+            None
+        }
+    }
+}
+
+// compile_excluding(&mut IrModule, &HashSet<String>, bool) -> Option<GeneratedCode>
+// Compiles every function in a module except the excluded ones. Backends that cannot leave
+// functions out compile the whole module.
+fn compile_excluding(
+    module: &mut backend_ir::IrModule,
+    exclude: &HashSet<String>,
+    far_calls: bool,
+) -> Option<GeneratedCode> {
+    match DEFAULT_ARCH {
+        "aarch64" => Some(aarch64::codegen::generate_code(module)),
+        "x86_64" => Some(x86_64::codegen::generate_code_excluding(
+            module, far_calls, exclude,
+        )),
+        _ => unsupported_arch(),
+    }
+}
+
+// repl(ErrorFormat, Option<PathBuf>, usize, Option<String>, &ReplConfig) -> ()
+// Runs the REPL, loading history from the given file and saving it there on exit. Every line is
+// added to the definitions entered before it, so redefining a name shadows the old definition;
+// earlier definitions keep referring to the value that was visible when they were entered. Only
+// the new definitions are converted, checked, and compiled; their code is linked against the code
+// already loaded. The startup lines of the configuration are entered before the first prompt,
+// followed by the initial code, if any, as one block.
+fn repl(
+    format: ErrorFormat,
+    history: Option<PathBuf>,
+    history_size: usize,
+    init: Option<String>,
+    config: &ReplConfig,
+) {
+    use std::io::BufRead;
+
+    // The machine REPL reads plain lines from stdin and prints every result as a JSON object
+    let machine = matches!(format, ErrorFormat::Machine(_));
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    let config = Config::builder()
+        .max_history_size(history_size)
+        .history_ignore_dups(true)
+        .history_ignore_space(true)
+        .build();
+    let mut rl = Editor::<ReplHelper>::with_config(config);
+    if let Some(path) = &history {
+        // There is no history the first time the REPL is run
+        let _ = rl.load_history(path);
+    }
+    rl.set_helper(Some(ReplHelper {
+        color: matches!(format, ErrorFormat::Human(style, _) if style.color_choice() != ColorChoice::Never),
+        ..ReplHelper::default()
+    }));
+    let mut definitions = String::new();
+    let mut entry = String::new();
+    let mut session = frontend_ir::Ir::new();
+    let mut jit: Option<Jit> = None;
+    let mut last: Option<String> = None;
+
+    // Pasted blocks are read until `:end` without checking whether they are complete
+    let mut pasting = false;
+    let mut queued: VecDeque<_> = config.startup.iter().cloned().collect();
+    if let Some(init) = init {
+        queued.push_back(String::from(":paste"));
+        queued.extend(init.lines().map(String::from));
+        queued.push_back(String::from(":end"));
+    }
+
+    loop {
+        // Unfinished input is continued on the next line; an empty line ends it regardless
+        let prompt = if entry.is_empty() && !pasting {
+            ">>> "
+        } else {
+            "... "
+        };
+        let readline = if let Some(line) = queued.pop_front() {
+            Ok(line)
+        } else if machine {
+            match lines.next() {
+                Some(Ok(v)) => Ok(v),
+                Some(Err(e)) => Err(ReadlineError::Io(e)),
+                None => Err(ReadlineError::Eof),
+            }
+        } else {
+            rl.readline(prompt)
+        };
+
+        // Ctrl-D ends a pasted block instead of the REPL
+        let readline = match readline {
+            Err(ReadlineError::Eof) if pasting => Ok(String::from(":end")),
+            v => v,
+        };
+
+        match readline {
+            Ok(line) => {
+                if pasting {
+                    if line.trim() != ":end" {
+                        entry.push_str(&line);
+                        entry.push('\n');
+                        continue;
+                    }
+                    pasting = false;
+                    if entry.is_empty() {
+                        continue;
+                    }
+                } else if entry.is_empty() && line.trim_start().starts_with(':') {
+                    rl.add_history_entry(line.trim());
+
+                    // Aliases cannot hide the built in commands
+                    let line = match line.split_whitespace().next() {
+                        Some(v) if REPL_COMMANDS.iter().any(|c| c.0 == v) => line,
+                        _ => config.expand(&line),
+                    };
+
+                    // Commands that change the session are handled here; the rest only read it
+                    let result = match line.split_whitespace().next() {
+                        Some(":quit") => break,
+
+                        Some(":paste") => {
+                            pasting = true;
+                            Ok(String::from(
+                                "Entering paste mode; end the block with `:end` or Ctrl-D",
+                            ))
+                        }
+
+                        Some(":clear") => {
+                            definitions.clear();
+                            session = frontend_ir::Ir::new();
+                            jit = None;
+                            last = None;
+                            if let Some(helper) = rl.helper_mut() {
+                                helper.names.clear();
+                            }
+                            Ok(String::from("Cleared every definition"))
+                        }
+
+                        _ => repl_command(line.trim(), &session, last.as_deref()),
+                    };
+
+                    match (result, machine) {
+                        (Ok(v), false) | (Err(v), false) => println!("{}", v),
+                        (Ok(v), true) => println!(
+                            "{{\"kind\":\"output\",\"text\":\"{}\"}}",
+                            closeyc::escape_json(&v)
+                        ),
+                        (Err(v), true) => report_eval_error(&v, format),
+                    }
+                    continue;
+                } else if entry.is_empty() || !line.trim().is_empty() {
+                    entry.push_str(&line);
+                    entry.push('\n');
+                    if parser::is_incomplete(&entry) {
+                        continue;
+                    }
+                }
+
+                let line = std::mem::take(&mut entry);
+                rl.add_history_entry(line.trim_end());
+
+                // Only the new definitions are converted and checked; everything entered before
+                // is kept in the session
+                let source = format!("{}{}", definitions, line);
+                let asts = match try_parse(&source, "repl", format) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let asts: Vec<_> = asts
+                    .into_iter()
+                    .filter(|v| v.get_span().start >= definitions.len())
+                    .collect();
+
+                let mut root = session.clone();
+                let module = root.modules.entry(String::from("repl")).or_insert_with(|| {
+                    let mut module = frontend_ir::IrModule::new("repl", "");
+                    module.name = String::from("repl");
+                    module
+                });
+
+                // A panic leaves the session untouched, since only its copy was being extended
+                match catch_ice(format, || frontend_ir::extend_module(module, &source, asts)) {
+                    Some(Ok(_)) => (),
+
+                    Some(Err(errors)) => {
+                        let mut files = SimpleFiles::new();
+                        files.add("repl", source.clone());
+                        let diagnostics = closeyc::ir_diagnostics(
+                            &[(String::from("repl"), false)],
+                            errors,
+                            format.catalog(),
+                        );
+                        report_diagnostics(&diagnostics, &files, format);
+                        continue;
+                    }
+
+                    None => continue,
+                }
+
+                let sources = [(String::from("repl"), source)];
+                if !catch_ice(format, || try_check_types(&sources, &mut root, format))
+                    .unwrap_or(false)
+                {
+                    continue;
+                }
+
+                definitions.push_str(&line);
+
+                // The last definition is the one that was just entered
+                session = root;
+                let f_module = session.modules.get("repl").unwrap();
+                if let Some(helper) = rl.helper_mut() {
+                    helper.names = f_module
+                        .globals
+                        .keys()
+                        .chain(f_module.types.keys())
+                        .cloned()
+                        .collect();
+                }
+
+                let last_definition = f_module
+                    .globals
+                    .iter()
+                    .map(|(name, raw)| (name, raw, f_module.funcs.get(raw).unwrap()))
+                    .max_by_key(|v| v.2.loc.span.start);
+                let (name, raw, func) = match last_definition {
+                    Some(v) => v,
+                    None => continue,
+                };
+                last = Some(name.clone());
+
+                if machine {
+                    println!(
+                        "{{\"kind\":\"type\",\"name\":\"{}\",\"type\":\"{}\"}}",
+                        closeyc::escape_json(name),
+                        closeyc::escape_json(&func._type.to_string())
+                    );
+                }
+
+                if !func.args.is_empty() {
+                    if !machine {
+                        println!("{} : {}", name, func._type);
+                    }
+                    continue;
+                }
+
+                let mut b_module = backend_ir::convert_frontend_ir_to_backend_ir(f_module);
+                // Code that fails to compile or load only loses this entry, not the session
+                if !supported_by_backend(&session, &sources, &b_module, format) {
+                    continue;
+                }
+
+                let loaded = match jit_load(&mut jit, &mut b_module) {
+                    Some(v) => v,
+                    None => continue,
+                };
+
+                eval_in_child(loaded, raw, &Limits::default(), format);
+            }
+
+            Err(ReadlineError::Interrupted) => {
+                println!("^C");
+                entry.clear();
             }
 
             Err(ReadlineError::Eof) => {
-                println!("^D");
+                if !machine {
+                    println!("^D");
+                }
                 break;
             }
 
@@ -434,6 +2183,328 @@ fn repl() {
             }
         }
     }
+
+    if let Some(path) = &history {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        if let Err(e) = rl.save_history(path) {
+            eprintln!("Error saving history to {}: {}", path.display(), e);
+        }
+    }
+}
+
+// eval_expression(&str, bool, ErrorFormat) -> ()
+// Checks a single expression and prints its type. If `run` is set, the expression is run first
+// and its value is printed, unless it is a function that takes arguments.
+fn eval_expression(expr: &str, run: bool, format: ErrorFormat) {
+    let sources = [(String::from("eval"), format!("it = {}\n", expr))];
+    let mut root = frontend_ir::Ir::new();
+    check(&sources, &mut root, format);
+
+    let module = root.modules.get("eval").unwrap();
+    let raw = module.globals.get("it").unwrap();
+    let func = module.funcs.get(raw).unwrap();
+
+    if !run {
+        println!("{}", func._type);
+        return;
+    }
+
+    if func.args.is_empty() {
+        let mut b_module = backend_ir::convert_frontend_ir_to_backend_ir(module);
+        if !supported_by_backend(&root, &sources, &b_module, format) {
+            exit(1);
+        }
+
+        let jit = match jit_compile(&mut b_module) {
+            Some(v) => v,
+            None => exit(1),
+        };
+
+        if !eval_in_child(&jit, raw, &Limits::default(), format) {
+            exit(1);
+        }
+    }
+
+    println!(": {}", func._type);
+}
+
+// repl_command(&str, &Ir, Option<&str>) -> Result<String, String>
+// Runs a REPL command that does not change the session, returning its output or why it failed.
+// `:ir`, `:llir`, and `:asm` print the frontend IR, the low level IR, and the assembly of the named
+// definition, or of the last definition if no name is given.
+fn repl_command(
+    command: &str,
+    session: &frontend_ir::Ir,
+    last: Option<&str>,
+) -> Result<String, String> {
+    let mut parts = command.split_whitespace();
+    let command = parts.next().unwrap_or_default();
+    match command {
+        ":help" => {
+            return Ok(REPL_COMMANDS
+                .iter()
+                .map(|(name, args, help)| format!("{:<14}{}", format!("{} {}", name, args), help))
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+
+        ":env" => {
+            let module = match session.modules.get("repl") {
+                Some(v) if !v.globals.is_empty() => v,
+                _ => return Err(String::from("Nothing has been defined yet")),
+            };
+
+            let mut globals: Vec<_> = module.globals.iter().collect();
+            globals.sort();
+            return Ok(globals
+                .into_iter()
+                .map(|(name, raw)| format!("{} : {}", name, module.funcs.get(raw).unwrap()._type))
+                .collect::<Vec<_>>()
+                .join("\n"));
+        }
+
+        _ if !REPL_COMMANDS.iter().any(|v| v.0 == command) => {
+            return Err(format!("Unknown command `{}`", command))
+        }
+
+        _ => (),
+    }
+
+    let (module, name) = match (session.modules.get("repl"), parts.next().or(last)) {
+        (Some(module), Some(name)) => (module, name),
+        _ => return Err(String::from("Nothing has been defined yet")),
+    };
+
+    let raw = match module.globals.get(name) {
+        Some(v) => v,
+        None => return Err(format!("`{}` is not defined", name)),
+    };
+
+    match command {
+        ":ir" => Ok(module.funcs.get(raw).unwrap().to_string()),
+
+        ":llir" => {
+            let b_module = backend_ir::convert_frontend_ir_to_backend_ir(module);
+            Ok(b_module
+                .funcs
+                .iter()
+                .find(|v| &v.name == raw)
+                .map(|v| v.to_string())
+                .unwrap_or_default())
+        }
+
+        ":asm" => {
+            let mut b_module = backend_ir::convert_frontend_ir_to_backend_ir(module);
+            let header = format!("{}:", raw);
+            let asm = generate_assembly(&mut b_module).unwrap_or_default();
+
+            // Each function is disassembled into its own paragraph starting with its name
+            Ok(asm
+                .split("\n\n")
+                .find(|v| v.trim_start().lines().next() == Some(&header))
+                .map(|v| String::from(v.trim()))
+                .unwrap_or_default())
+        }
+
+        _ => unreachable!("Invalid command"),
+    }
+}
+
+// eval_in_child(&Jit, &str, &Limits, ErrorFormat) -> bool
+// Calls a function in a child process with the given resource limits and prints its result.
+// Ctrl-C kills only the child, so long running or infinitely looping expressions can be
+// interrupted without leaving the REPL. Returns whether the function returned normally.
+#[cfg(unix)]
+fn eval_in_child(jit: &Jit, func: &str, limits: &Limits, format: ErrorFormat) -> bool {
+    use std::io::Write;
+
+    let _ = std::io::stdout().flush();
+    match unsafe { libc::fork() } {
+        -1 => {
+            eprintln!(
+                "Error forking evaluator: {}",
+                std::io::Error::last_os_error()
+            );
+            false
+        }
+
+        0 => unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+
+            // The limit is on top of what the evaluator has already mapped, so that only memory
+            // the program maps while running counts towards it
+            if let Some(memory) = limits.memory {
+                catch_out_of_memory();
+                let memory = address_space_size().unwrap_or(0).saturating_add(memory);
+                let limit = libc::rlimit {
+                    rlim_cur: memory as libc::rlim_t,
+                    rlim_max: memory as libc::rlim_t,
+                };
+                libc::setrlimit(libc::RLIMIT_AS, &limit);
+            }
+
+            if let Some(time) = limits.time {
+                libc::alarm(time);
+            }
+
+            print_value(jit.call(func).unwrap() as u64, format);
+            let _ = std::io::stdout().flush();
+            libc::_exit(0);
+        },
+
+        pid => unsafe {
+            let handler = libc::signal(libc::SIGINT, libc::SIG_IGN);
+            let mut status = 0;
+            while libc::waitpid(pid, &mut status, 0) == -1
+                && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+            {
+            }
+            libc::signal(libc::SIGINT, handler);
+
+            let message = if libc::WIFEXITED(status) {
+                match libc::WEXITSTATUS(status) {
+                    EXIT_OUT_OF_MEMORY if limits.memory.is_some() => format!(
+                        "resource limit exceeded: used more than {} bytes of memory",
+                        limits.memory.unwrap()
+                    ),
+
+                    _ => return true,
+                }
+            } else {
+                match libc::WTERMSIG(status) {
+                    libc::SIGINT => String::from("interrupted"),
+
+                    libc::SIGALRM if limits.time.is_some() => format!(
+                        "resource limit exceeded: ran for longer than {} seconds",
+                        limits.time.unwrap()
+                    ),
+
+                    sig => format!("evaluation terminated by signal {}", sig),
+                }
+            };
+            report_eval_error(&message, format);
+            false
+        },
+    }
+}
+
+// The status the evaluator exits with when the program crashed because it ran out of memory.
+#[cfg(unix)]
+const EXIT_OUT_OF_MEMORY: i32 = 99;
+
+// address_space_size() -> Option<u64>
+// Gets the size in bytes of the address space of the current process, or None if it is unknown.
+#[cfg(unix)]
+fn address_space_size() -> Option<u64> {
+    let statm = fs::read_to_string("/proc/self/statm").ok()?;
+    let pages = statm.split_whitespace().next()?.parse::<u64>().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    pages.checked_mul(page_size as u64)
+}
+
+// catch_out_of_memory() -> ()
+// Makes the current process exit with EXIT_OUT_OF_MEMORY if it crashes while its address space is
+// full. A failed allocation shows up as a crash once the program uses the pointer it got back, so
+// a crash counts as running out of memory only if a page can no longer be mapped.
+#[cfg(unix)]
+unsafe fn catch_out_of_memory() {
+    const STACK_SIZE: usize = 64 << 10;
+
+    extern "C" fn on_crash(sig: libc::c_int) {
+        unsafe {
+            let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+            let page = libc::mmap(
+                std::ptr::null_mut(),
+                page_size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if page == libc::MAP_FAILED {
+                libc::_exit(EXIT_OUT_OF_MEMORY);
+            }
+
+            libc::munmap(page, page_size);
+            libc::signal(sig, libc::SIG_DFL);
+            libc::raise(sig);
+        }
+    }
+
+    // The handler runs on its own stack so that running out of stack is caught too
+    let stack = libc::mmap(
+        std::ptr::null_mut(),
+        STACK_SIZE,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+        -1,
+        0,
+    );
+    if stack == libc::MAP_FAILED {
+        return;
+    }
+
+    let stack = libc::stack_t {
+        ss_sp: stack,
+        ss_flags: 0,
+        ss_size: STACK_SIZE,
+    };
+    libc::sigaltstack(&stack, std::ptr::null_mut());
+
+    let mut action: libc::sigaction = std::mem::zeroed();
+    action.sa_sigaction = on_crash as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_ONSTACK;
+    libc::sigemptyset(&mut action.sa_mask);
+    for sig in [libc::SIGSEGV, libc::SIGBUS, libc::SIGABRT] {
+        libc::sigaction(sig, &action, std::ptr::null_mut());
+    }
+}
+
+#[cfg(not(unix))]
+fn eval_in_child(jit: &Jit, func: &str, limits: &Limits, format: ErrorFormat) -> bool {
+    if limits.memory.is_some() || limits.time.is_some() {
+        eprintln!("warning: resource limits are not supported on this platform");
+    }
+
+    print_value(unsafe { jit.call(func) }.unwrap() as u64, format);
+    true
+}
+
+// print_value(u64, ErrorFormat) -> ()
+// Prints the result of running a function.
+fn print_value(value: u64, format: ErrorFormat) {
+    match format {
+        ErrorFormat::Machine(_) => println!("{{\"kind\":\"value\",\"value\":\"{:#x}\"}}", value),
+        _ => println!("{:#x}", value),
+    }
+}
+
+// report_eval_error(&str, ErrorFormat) -> ()
+// Reports why running a function did not finish.
+fn report_eval_error(message: &str, format: ErrorFormat) {
+    match format {
+        ErrorFormat::Machine(_) => println!(
+            "{{\"kind\":\"error\",\"message\":\"{}\"}}",
+            closeyc::escape_json(message)
+        ),
+        _ => eprintln!("{}", message),
+    }
+}
+
+// parse_memory(&str) -> Option<u64>
+// Parses a number of bytes with an optional K, M, or G suffix.
+fn parse_memory(s: &str) -> Option<u64> {
+    let (digits, scale) = match s.chars().last()?.to_ascii_uppercase() {
+        'K' => (&s[..s.len() - 1], 1 << 10),
+        'M' => (&s[..s.len() - 1], 1 << 20),
+        'G' => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+
+    digits.parse::<u64>().ok()?.checked_mul(scale)
 }
 
 #[allow(dead_code, unused_mut)]
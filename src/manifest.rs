@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::lint::{Level, LintConfig, Rule};
+use crate::repl::ReplConfig;
+
+/// The name of the project manifest file.
+pub const MANIFEST_NAME: &str = "closey.toml";
+
+/// A project manifest. Every field corresponds to a key in the `[build]` table, except for the lint
+/// levels, which are given by the `[lint]` table, and the REPL configuration, which is given by the
+/// `[repl]` and `[repl.aliases]` tables.
+#[derive(Debug, Default)]
+pub struct Manifest {
+    /// The source files to build, with the entry point first.
+    pub files: Vec<String>,
+
+    /// The output file.
+    pub output: Option<String>,
+
+    /// The backend to generate code for.
+    pub backend: Option<String>,
+
+    /// Whether to always use absolute addresses for calls.
+    pub far_calls: bool,
+
+    /// The profile to build with.
+    pub profile: Profile,
+
+    /// The level of each lint rule, keyed by the name of the rule.
+    pub lints: LintConfig,
+
+    /// The startup lines and command aliases of the REPL.
+    pub repl: ReplConfig,
+}
+
+/// The restrictions a build is made under.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Profile {
+    /// No restrictions.
+    #[default]
+    Default,
+
+    /// Freestanding code that never allocates on the heap, with a report of the stack used by
+    /// every function.
+    Embedded,
+}
+
+impl Profile {
+    /// Gets a profile by its name as written in the manifest or on the command line.
+    pub fn from_name(name: &str) -> Option<Profile> {
+        match name {
+            "default" => Some(Profile::Default),
+            "embedded" => Some(Profile::Embedded),
+            _ => None,
+        }
+    }
+}
+
+/// An error encountered while reading a manifest.
+#[derive(Debug)]
+pub struct ManifestError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl Display for ManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+/// A value in a manifest.
+#[derive(Debug, PartialEq)]
+enum Value {
+    String(String),
+    Bool(bool),
+    Array(Vec<String>),
+}
+
+impl Manifest {
+    /// Parses a manifest. Only the subset of TOML needed for manifests is supported: tables,
+    /// strings, booleans, and single line arrays of strings.
+    pub fn parse(s: &str) -> Result<Manifest, ManifestError> {
+        let mut table = String::new();
+        let mut values = HashMap::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let error = |msg: &str| ManifestError {
+                line: i + 1,
+                msg: String::from(msg),
+            };
+            let line = strip_comment(line).trim();
+
+            if line.is_empty() {
+                continue;
+            } else if let Some(name) = line.strip_prefix('[') {
+                match name.strip_suffix(']') {
+                    Some(name) => table = String::from(name.trim()),
+                    None => return Err(error("expected `]` after table name")),
+                }
+            } else if let Some((key, value)) = line.split_once('=') {
+                let value = parse_value(value.trim()).ok_or_else(|| error("invalid value"))?;
+                values.insert((table.clone(), String::from(key.trim())), (i + 1, value));
+            } else {
+                return Err(error("expected `key = value` or `[table]`"));
+            }
+        }
+
+        let mut manifest = Manifest::default();
+        for ((table, key), (line, value)) in values {
+            let error = |msg: String| Err(ManifestError { line, msg });
+            match (table.as_str(), key.as_str(), value) {
+                ("build", "files", Value::Array(v)) => manifest.files = v,
+                ("build", "output", Value::String(v)) => manifest.output = Some(v),
+                ("build", "backend", Value::String(v)) => manifest.backend = Some(v),
+                ("build", "far-calls", Value::Bool(v)) => manifest.far_calls = v,
+                ("build", "profile", Value::String(v)) => match Profile::from_name(&v) {
+                    Some(profile) => manifest.profile = profile,
+                    None => {
+                        return error(format!(
+                            "invalid profile `{}`; expected `default` or `embedded`",
+                            v
+                        ))
+                    }
+                },
+                ("build", "files" | "output" | "backend" | "far-calls" | "profile", _) => {
+                    return error(format!("invalid type for `{}`", key))
+                }
+                ("lint", _, Value::String(v)) => {
+                    match (Rule::from_name(&key), Level::from_name(&v)) {
+                        (Some(rule), Some(level)) => manifest.lints.set(rule, level),
+                        (None, _) => return error(format!("unknown lint rule `{}`", key)),
+                        (_, None) => {
+                            return error(format!(
+                                "invalid level `{}` for `{}`; expected `allow`, `warn`, or `deny`",
+                                v, key
+                            ))
+                        }
+                    }
+                }
+                ("lint", _, _) => return error(format!("invalid type for `{}`", key)),
+                ("repl", "startup", Value::Array(v)) => manifest.repl.startup = v,
+                ("repl", "startup", _) => return error(format!("invalid type for `{}`", key)),
+                ("repl.aliases", _, Value::String(v)) if v.starts_with(':') => {
+                    manifest.repl.aliases.insert(key.clone(), v);
+                }
+                ("repl.aliases", _, Value::String(v)) => {
+                    return error(format!(
+                        "alias `{}` must stand for a command starting with `:`, not `{}`",
+                        key, v
+                    ))
+                }
+                ("repl.aliases", _, _) => return error(format!("invalid type for `{}`", key)),
+                _ => return error(format!("unknown key `{}` in table `[{}]`", key, table)),
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+// strip_comment(&str) -> &str
+// Removes a comment from the end of a line, ignoring `#` inside strings.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+// parse_value(&str) -> Option<Value>
+// Parses a string, boolean, or array of strings.
+fn parse_value(s: &str) -> Option<Value> {
+    match s {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ if s.starts_with('[') => {
+            let inner = s.strip_prefix('[')?.strip_suffix(']')?.trim();
+            let inner = inner.strip_suffix(',').unwrap_or(inner);
+            if inner.trim().is_empty() {
+                return Some(Value::Array(vec![]));
+            }
+
+            let mut items = vec![];
+            for item in inner.split(',') {
+                items.push(parse_string(item.trim())?);
+            }
+            Some(Value::Array(items))
+        }
+        _ => parse_string(s).map(Value::String),
+    }
+}
+
+// parse_string(&str) -> Option<String>
+// Parses a basic string without escapes.
+fn parse_string(s: &str) -> Option<String> {
+    let inner = s.strip_prefix('"')?.strip_suffix('"')?;
+    if inner.contains('"') || inner.contains('\\') {
+        None
+    } else {
+        Some(String::from(inner))
+    }
+}
@@ -0,0 +1,211 @@
+use std::fmt::Display;
+
+/// A catalog of diagnostic messages in one language. Messages are keyed by error code, and labels
+/// by the error code followed by the name of the label. Syntax errors are keyed by `syntax.`
+/// followed by the name of the error, lints by the name of their rule, and internal compiler
+/// errors by `ice`. Messages may contain placeholders `{0}`, `{1}`, etc, which are replaced by
+/// arguments.
+#[derive(Debug, PartialEq)]
+pub struct Catalog {
+    /// The name of the language as given on the command line.
+    pub lang: &'static str,
+
+    messages: &'static [(&'static str, &'static str)],
+}
+
+/// The English catalog. Every other catalog falls back to this one for missing messages.
+pub const ENGLISH: Catalog = Catalog {
+    lang: "en",
+    messages: &[
+        ("E0001", "Invalid type used"),
+        ("E0001.type", "Undeclared type"),
+        ("E0002", "Duplicate type in union type declaration"),
+        ("E0002.first", "Type used here first"),
+        ("E0002.second", "Type `{0}` used a second time here"),
+        ("E0003", "Value exported twice"),
+        ("E0003.first", "Value exported here first"),
+        ("E0003.second", "Value {0} exported a second time here"),
+        ("E0004", "Alias defined twice"),
+        ("E0004.first", "Alias defined here first"),
+        ("E0004.second", "Alias {0} defined a second time here"),
+        ("E0005", "Unsupported annotation used"),
+        ("E0005.annotation", "Annotation {0} is unsupported"),
+        ("E0006", "Unsupported type used for FFI"),
+        ("E0006.type", "Type {0} is unsupported by FFI"),
+        ("E0007", "Duplicate module `{0}`"),
+        ("E0008", "Type signature without a definition"),
+        (
+            "E0008.signature",
+            "Signature for `{0}` must be followed by its definition",
+        ),
+        ("E0009", "Type ascription mismatch"),
+        ("E0009.expr", "Expression has type `{0}`"),
+        ("E0009.type", "Expected type `{0}`"),
+        ("E0010", "Definition does not match its type signature"),
+        ("E0010.definition", "Definition has type `{0}`"),
+        ("E0010.signature", "Signature gives type `{0}`"),
+        ("E0011", "Macro `{0}` given too few arguments"),
+        ("E0011.use", "`{0}` takes {1} arguments but was given {2}"),
+        ("E0012", "Macro `{0}` expands into itself"),
+        ("E0012.use", "Expansion stopped here"),
+        (
+            "E0013",
+            "Destructured value is not a tuple of the right size",
+        ),
+        (
+            "E0013.value",
+            "Value has type `{0}` but is destructured into {1} elements",
+        ),
+        ("E0014", "No field `{0}`"),
+        (
+            "E0014.value",
+            "Value has type `{0}`, which has no field `{1}`",
+        ),
+        ("E0015", "Cannot find module `{0}`"),
+        ("E0015.import", "Imported here"),
+        ("E0015.paths", "Looked for `{0}`"),
+        ("E0016", "Import cycle `{0}`"),
+        (
+            "E0016.import",
+            "`{0}` imports `{1}` here, which completes the cycle",
+        ),
+        ("E0016.step", "`{0}` imports `{1}` here"),
+        ("E0017", "No definition `{0}` in module `{1}`"),
+        ("E0017.import", "Imported here"),
+        ("E0018", "Unsupported by backend {0}: {1}"),
+        ("E0018.func", "Used by `{0}`"),
+        (
+            "E0018.help",
+            "`closeyc step` runs this code in the interpreter, which does not need a backend",
+        ),
+        ("E0019", "`{0}` is private to module `{1}`"),
+        ("E0019.use", "Used here"),
+        ("E0019.definition", "`{0}` is declared here without `pub`"),
+        (
+            "E0020",
+            "Argument does not match the function it is passed to",
+        ),
+        ("E0020.arg", "Argument has type `{0}`"),
+        ("E0020.func", "Function expects an argument of type `{0}`"),
+        ("E0020.conflict", "`{0}` conflicts with `{1}`"),
+        (
+            "E0020.occurs",
+            "`{0}` would have to be `{1}`, which contains `{0}` itself",
+        ),
+        ("E0021", "Value applied to arguments is not a function"),
+        ("E0021.value", "Value has type `{0}`"),
+        (
+            "E0022",
+            "Freestanding build uses {0}, which needs the runtime",
+        ),
+        ("E0022.func", "Used by `{0}`"),
+        ("E0022.annotation", "Runtime excluded here"),
+        (
+            "E0022.heap",
+            "A closure struct is allocated on the heap for {0}",
+        ),
+        (
+            "E0022.help",
+            "Freestanding code can only call functions with all of their arguments at once",
+        ),
+        ("E0023", "Recursive function `{0}` has no type signature"),
+        (
+            "E0023.use",
+            "`{0}` is used here before its type is known",
+        ),
+        (
+            "E0023.help",
+            "Add a signature such as `{0}: Int -> Int` before the definition of `{0}` or of another function in the cycle",
+        ),
+        (
+            "E0024",
+            "Function takes {0} arguments but was given {1}",
+        ),
+        ("E0024.arg", "Argument {0} is one too many"),
+        (
+            "E0024.func",
+            "Function of type `{0}` takes {1} arguments and is declared here",
+        ),
+        ("E0025", "Cannot find `{0}` in this scope"),
+        ("E0025.use", "Not found in this scope"),
+        ("E0025.suggestion", "Did you mean `{1}` instead of `{0}`?"),
+        ("E0026", "{0} are not supported yet"),
+        ("E0026.use", "Not supported yet"),
+        ("ice", "Internal compiler error: {0}"),
+        (
+            "ice.note",
+            "This is a bug in closeyc rather than in the code being checked",
+        ),
+        ("syntax.assign", "Expected `=`, got `{0}`"),
+        ("syntax.duplicate_field", "Field `{0}` given twice"),
+        ("syntax.extern_colon", "Expected `:` after foreign function declaration"),
+        ("syntax.extern_name", "Expected symbol after external function declaration"),
+        ("syntax.extern_string", "Expected string literal after `extern`"),
+        ("syntax.field_after_brace", "Expected field name after `{`"),
+        ("syntax.field_after_comma", "Expected field name after `,`"),
+        ("syntax.function_body", "Expected function body after `=`"),
+        ("syntax.import_module", "Expected module name after `import`"),
+        ("syntax.import_separator", "Expected `,` or `)` after imported name"),
+        ("syntax.imported_name", "Expected imported name"),
+        ("syntax.lambda_arg", "Expected argument after `lambda`"),
+        ("syntax.lambda_dot", "Expected `.`, got `{0}`"),
+        ("syntax.let_in", "Expected `in` after let bindings"),
+        ("syntax.list_end", "Expected `]` after end of list"),
+        ("syntax.macro_body", "Expected macro body after `=`"),
+        ("syntax.macro_name", "Expected name after `macro`"),
+        ("syntax.match_arm", "Expected expression after `=>`"),
+        ("syntax.match_arrow", "Expected `=>` after type"),
+        ("syntax.match_to", "Expected `to` after match value"),
+        ("syntax.match_value", "Expected expression after `match`"),
+        ("syntax.module_name", "Expected module name after `module`"),
+        ("syntax.operand", "Expected value after infix operator"),
+        ("syntax.rbrace", "Expected right brace"),
+        ("syntax.record_assign", "Expected `=` after field name"),
+        ("syntax.record_end", "Expected `}` after end of record"),
+        ("syntax.record_type_colon", "Expected `:` after field name"),
+        ("syntax.rparen", "Expected right parenthesis"),
+        ("syntax.type_after_assign", "Expected type after `=`"),
+        ("syntax.type_after_colon", "Expected type after `:`"),
+        ("syntax.type_after_comma", "Expected type after `,`"),
+        ("syntax.type_after_to", "Expected type after `to`"),
+        ("syntax.type_alias_assign", "Expected `=` after type name"),
+        ("syntax.type_alias_name", "Expected symbol after type"),
+        ("syntax.unclosed_comment", "Unclosed block comment"),
+        ("syntax.unexpected", "Unexpected `{0}`"),
+        ("syntax.value_after_assign", "Expected value after `=`"),
+        ("syntax.value_after_comma", "Expected value after `,`"),
+        ("syntax.where_definition", "Expected definition after `where`"),
+        ("naming.type", "Type `{0}` should be named in upper camel case"),
+        ("naming.value", "`{0}` should be named in snake case"),
+        ("nesting", "Nested more than {0} levels deep"),
+        ("redundant-parens", "Parentheses around a single value are redundant"),
+        ("shadowing.local", "`{0}` shadows a local value with the same name"),
+        ("shadowing.top-level", "`{0}` shadows a top level definition"),
+        ("unreachable", "Match arm is never reached, since an earlier arm matches every value"),
+        ("unused-function", "`{0}` is never used"),
+        ("unused-variable", "`{0}` is never used"),
+    ],
+};
+
+/// Every available catalog.
+pub const CATALOGS: &[&Catalog] = &[&ENGLISH];
+
+impl Catalog {
+    /// Gets the catalog for the given language.
+    pub fn get(lang: &str) -> Option<&'static Catalog> {
+        CATALOGS.iter().copied().find(|v| v.lang == lang)
+    }
+
+    /// Gets the message with the given key, replacing placeholders with the given arguments. Keys
+    /// missing from this catalog are looked up in the English catalog, and keys missing from both
+    /// are returned as is.
+    pub fn message(&self, key: &str, args: &[&dyn Display]) -> String {
+        let find = |catalog: &Catalog| catalog.messages.iter().find(|v| v.0 == key).map(|v| v.1);
+
+        let mut message = String::from(find(self).or_else(|| find(&ENGLISH)).unwrap_or(key));
+        for (i, arg) in args.iter().enumerate() {
+            message = message.replace(&format!("{{{}}}", i), &arg.to_string());
+        }
+        message
+    }
+}
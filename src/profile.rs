@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::escape_json;
+
+/// A pass that ran while profiling was enabled.
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// The name of the pass.
+    pub name: &'static str,
+
+    /// The names of the passes that were running when this pass started, outermost first.
+    pub stack: Vec<&'static str>,
+
+    /// The time from when profiling was enabled to when the pass started.
+    pub start: Duration,
+
+    /// How long the pass ran for.
+    pub duration: Duration,
+}
+
+// The passes recorded so far and the passes that are still running.
+struct Profiler {
+    epoch: Instant,
+    events: Vec<Event>,
+    running: Vec<&'static str>,
+}
+
+static PROFILER: Mutex<Option<Profiler>> = Mutex::new(None);
+
+/// Starts recording passes. Passes that started before this are not recorded.
+pub fn enable() {
+    *PROFILER.lock().unwrap() = Some(Profiler {
+        epoch: Instant::now(),
+        events: vec![],
+        running: vec![],
+    });
+}
+
+/// A running pass. The pass ends when this is dropped.
+pub struct Pass {
+    name: &'static str,
+    start: Option<Instant>,
+}
+
+impl Drop for Pass {
+    fn drop(&mut self) {
+        let start = match self.start {
+            Some(v) => v,
+            None => return,
+        };
+
+        if let Some(profiler) = PROFILER.lock().unwrap().as_mut() {
+            profiler.running.pop();
+            profiler.events.push(Event {
+                name: self.name,
+                stack: profiler.running.clone(),
+                start: start.saturating_duration_since(profiler.epoch),
+                duration: start.elapsed(),
+            });
+        }
+    }
+}
+
+/// Starts a pass with the given name, which ends when the returned value is dropped. Passes started
+/// while another pass is running are nested in it. Nothing is recorded unless profiling is enabled.
+/// Passes should only be started on the main thread, since nesting is tracked for one thread.
+pub fn pass(name: &'static str) -> Pass {
+    let start = PROFILER.lock().unwrap().as_mut().map(|profiler| {
+        profiler.running.push(name);
+        Instant::now()
+    });
+
+    Pass { name, start }
+}
+
+/// Takes every pass recorded so far, in the order they ended.
+pub fn take_events() -> Vec<Event> {
+    match PROFILER.lock().unwrap().as_mut() {
+        Some(profiler) => std::mem::take(&mut profiler.events),
+        None => vec![],
+    }
+}
+
+/// Converts passes into the Chrome trace event format, which can be opened in `chrome://tracing`
+/// or Perfetto. Every pass is a complete event with times in microseconds.
+pub fn to_chrome_trace(events: &[Event]) -> String {
+    let events: Vec<_> = events
+        .iter()
+        .map(|v| {
+            format!(
+                "{{\"name\":\"{}\",\"cat\":\"pass\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":1}}",
+                escape_json(v.name),
+                v.start.as_micros(),
+                v.duration.as_micros()
+            )
+        })
+        .collect();
+
+    format!("{{\"traceEvents\":[{}]}}\n", events.join(","))
+}
+
+/// Converts passes into folded stacks, which can be turned into a flamegraph by inferno or
+/// flamegraph.pl. Each line is a stack of passes followed by the microseconds spent in the
+/// innermost pass and not in any pass nested in it. Passes with the same stack are added together.
+pub fn to_folded(events: &[Event]) -> String {
+    let mut totals: BTreeMap<String, i128> = BTreeMap::new();
+    for event in events {
+        let mut stack = event.stack.join(";");
+        let duration = event.duration.as_micros() as i128;
+        if !stack.is_empty() {
+            *totals.entry(stack.clone()).or_insert(0) -= duration;
+            stack.push(';');
+        }
+        stack.push_str(event.name);
+        *totals.entry(stack).or_insert(0) += duration;
+    }
+
+    let mut folded = String::new();
+    for (stack, time) in totals {
+        folded.push_str(&format!("{} {}\n", stack, time.max(0)));
+    }
+    folded
+}
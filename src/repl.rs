@@ -0,0 +1,29 @@
+use std::collections::HashMap;
+
+/// The configuration of the REPL. Startup lines are entered before the first prompt, and aliases
+/// give other names to commands.
+#[derive(Debug, Default, Clone)]
+pub struct ReplConfig {
+    /// The lines entered before the first prompt, which may be commands or definitions.
+    pub startup: Vec<String>,
+
+    /// The command each alias stands for, keyed by the name of the alias without its colon.
+    pub aliases: HashMap<String, String>,
+}
+
+impl ReplConfig {
+    /// Replaces an alias at the start of a command with the command it stands for, keeping the
+    /// arguments after it. Commands that do not start with an alias are returned as is.
+    pub fn expand(&self, command: &str) -> String {
+        let command = command.trim();
+        let (name, args) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+
+        match name.strip_prefix(':').and_then(|v| self.aliases.get(v)) {
+            Some(v) if args.is_empty() => v.clone(),
+            Some(v) => format!("{} {}", v, args.trim_start()),
+            None => String::from(command),
+        }
+    }
+}
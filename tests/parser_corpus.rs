@@ -0,0 +1,73 @@
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use closeyc::frontend::ir::{self, Ir, IrError};
+use closeyc::frontend::parser;
+use closeyc::messages::ENGLISH;
+
+// Gets the name and contents of every file in the corpus of inputs that used to crash the parser
+// or the conversion of types, sorted by name.
+fn corpus() -> Vec<(String, String)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/parser_corpus");
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|v| v.unwrap().path())
+        .filter(|v| v.extension().is_some_and(|e| e == "cly"))
+        .map(|v| {
+            let contents = fs::read_to_string(&v).unwrap();
+            (v.to_string_lossy().into_owned(), contents)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+// Every input in the corpus is parsed, and inputs that parse are converted into ir. Errors are
+// fine; only panics fail the test.
+#[test]
+fn corpus_does_not_panic() {
+    let files = corpus();
+    assert!(!files.is_empty());
+
+    for (filename, contents) in files {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            if let Ok(asts) = parser::parse(&contents) {
+                let _ = ir::convert_ast_to_ir(&filename, &contents, asts, &mut Ir::new());
+            }
+        }));
+        assert!(result.is_ok(), "{} panicked", filename);
+    }
+}
+
+// Every syntax error reported for the corpus has a message in the English catalog.
+#[test]
+fn corpus_errors_have_messages() {
+    for (filename, contents) in corpus() {
+        if let Err(errors) = parser::parse(&contents) {
+            for e in errors {
+                assert_ne!(e.message(&ENGLISH), e.key, "{}", filename);
+            }
+        }
+    }
+}
+
+// Constructs that parse but cannot be converted yet are reported as errors at their location.
+#[test]
+fn unsupported_constructs_are_reported() {
+    for (name, construct) in [("list", "Lists"), ("match", "Match expressions")] {
+        let (filename, contents) = corpus()
+            .into_iter()
+            .find(|v| v.0.ends_with(&format!("/{}.cly", name)))
+            .unwrap();
+        let asts = parser::parse(&contents).unwrap();
+        let errors = ir::convert_ast_to_ir(&filename, &contents, asts, &mut Ir::new()).unwrap_err();
+        assert!(
+            errors
+                .iter()
+                .any(|e| matches!(e, IrError::Unsupported(_, v) if *v == construct)),
+            "{}",
+            filename
+        );
+    }
+}
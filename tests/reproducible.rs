@@ -0,0 +1,44 @@
+use closeyc::backends::ir as backend_ir;
+use closeyc::backends::x86_64;
+use closeyc::frontend::correctness;
+use closeyc::frontend::ir::{self, Ir};
+use closeyc::frontend::parser;
+
+const SOURCE: &str = "id x: 'a = x
+first a: 'a, b: 'b = id a
+second a: 'a, b: 'b = first b a
+main = second id id
+";
+
+// Checks and compiles the source from scratch, returning the printed low level IR and the
+// generated code. Every build creates its own maps, which iterate in different orders.
+fn build() -> (String, Vec<u8>) {
+    let mut root = Ir::new();
+    let asts = parser::parse(SOURCE).unwrap_or_else(|_| panic!("source does not parse"));
+    if ir::convert_ast_to_ir("reproducible.cly", SOURCE, asts, &mut root).is_err() {
+        panic!("source does not convert");
+    }
+
+    let names: Vec<_> = root.modules.keys().cloned().collect();
+    if ir::resolve_imports(&mut root, &names).is_err()
+        || correctness::check_correctness(&mut root, true).is_err()
+    {
+        panic!("source does not check");
+    }
+
+    let mut module =
+        backend_ir::convert_frontend_ir_to_backend_ir(root.modules.values().next().unwrap());
+    let printed = module.to_string();
+    let code = x86_64::codegen::generate_code(&mut module);
+    (printed, code.data().clone())
+}
+
+#[test]
+fn builds_are_reproducible() {
+    let (ir_a, code_a) = build();
+    for _ in 0..8 {
+        let (ir_b, code_b) = build();
+        assert_eq!(ir_a, ir_b);
+        assert_eq!(code_a, code_b);
+    }
+}